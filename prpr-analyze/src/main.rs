@@ -0,0 +1,52 @@
+use anyhow::{bail, Context, Result};
+use prpr::{
+    analysis::{analyze, ChartStats},
+    fs,
+    parse::{chart_fingerprint, Fingerprint},
+    simulate::load_chart,
+};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report<'a> {
+    path: &'a str,
+    name: String,
+    level: String,
+    fingerprint: Fingerprint,
+    /// The difficulty given in the chart info, to be compared with the estimated one
+    declared_difficulty: f32,
+    stats: ChartStats,
+}
+
+fn main() -> Result<()> {
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        if arg.starts_with("--") {
+            bail!("Unknown option: {arg}");
+        }
+        paths.push(arg);
+    }
+    if paths.is_empty() {
+        bail!("Usage: prpr-analyze <chart>...");
+    }
+
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    let mut reports = Vec::new();
+    for path in &paths {
+        let mut fs = fs::fs_from_file(Path::new(path))?;
+        let (info, chart) = rt.block_on(load_chart(fs.as_mut())).with_context(|| format!("Failed to load {path}"))?;
+        let fingerprint = chart_fingerprint(&chart, &info);
+        reports.push(Report {
+            path,
+            name: info.name,
+            level: info.level,
+            fingerprint,
+            declared_difficulty: info.difficulty,
+            stats: analyze(&chart),
+        });
+    }
+    println!("{}", serde_json::to_string_pretty(&reports)?);
+    Ok(())
+}
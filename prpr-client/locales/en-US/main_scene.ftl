@@ -4,3 +4,6 @@ cannot-find-chart = Beatmap not found
 
 delete-success = Deleted successfully
 delete-failed = Failed to delete
+
+load-calibration-failed = Failed to start calibration
+calibration-not-enough-hits = Not enough hits to calibrate, please try again
@@ -24,6 +24,8 @@ chal-color = Challenge mode color
 chal-colors = White,Green,Blue,Red,Gold,Rainbow
 chal-level = Challenge mode level
 double-click-pause = Double click to pause
+keyboard-mode = Keyboard mode
+keyboard-lanes = Keyboard lanes
 
 respack = Respack
 reset = Reset
@@ -35,3 +37,8 @@ reset-all = Restore default settings
 confirm-reset = Sure?
 reset-all-done = Settings are restored
 save-failed = Failed to save config
+
+calibrate = Calibrate
+apply = Apply
+calibration-result = Calibrated: { $offset }s ({ $confidence }%)
+recent-suggestion = From recent plays: { $offset }s ({ $confidence }%)
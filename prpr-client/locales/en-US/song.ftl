@@ -42,7 +42,6 @@ edit-saving = Saving…
 edit-builtin = You cannot change built-in beatmaps
 edit-fix-chart = Fix beatmap
 edit-load-file-failed = Failed to load file
-edit-save-config-failed = Failed to save configuration
 edit-save-failed = Failed to save file
 
 fix-chart-success = Repair successful
@@ -82,3 +81,8 @@ review-exec = Executing
 review-suc = Executed successfully
 review-wait = Please wait until the last task is done
 review-del-confirm = Are you sure?
+
+stats = Statistics
+stats-loading = Analyzing chart…
+stats-failed = Failed to analyze chart
+stats-summary = { $notes } notes, { $nps } NPS on average, { $peak } at peak, estimated difficulty { $difficulty }
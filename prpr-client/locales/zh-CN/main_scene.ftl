@@ -4,3 +4,6 @@ cannot-find-chart = 找不到谱面
 
 delete-success = 已删除
 delete-failed = 删除失败
+
+load-calibration-failed = 无法开始校准
+calibration-not-enough-hits = 有效点击过少，请重新校准
@@ -24,6 +24,8 @@ chal-color = 挑战模式颜色
 chal-colors = 白,绿,蓝,红,金,彩
 chal-level = 挑战模式等级
 double-click-pause = 双击暂停
+keyboard-mode = 键盘模式
+keyboard-lanes = 键盘分轨
 
 respack = 资源包
 reset = 重置
@@ -35,3 +37,8 @@ reset-all = 恢复默认设定
 confirm-reset = 确定？
 reset-all-done = 设定恢复成功
 save-failed = 保存设定失败
+
+calibrate = 校准
+apply = 应用
+calibration-result = 校准结果：{ $offset }s（置信度 { $confidence }%）
+recent-suggestion = 根据最近游玩：{ $offset }s（置信度 { $confidence }%）
@@ -42,7 +42,6 @@ edit-saving = 保存中…
 edit-builtin = 不能更改内置谱面
 edit-fix-chart = 自动修复谱面
 edit-load-file-failed = 加载文件失败
-edit-save-config-failed = 写入配置文件失败
 edit-save-failed = 保存文件失败
 
 fix-chart-success = 修复成功
@@ -82,3 +81,8 @@ review-exec = 执行中
 review-suc = 执行成功
 review-wait = 请等待上一次操作完成
 review-del-confirm = 你确定吗？
+
+stats = 谱面统计
+stats-loading = 正在分析谱面…
+stats-failed = 分析谱面失败
+stats-summary = 共 { $notes } 个音符，平均每秒 { $nps } 个，峰值每秒 { $peak } 个，估计难度 { $difficulty }
@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use super::LCObject;
 use crate::data::BriefChartInfo;
 use chrono::{DateTime, Utc};
+use prpr::parse::Fingerprint;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -141,6 +142,8 @@ pub struct LCRecord {
     pub bad: u32,
     pub miss: u32,
     pub time: LCDate,
+    /// Fingerprint of the chart played, absent in records uploaded by older clients
+    pub fingerprint: Option<Fingerprint>,
 }
 
 impl LCObject for LCRecord {
@@ -85,6 +85,8 @@ pub struct Data {
     pub message_check_time: Option<DateTime<Utc>>,
     pub language: Option<String>,
     pub theme: usize,
+    /// Hit errors of recent plays, see `prpr::calibrate::recent_offsets`
+    pub recent_offsets: Vec<Vec<f32>>,
 }
 
 impl Data {
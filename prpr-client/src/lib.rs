@@ -126,6 +126,7 @@ async fn the_main() -> Result<()> {
         .and_then(|s| Ok(serde_json::from_str(&s)?))
         .unwrap_or_default();
     data.init().await?;
+    prpr::calibrate::set_recent_offsets(data.recent_offsets.clone());
     set_data(data);
     sync_lang();
 
@@ -30,8 +30,10 @@ use lyon::{
 };
 use macroquad::prelude::*;
 use prpr::{
-    ext::{SafeTexture, BLACK_TEXTURE},
+    calibrate::Calibration,
+    ext::{LocalTask, SafeTexture, BLACK_TEXTURE},
     fs,
+    scene::LoadingScene,
     task::Task,
     ui::{Scroll, Ui},
 };
@@ -132,6 +134,9 @@ pub struct SharedState {
     pub charts_online: Vec<ChartItem>,
 
     pub transit: Option<(Option<LCFile>, u32, f32, Rect, bool, bool)>, // online, id, start_time, rect, delete, public
+
+    pub scene_task: LocalTask<Result<LoadingScene>>,
+    pub calibration: Option<Calibration>,
 }
 
 impl SharedState {
@@ -147,6 +152,9 @@ impl SharedState {
             charts_online: Vec::new(),
 
             transit: None,
+
+            scene_task: None,
+            calibration: None,
         })
     }
 
@@ -5,9 +5,11 @@ use crate::{dir, get_data, get_data_mut, save_data, sync_lang, data::THEMES};
 use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use prpr::{
+    calibrate::{calibration_chart, suggest_from_recent},
+    config::{Config, KeyboardMapping},
     core::{ParticleEmitter, ResourcePack, JUDGE_LINE_PERFECT_COLOR, NOTE_WIDTH_RATIO_BASE},
     ext::{create_audio_manger, poll_future, LocalTask, RectExt, SafeTexture},
-    scene::{request_file, return_file, show_error, show_message, take_file},
+    scene::{request_file, return_file, show_error, show_message, take_file, GameMode, LoadingScene},
     time::TimeManager,
     ui::{RectButton, Ui},
 };
@@ -201,6 +203,40 @@ impl Page for SettingsPage {
             ui.scope(|ui| {
                 let r = ui.slider(tl!("offset"), -0.5..0.5, 0.005, &mut config.offset, None);
                 ui.dy(r.h + s);
+                let mut r = Rect::new(0., 0., 0.15, 0.05);
+                if ui.button("calibrate", r, tl!("calibrate")) && state.scene_task.is_none() {
+                    state.scene_task = Some(Box::pin(async move {
+                        let (info, fs) = calibration_chart()?;
+                        let config = Config {
+                            res_pack_path: get_data()
+                                .config
+                                .res_pack_path
+                                .as_ref()
+                                .map(|it| format!("{}/{it}", dir::root().unwrap())),
+                            ..get_data().config.clone()
+                        };
+                        LoadingScene::new(GameMode::Calibrate, info, config, fs, (None, None), None, None).await
+                    }));
+                }
+                let (calibration, from_recent) = match &state.calibration {
+                    Some(calibration) => (Some(calibration.clone()), false),
+                    None => (suggest_from_recent(), true),
+                };
+                if let Some(calibration) = calibration.filter(|it| (it.offset - config.offset).abs() >= 0.001) {
+                    r.x += r.w + 0.01;
+                    r.w = 0.08;
+                    if ui.button("apply_calibration", r, tl!("apply")) {
+                        config.offset = (calibration.offset * 1000.).round() / 1000.;
+                        state.calibration = None;
+                    }
+                    let text = tl!(
+                        if from_recent { "recent-suggestion" } else { "calibration-result" },
+                        "offset" => format!("{:.3}", calibration.offset),
+                        "confidence" => format!("{:.0}", calibration.confidence * 100.)
+                    );
+                    ui.text(text).pos(r.right() + 0.01, r.center().y).anchor(0., 0.5).size(0.35).draw();
+                }
+                ui.dy(r.h + s);
                 let r = ui.slider(tl!("speed"), 0.5..2.0, 0.005, &mut config.speed, None);
                 ui.dy(r.h + s);
                 let r = ui.slider(tl!("note-size"), 0.8..1.2, 0.005, &mut config.note_scale, None);
@@ -238,6 +274,12 @@ impl Page for SettingsPage {
                 ui.dx(0.65);
                 let r = ui.checkbox(tl!("double-click-pause"), &mut config.double_click_to_pause);
                 ui.dy(r.h + s);
+                let r = ui.checkbox(tl!("keyboard-mode"), &mut config.keyboard.enabled);
+                ui.dy(r.h + s);
+                let mut lanes = config.keyboard.mapping == KeyboardMapping::Lanes;
+                let r = ui.checkbox(tl!("keyboard-lanes"), &mut lanes);
+                config.keyboard.mapping = if lanes { KeyboardMapping::Lanes } else { KeyboardMapping::Nearest };
+                ui.dy(r.h + s);
                 let r = ui.text(tl!("respack")).size(0.4).anchor(1., 0.).draw();
                 let mut r = Rect::new(0.02, r.y - 0.01, 0.3, r.h + 0.02);
                 if ui.button("choose_res_pack", r, &self.res_pack.info.name) {
@@ -15,8 +15,9 @@ use lyon::{
 };
 use macroquad::{prelude::*, texture::RenderTarget};
 use prpr::{
+    calibrate::Calibration,
     core::Tweenable,
-    ext::{screen_aspect, SafeTexture, ScaleType},
+    ext::{poll_future, screen_aspect, SafeTexture, ScaleType},
     scene::{show_error, show_message, NextScene, Scene},
     time::TimeManager,
     ui::{RectButton, Scroll, Ui},
@@ -212,6 +213,24 @@ impl Scene for MainScene {
         Ok(())
     }
 
+    fn on_result(&mut self, _tm: &mut TimeManager, result: Box<dyn std::any::Any>) -> Result<()> {
+        self.pages[self.page_index].resume()?;
+        let result = match result.downcast::<anyhow::Error>() {
+            Ok(error) => {
+                show_error(error.context(tl!("load-calibration-failed")));
+                return Ok(());
+            }
+            Err(res) => res,
+        };
+        if let Ok(calibration) = result.downcast::<Option<Calibration>>() {
+            if calibration.is_none() {
+                show_message(tl!("calibration-not-enough-hits"));
+            }
+            self.shared_state.calibration = *calibration;
+        }
+        Ok(())
+    }
+
     fn touch(&mut self, tm: &mut TimeManager, touch: &Touch) -> Result<bool> {
         if tm.real_time() as f32 <= self.switch_start_time + SWITCH_TIME || self.shared_state.transit.is_some() {
             return Ok(false);
@@ -232,6 +251,19 @@ impl Scene for MainScene {
     }
 
     fn update(&mut self, tm: &mut TimeManager) -> Result<()> {
+        if let Some(future) = &mut self.shared_state.scene_task {
+            if let Some(scene) = poll_future(future.as_mut()) {
+                self.shared_state.scene_task = None;
+                match scene {
+                    Ok(scene) => {
+                        // the page launching the scene should not keep running (e.g. playing music) underneath
+                        self.pages[self.page_index].pause()?;
+                        self.next_scene = Some(NextScene::Overlay(Box::new(scene)));
+                    }
+                    Err(err) => show_error(err.context(tl!("load-calibration-failed"))),
+                }
+            }
+        }
         let p = ((tm.real_time() as f32 - self.switch_start_time) / SWITCH_TIME).min(1.);
         if p < 1. {
             let p = 1. - (1. - p).powi(3);
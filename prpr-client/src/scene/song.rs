@@ -14,14 +14,17 @@ use image::DynamicImage;
 use macroquad::prelude::*;
 use pollster::FutureExt;
 use prpr::{
+    analysis::{analyze, ChartStats, DENSITY_INTERVAL},
+    calibrate,
     config::Config,
     core::Tweenable,
     ext::{poll_future, screen_aspect, JoinToString, LocalTask, RectExt, SafeTexture, ScaleType, BLACK_TEXTURE},
-    fs::{self, update_zip, FileSystem, ZipFileSystem},
+    fs::{self, FileSystem, PatchedFileSystem},
     info::ChartInfo,
     scene::{
         request_input, return_input, show_error, show_message, take_input, GameMode, GameScene, LoadingScene, NextScene, RecordUpdateState, Scene,
     },
+    simulate,
     task::Task,
     time::TimeManager,
     ui::{render_chart_info, ChartInfoEdit, Dialog, MessageHandle, RectButton, Scroll, Ui},
@@ -125,6 +128,51 @@ impl TrashBin {
     }
 }
 
+/// Draws the statistics with a graph of notes per second, returning the height taken
+fn render_stats(ui: &mut Ui, stats: Option<&ChartStats>, width: f32) -> f32 {
+    let Some(stats) = stats else {
+        return ui.text(tl!("stats-loading")).size(0.4).color(GRAY).draw().h;
+    };
+    ui.scope(|ui| {
+        let r = ui.text(tl!("stats")).size(0.6).draw();
+        let mut h = r.h + 0.02;
+        ui.dy(r.h + 0.02);
+        let peak = stats.peaks.first().map_or(0., |it| it.nps);
+        let r = ui
+            .text(tl!(
+                "stats-summary",
+                "notes" => stats.counts.total(),
+                "nps" => format!("{:.1}", stats.average_nps),
+                "peak" => format!("{peak:.1}"),
+                "difficulty" => format!("{:.1}", stats.difficulty)
+            ))
+            .size(0.4)
+            .max_width(width)
+            .multiline()
+            .draw();
+        h += r.h + 0.02;
+        ui.dy(r.h + 0.02);
+
+        let graph = Rect::new(0., 0., width, 0.15);
+        ui.fill_rect(graph, Color::new(1., 1., 1., 0.1));
+        let max = stats.density.iter().copied().fold(1., f32::max);
+        let n = stats.density.len().max(1) as f32;
+        let duration = n * DENSITY_INTERVAL;
+        for peak in &stats.peaks {
+            let x = (peak.start / duration).clamp(0., 1.) * width;
+            let w = (peak.end / duration).clamp(0., 1.) * width - x;
+            ui.fill_rect(Rect::new(x, 0., w, graph.h), Color::new(1., 1., 1., 0.1));
+        }
+        let accent = ui.accent();
+        for (i, nps) in stats.density.iter().enumerate() {
+            let bh = nps / max * graph.h;
+            ui.fill_rect(Rect::new(i as f32 / n * width, graph.h - bh, width / n, bh), accent);
+        }
+        ui.text(format!("{max:.0}")).pos(0.01, 0.01).size(0.3).color(GRAY).draw();
+        h + graph.h
+    })
+}
+
 enum SideContent {
     Edit,
     Tool,
@@ -147,6 +195,7 @@ pub struct SongScene {
     edit_button: RectButton,
     back_button: RectButton,
     center_button: RectButton,
+    difficulty_buttons: Vec<RectButton>,
 
     scroll: Scroll,
     edit_scroll: Scroll,
@@ -156,6 +205,8 @@ pub struct SongScene {
     online_illustration_task: Option<Task<Result<DynamicImage>>>,
     chart_info: Option<ChartInfo>,
     scene_task: LocalTask<Result<LoadingScene>>,
+    stats_task: LocalTask<Result<ChartStats>>,
+    stats: Option<ChartStats>,
 
     target: Option<RenderTarget>,
     first_in: bool,
@@ -240,6 +291,7 @@ impl SongScene {
             edit_button: RectButton::new(),
             back_button: RectButton::new(),
             center_button: RectButton::new(),
+            difficulty_buttons: Vec::new(),
 
             scroll: Scroll::new(),
             edit_scroll: Scroll::new(),
@@ -250,6 +302,8 @@ impl SongScene {
 
             chart_info: None,
             scene_task: None,
+            stats_task: None,
+            stats: None,
 
             target: None,
             first_in: true,
@@ -365,12 +419,37 @@ impl SongScene {
                     .size(0.7)
                     .color(color)
                     .draw();
-                ui.text(&self.chart.info.composer)
+                let composer = ui
+                    .text(&self.chart.info.composer)
                     .pos(0., r.y - 0.02)
                     .anchor(0., 1.)
                     .size(0.4)
                     .color(Color::new(1., 1., 1., 0.77 * p))
                     .draw();
+                if let Some(info) = self.chart_info.as_ref().filter(|it| it.difficulties.len() > 1) {
+                    self.difficulty_buttons.resize_with(info.difficulties.len(), RectButton::new);
+                    let h = 0.06;
+                    let mut x = 0.;
+                    for (i, (diff, button)) in info.difficulties.iter().zip(&mut self.difficulty_buttons).enumerate() {
+                        let w = ui.text(diff.level()).size(0.4).measure().w + 0.04;
+                        let r = Rect::new(x, composer.y - 0.02 - h, w, h);
+                        let bg = if i == info.difficulty_index {
+                            Color { a: p, ..ui.accent() }
+                        } else {
+                            Color::new(1., 1., 1., 0.2 * p)
+                        };
+                        ui.fill_rect(r, bg);
+                        ui.text(diff.level())
+                            .pos(r.center().x, r.center().y)
+                            .anchor(0.5, 0.5)
+                            .no_baseline()
+                            .size(0.4)
+                            .color(color)
+                            .draw();
+                        button.set(ui, r);
+                        x += w + 0.02;
+                    }
+                }
                 ui.dy(top + 0.03);
                 sy += top + 0.03;
                 if let Some(user) = self.chart.info.uploader.as_ref() {
@@ -652,7 +731,8 @@ impl SongScene {
                     show_message(tl!("fix-chart-failed"));
                 }
             }
-            h += r.h + 0.1;
+            ui.dy(r.h + 0.04);
+            h += r.h + 0.04 + render_stats(ui, self.stats.as_ref(), self.side_width - 0.2) + 0.1;
             (w, h)
         });
     }
@@ -663,14 +743,9 @@ impl SongScene {
             let path = self.chart.path.clone();
             let edit = edit.clone();
             self.save_task = Some(Task::new(async move {
-                let mut fs = fs_from_path(&path)?;
+                let fs = fs_from_path(&path)?;
                 let patches = edit.to_patches().await.with_context(|| tl!("edit-load-file-failed"))?;
-                if let Some(zip) = fs.as_any().downcast_mut::<ZipFileSystem>() {
-                    let bytes = update_zip(&mut zip.0.lock().unwrap(), patches).with_context(|| tl!("edit-save-config-failed"))?;
-                    std::fs::write(format!("{}/{}", dir::charts()?, path), bytes).with_context(|| tl!("edit-save-failed"))?;
-                } else {
-                    unreachable!();
-                }
+                PatchedFileSystem(fs, patches).commit().await.with_context(|| tl!("edit-save-failed"))?;
                 Ok(())
             }));
         }
@@ -756,12 +831,13 @@ impl SongScene {
                 fs,
                 (get_data().me.as_ref().and_then(|it| UserManager::get_avatar(&it.id)), get_data().me.as_ref().map(|it| it.id.clone())),
                 None,
-                Some(move |data| {
+                Some(move |data, fingerprint| {
                     Task::new(async move {
                         let resp = Client::post(
                             "/functions/uploadRecord",
                             json!({
                                 "data": data,
+                                "fingerprint": fingerprint,
                             }),
                         )
                         .with_session()
@@ -780,6 +856,36 @@ impl SongScene {
         Ok(())
     }
 
+    fn load_stats(&mut self) {
+        if self.stats.is_some() || self.stats_task.is_some() {
+            return;
+        }
+        let path = self.chart.path.clone();
+        let info = self.chart_info.clone().unwrap();
+        self.stats_task = Some(Box::pin(async move {
+            let mut fs = fs_from_path(&path)?;
+            let chart = simulate::load_chart_of(fs.deref_mut(), &info).await?;
+            Ok(analyze(&chart))
+        }));
+    }
+
+    fn select_difficulty(&mut self, index: usize) {
+        let info = self.chart_info.as_mut().unwrap();
+        if info.difficulty_index == index {
+            return;
+        }
+        info.select_difficulty(index);
+        self.chart.info.level = info.level.clone();
+        self.chart.info.difficulty = info.difficulty;
+        self.stats = None;
+        self.stats_task = None;
+        // keeps the unsaved edits to the fields shared by all difficulties
+        if let Some(edit) = &mut self.info_edit {
+            edit.info.select_difficulty(index);
+            self.load_stats();
+        }
+    }
+
     fn get_id(&self) -> Option<&str> {
         self.chart.info.id.as_deref().or_else(|| self.chart.path.strip_prefix("download/"))
     }
@@ -815,6 +921,11 @@ impl Scene for SongScene {
             tm.seek_to(-FADEIN_TIME as _);
         }
         self.need_reload_leaderboard = true;
+        let recent_offsets = calibrate::recent_offsets();
+        if recent_offsets != get_data().recent_offsets {
+            get_data_mut().recent_offsets = recent_offsets;
+            let _ = save_data();
+        }
         Ok(())
     }
 
@@ -840,11 +951,16 @@ impl Scene for SongScene {
                     return Ok(true);
                 }
                 if loaded && !self.online {
+                    if let Some(index) = self.difficulty_buttons.iter_mut().position(|it| it.touch(touch)) {
+                        self.select_difficulty(index);
+                        return Ok(true);
+                    }
                     if self.bin.touch(touch, tm.now() as _) {
                         return Ok(true);
                     }
                     if self.edit_button.touch(touch) {
                         self.info_edit = Some(ChartInfoEdit::new(self.chart_info.clone().unwrap()));
+                        self.load_stats();
                         self.side_content = SideContent::Edit;
                         self.side_width = 0.8;
                         self.side_enter_time = rt;
@@ -910,6 +1026,15 @@ impl Scene for SongScene {
                 self.next_scene = Some(NextScene::Overlay(Box::new(scene?)));
             }
         }
+        if let Some(future) = &mut self.stats_task {
+            if let Some(stats) = poll_future(future.as_mut()) {
+                self.stats_task = None;
+                match stats {
+                    Ok(stats) => self.stats = Some(stats),
+                    Err(err) => show_error(err.context(tl!("stats-failed"))),
+                }
+            }
+        }
         if self.leaderboard_scroll.y_scroller.pulled {
             self.fetch_leaderboard();
         }
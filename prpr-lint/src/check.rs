@@ -0,0 +1,462 @@
+use prpr::{
+    core::ChartExtra,
+    fs::{self, FileSystem},
+    info::{ChartFormat, ChartInfo},
+    parse::{check_extra, infer_format, parse_chart_tolerant, PEC_MAX_LINE},
+    scene::GameScene,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const EPS: f64 = 1e-5;
+
+const RPE_CHART_KEYS: &[&str] = &[
+    "BPMList",
+    "META",
+    "chartTime",
+    "judgeLineGroup",
+    "judgeLineList",
+    "multiLineString",
+    "multiScale",
+    "prprSettings",
+];
+const RPE_LINE_KEYS: &[&str] = &[
+    "Group",
+    "Name",
+    "Texture",
+    "alphaControl",
+    "attachUI",
+    "bpmfactor",
+    "eventLayers",
+    "extended",
+    "father",
+    "isCover",
+    "notes",
+    "numOfNotes",
+    "posControl",
+    "sizeControl",
+    "yControl",
+    "zOrder",
+];
+const RPE_LAYER_KEYS: &[&str] = &["alphaEvents", "moveXEvents", "moveYEvents", "rotateEvents", "speedEvents"];
+const RPE_EXTENDED_KEYS: &[&str] = &[
+    "colorEvents",
+    "inclineEvents",
+    "paintEvents",
+    "scaleXEvents",
+    "scaleYEvents",
+    "textEvents",
+];
+const RPE_EVENT_KEYS: &[&str] = &[
+    "bezier",
+    "bezierPoints",
+    "easingLeft",
+    "easingRight",
+    "easingType",
+    "end",
+    "endTime",
+    "linkgroup",
+    "start",
+    "startTime",
+];
+const RPE_SPEED_EVENT_KEYS: &[&str] = &["end", "endTime", "linkgroup", "start", "startTime"];
+const RPE_NOTE_KEYS: &[&str] = &[
+    "above",
+    "alpha",
+    "endTime",
+    "isFake",
+    "positionX",
+    "size",
+    "speed",
+    "startTime",
+    "type",
+    "visibleTime",
+    "yOffset",
+];
+
+const PGR_CHART_KEYS: &[&str] = &["formatVersion", "judgeLineList", "numOfNotes", "offset"];
+const PGR_LINE_KEYS: &[&str] = &[
+    "bpm",
+    "judgeLineDisappearEvents",
+    "judgeLineMoveEvents",
+    "judgeLineRotateEvents",
+    "notesAbove",
+    "notesBelow",
+    "numOfNotes",
+    "numOfNotesAbove",
+    "numOfNotesBelow",
+    "speedEvents",
+];
+const PGR_EVENT_KEYS: &[&str] = &["end", "end2", "endTime", "start", "start2", "startTime"];
+const PGR_SPEED_EVENT_KEYS: &[&str] = &["endTime", "floorPosition", "startTime", "value"];
+const PGR_NOTE_KEYS: &[&str] = &["floorPosition", "holdTime", "positionX", "speed", "time", "type"];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+#[derive(Serialize)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub kind: &'static str,
+    pub location: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Linter {
+    pub diagnostics: Vec<Diagnostic>,
+    // (object description, key) -> (count, first location)
+    unknown_keys: BTreeMap<(&'static str, String), (usize, String)>,
+}
+
+fn beats(value: &Value) -> Option<f64> {
+    let v = value.as_array()?;
+    let (a, b, c) = (v.get(0)?.as_f64()?, v.get(1)?.as_f64()?, v.get(2)?.as_f64()?);
+    Some(if c == 0. { a } else { a + b / c })
+}
+
+fn events(value: &Value) -> impl Iterator<Item = (usize, &Value)> {
+    value.as_array().into_iter().flatten().enumerate()
+}
+
+impl Linter {
+    pub fn warn(&mut self, kind: &'static str, location: impl Into<String>, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            level: Level::Warning,
+            kind,
+            location: location.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn error(&mut self, kind: &'static str, location: impl Into<String>, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic {
+            level: Level::Error,
+            kind,
+            location: location.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|it| it.level == Level::Error)
+    }
+
+    fn check_keys(&mut self, value: &Value, known: &[&str], desc: &'static str, location: &str) {
+        let Some(obj) = value.as_object() else {
+            return;
+        };
+        for key in obj.keys() {
+            if !known.contains(&key.as_str()) {
+                self.unknown_keys.entry((desc, key.clone())).or_insert_with(|| (0, location.to_owned())).0 += 1;
+            }
+        }
+    }
+
+    fn flush_unknown_keys(&mut self) {
+        for ((desc, key), (count, location)) in std::mem::take(&mut self.unknown_keys) {
+            self.warn(
+                "unknown-key",
+                location,
+                if count == 1 {
+                    format!("unknown key `{key}` in {desc} is ignored")
+                } else {
+                    format!("unknown key `{key}` in {count} {desc}s is ignored")
+                },
+            );
+        }
+    }
+
+    pub async fn lint(&mut self, fs: &mut dyn FileSystem) {
+        let mut info = match fs::load_info(fs).await {
+            Ok(info) => info,
+            Err(err) => {
+                self.error("info", "info", format!("failed to load chart info: {err:?}"));
+                return;
+            }
+        };
+        if !fs.exists(&info.music).await.unwrap_or_default() {
+            self.error("missing-file", "info", format!("music `{}` does not exist", info.music));
+        }
+        if !fs.exists(&info.illustration).await.unwrap_or_default() {
+            self.warn("missing-file", "info", format!("illustration `{}` does not exist", info.illustration));
+        }
+        if let Ok(extra) = fs.load_file("extra.json").await {
+            let result = match String::from_utf8(extra) {
+                Ok(extra) => check_extra(&extra, fs).await,
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                self.error("extra", "extra.json", format!("{err:?}"));
+            }
+        }
+        // every difficulty of the package is checked
+        for index in 0..info.difficulties.len().max(1) {
+            info.select_difficulty(index);
+            self.lint_chart(fs, &info).await;
+        }
+    }
+
+    async fn lint_chart(&mut self, fs: &mut dyn FileSystem, info: &ChartInfo) {
+        let text = match GameScene::load_chart_bytes(fs, info).await.map(String::from_utf8) {
+            Ok(Ok(text)) => text,
+            Ok(Err(_)) => {
+                self.error("chart", &info.chart, "chart is not valid UTF-8");
+                return;
+            }
+            Err(_) => {
+                self.error("missing-file", "info", format!("chart `{}` does not exist", info.chart));
+                return;
+            }
+        };
+        let format = info.format.clone().unwrap_or_else(|| infer_format(&text));
+        match format {
+            ChartFormat::Rpe => self.lint_rpe(&text, fs).await,
+            ChartFormat::Pgr => self.lint_pgr(&text),
+            ChartFormat::Pec => self.lint_pec(&text),
+        }
+        self.flush_unknown_keys();
+        match parse_chart_tolerant(&text, &format, fs, ChartExtra::default()).await {
+            Ok((_, diagnostics)) => {
+                for it in diagnostics {
+                    self.error("parse", format!("line {}, column {}", it.line, it.column), it.message);
+                }
+            }
+            Err(err) => {
+                self.error("parse", &info.chart, format!("{err:?}"));
+            }
+        }
+    }
+
+    fn lint_rpe_events(&mut self, value: &Value, speed: bool, location: &str) {
+        if value.as_array().map_or(false, Vec::is_empty) {
+            self.error("empty-events", location, "event list is empty");
+            return;
+        }
+        let mut last_end = f64::NEG_INFINITY;
+        for (id, e) in events(value) {
+            let location = format!("{location}[{id}]");
+            self.check_keys(e, if speed { RPE_SPEED_EVENT_KEYS } else { RPE_EVENT_KEYS }, "event", &location);
+            let (Some(start), Some(end)) = (beats(&e["startTime"]), beats(&e["endTime"])) else {
+                continue;
+            };
+            if end < start {
+                self.error("reversed-event", &location, format!("event ends at beat {end} before it starts at beat {start}"));
+            } else if start < last_end - EPS {
+                self.warn("overlapping-events", &location, format!("event starts at beat {start} before the previous one ends at beat {last_end}"));
+            }
+            last_end = last_end.max(end);
+        }
+    }
+
+    async fn lint_rpe(&mut self, text: &str, fs: &mut dyn FileSystem) {
+        let Ok(root) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        self.check_keys(&root, RPE_CHART_KEYS, "chart", "");
+        if root["BPMList"].as_array().map_or(true, Vec::is_empty) {
+            self.error("bpm", "BPMList", "BPM list is empty");
+        }
+        let Some(lines) = root["judgeLineList"].as_array() else {
+            return;
+        };
+        for (id, line) in lines.iter().enumerate() {
+            let location = format!("judgeLineList[{id}]");
+            self.check_keys(line, RPE_LINE_KEYS, "judge line", &location);
+            if let Some(parent) = line["father"].as_i64() {
+                if parent != -1 && (parent < 0 || parent as usize >= lines.len() || parent as usize == id) {
+                    self.error("missing-line", format!("{location}.father"), format!("parent judge line #{parent} does not exist"));
+                }
+            }
+            if let Some(factor) = line["bpmfactor"].as_f64() {
+                // the BPM of the line is divided by it
+                if factor <= 0. {
+                    self.error("bpm", format!("{location}.bpmfactor"), format!("bpm factor {factor} is not positive"));
+                }
+            }
+            if let Some(texture) = line["Texture"].as_str() {
+                if texture != "line.png" && !fs.exists(texture).await.unwrap_or_default() {
+                    self.error("missing-file", format!("{location}.Texture"), format!("texture `{texture}` does not exist"));
+                }
+            }
+            for (layer_id, layer) in events(&line["eventLayers"]) {
+                let location = format!("{location}.eventLayers[{layer_id}]");
+                self.check_keys(layer, RPE_LAYER_KEYS, "event layer", &location);
+                for key in RPE_LAYER_KEYS {
+                    self.lint_rpe_events(&layer[key], *key == "speedEvents", &format!("{location}.{key}"));
+                }
+            }
+            let extended = &line["extended"];
+            self.check_keys(extended, RPE_EXTENDED_KEYS, "extended events", &format!("{location}.extended"));
+            for key in RPE_EXTENDED_KEYS {
+                self.lint_rpe_events(&extended[key], false, &format!("{location}.extended.{key}"));
+            }
+            for (note_id, note) in events(&line["notes"]) {
+                let location = format!("{location}.notes[{note_id}]");
+                self.check_keys(note, RPE_NOTE_KEYS, "note", &location);
+                match note["type"].as_u64() {
+                    Some(1 | 3 | 4) => {}
+                    Some(2) => {
+                        if let (Some(start), Some(end)) = (beats(&note["startTime"]), beats(&note["endTime"])) {
+                            if end < start {
+                                self.error("reversed-hold", &location, format!("hold ends at beat {end} before it starts at beat {start}"));
+                            }
+                        }
+                    }
+                    kind => self.error("note-type", &location, format!("unknown note type {}", kind.map_or("-".to_owned(), |it| it.to_string()))),
+                }
+            }
+        }
+    }
+
+    fn lint_pgr(&mut self, text: &str) {
+        let Ok(root) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        self.check_keys(&root, PGR_CHART_KEYS, "chart", "");
+        for (id, line) in events(&root["judgeLineList"]) {
+            let location = format!("judgeLineList[{id}]");
+            self.check_keys(line, PGR_LINE_KEYS, "judge line", &location);
+            for key in ["judgeLineDisappearEvents", "judgeLineMoveEvents", "judgeLineRotateEvents", "speedEvents"] {
+                let location = format!("{location}.{key}");
+                if line[key].as_array().map_or(false, Vec::is_empty) {
+                    self.error("empty-events", &location, "event list is empty");
+                    continue;
+                }
+                for (event_id, e) in events(&line[key]) {
+                    let location = format!("{location}[{event_id}]");
+                    self.check_keys(e, if key == "speedEvents" { PGR_SPEED_EVENT_KEYS } else { PGR_EVENT_KEYS }, "event", &location);
+                    if let (Some(start), Some(end)) = (e["startTime"].as_f64(), e["endTime"].as_f64()) {
+                        if end < start {
+                            self.warn("reversed-event", &location, format!("event ends at {end} before it starts at {start} and will be ignored"));
+                        }
+                    }
+                }
+            }
+            for key in ["notesAbove", "notesBelow"] {
+                for (note_id, note) in events(&line[key]) {
+                    let location = format!("{location}.{key}[{note_id}]");
+                    self.check_keys(note, PGR_NOTE_KEYS, "note", &location);
+                    match note["type"].as_u64() {
+                        Some(1 | 2 | 4) => {}
+                        Some(3) => {
+                            if note["holdTime"].as_f64().map_or(false, |it| it < 0.) {
+                                self.error("reversed-hold", &location, "hold has negative hold time");
+                            }
+                        }
+                        kind => self.error("note-type", &location, format!("unknown note type {}", kind.map_or("-".to_owned(), |it| it.to_string()))),
+                    }
+                }
+            }
+        }
+    }
+
+    fn lint_pec(&mut self, text: &str) {
+        #[derive(Default)]
+        struct Line {
+            speed: bool,
+            // alpha, move, rotate: (start, end, source line)
+            events: [Vec<(f64, f64, usize)>; 3],
+            notes: usize,
+        }
+        const EVENT_DESC: [&str; 3] = ["alpha", "move", "rotate"];
+        let mut lines: Vec<Line> = Vec::new();
+        for (row, source) in text.lines().enumerate().skip(1) {
+            let row = row + 1;
+            let location = format!("line {row}");
+            let mut it = source.split_whitespace();
+            let Some(cmd) = it.next() else {
+                continue;
+            };
+            let args: Vec<_> = it.map(|it| it.parse::<f64>().ok()).collect();
+            let arg = |i: usize| args.get(i).copied().flatten();
+            let Some(line) = arg(0).filter(|_| cmd.len() == 2 && cmd != "bp") else {
+                continue;
+            };
+            let line = line as usize;
+            if line > PEC_MAX_LINE {
+                self.error("line-id", &location, format!("judge line id {line} is too large (at most {PEC_MAX_LINE})"));
+                continue;
+            }
+            if lines.len() <= line {
+                lines.resize_with(line + 1, Line::default);
+            }
+            let line = &mut lines[line];
+            let (kind, start, end) = match cmd {
+                "n1" | "n2" | "n3" | "n4" => {
+                    line.notes += 1;
+                    if cmd == "n2" {
+                        if let (Some(start), Some(end)) = (arg(1), arg(2)) {
+                            if end < start {
+                                self.error("reversed-hold", &location, format!("hold ends at beat {end} before it starts at beat {start}"));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                "cv" => {
+                    line.speed = true;
+                    continue;
+                }
+                "ca" => (0, arg(1), arg(1)),
+                "cp" => (1, arg(1), arg(1)),
+                "cd" => (2, arg(1), arg(1)),
+                "cf" => (0, arg(1), arg(2)),
+                "cm" => (1, arg(1), arg(2)),
+                "cr" => (2, arg(1), arg(2)),
+                _ => continue,
+            };
+            if let (Some(start), Some(end)) = (start, end) {
+                if end < start {
+                    self.error("reversed-event", &location, format!("event ends at beat {end} before it starts at beat {start}"));
+                }
+                line.events[kind].push((start, end, row));
+            }
+        }
+        for (id, line) in lines.iter_mut().enumerate() {
+            let location = format!("judge line #{id}");
+            if !line.speed && line.notes == 0 && line.events.iter().all(Vec::is_empty) {
+                self.error("missing-line", &location, "judge line is never defined, but a judge line after it is used");
+                continue;
+            }
+            if line.events.iter().all(Vec::is_empty) && !line.speed {
+                self.error("missing-line", &location, format!("{} notes are placed on a judge line without any events", line.notes));
+                continue;
+            }
+            if !line.speed {
+                self.error("missing-events", &location, "judge line has no speed events");
+            }
+            for (kind, events) in line.events.iter_mut().enumerate() {
+                let desc = EVENT_DESC[kind];
+                if events.is_empty() {
+                    self.error("missing-events", &location, format!("judge line has no {desc} events"));
+                    continue;
+                }
+                // mirrors `sanitize_events` in the PEC parser
+                events.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.total_cmp(&b.0)));
+                let mut last = (0., f64::NEG_INFINITY);
+                for &(start, end, row) in events.iter() {
+                    let start = if start < last.1 {
+                        self.warn(
+                            "overlapping-events",
+                            format!("line {row}"),
+                            format!(
+                                "{desc} event [{start}, {end}) on {location} overlaps [{}, {}) and will be clipped to [{}, {end})",
+                                last.0, last.1, last.1
+                            ),
+                        );
+                        last.1
+                    } else {
+                        start
+                    };
+                    last = (start, end);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+mod check;
+
+use crate::check::{Level, Linter};
+use anyhow::{bail, Result};
+use prpr::fs;
+use serde::Serialize;
+use std::{any::Any, panic::AssertUnwindSafe, path::Path};
+
+#[derive(Serialize)]
+struct Report<'a> {
+    path: &'a str,
+    diagnostics: &'a [check::Diagnostic],
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+fn main() -> Result<()> {
+    let mut json = false;
+    let mut strict = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--strict" => strict = true,
+            _ if arg.starts_with("--") => bail!("Unknown option: {arg}"),
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        bail!("Usage: prpr-lint [--json] [--strict] <chart>...");
+    }
+
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+
+    let mut failed = false;
+    let mut reports = Vec::new();
+    for path in &paths {
+        let mut linter = Linter::default();
+        match fs::fs_from_file(Path::new(path)) {
+            Ok(mut fs) => {
+                if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(|| rt.block_on(linter.lint(fs.as_mut())))) {
+                    linter.error("panic", "", format!("chart crashes the parser: {}", panic_message(payload)));
+                }
+            }
+            Err(err) => linter.error("io", "", format!("{err:?}")),
+        }
+        failed |= linter.has_errors() || (strict && !linter.diagnostics.is_empty());
+        reports.push((path, linter));
+    }
+
+    if json {
+        let reports: Vec<_> = reports
+            .iter()
+            .map(|(path, linter)| Report {
+                path,
+                diagnostics: &linter.diagnostics,
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for (path, linter) in &reports {
+            println!("{path}:");
+            for diag in &linter.diagnostics {
+                let level = match diag.level {
+                    Level::Warning => "warning",
+                    Level::Error => "error",
+                };
+                if diag.location.is_empty() {
+                    println!("  {level}[{}]: {}", diag.kind, diag.message);
+                } else {
+                    println!("  {level}[{}] {}: {}", diag.kind, diag.location, diag.message);
+                }
+            }
+            let errors = linter.diagnostics.iter().filter(|it| it.level == Level::Error).count();
+            println!("  {errors} error(s), {} warning(s)", linter.diagnostics.len() - errors);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use anyhow::{bail, Context, Result};
+use prpr::{
+    core::ChartExtra,
+    fs::{self, FileSystem, ZipFileSystem},
+    info::ChartInfo,
+    parse::{check_extra, infer_format, parse_chart},
+    scene::GameScene,
+};
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+const USAGE: &str = "Usage: prpr-pack [--check] [-o <output>] <chart folder or archive>";
+const INFO_FILES: &[&str] = &["info.yml", "info.txt", "info.csv"];
+
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Report {
+    fn error(&mut self, msg: impl Into<String>) {
+        self.errors.push(msg.into());
+    }
+
+    fn warn(&mut self, msg: impl Into<String>) {
+        self.warnings.push(msg.into());
+    }
+}
+
+fn read_folder(root: &Path, dir: &Path, files: &mut BTreeMap<String, Vec<u8>>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Cannot list {}", dir.display()))? {
+        let path = entry?.path();
+        // hidden files like .DS_Store are left out
+        if path.file_name().and_then(|it| it.to_str()).map_or(true, |it| it.starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            read_folder(root, &path, files)?;
+        } else {
+            let name = path
+                .strip_prefix(root)?
+                .iter()
+                .map(|it| it.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.insert(name, std::fs::read(&path).with_context(|| format!("Cannot read {}", path.display()))?);
+        }
+    }
+    Ok(())
+}
+
+fn read_archive(bytes: Vec<u8>) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut zip = ZipArchive::new(Cursor::new(bytes)).context("Not a zip archive")?;
+    let mut files = BTreeMap::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let Some(path) = entry
+            .enclosed_name()
+            .map(|it| it.iter().map(|it| it.to_string_lossy()).collect::<Vec<_>>().join("/"))
+        else {
+            continue;
+        };
+        // hidden files and macOS metadata are left out
+        if entry.is_dir() || path.starts_with("__MACOSX/") || path.split('/').any(|it| it.starts_with('.')) {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(path, data);
+    }
+    Ok(files)
+}
+
+/// Moves the files up as long as all of them are inside one folder, which is what zipping a folder by hand usually gives
+fn strip_nested_root(mut files: BTreeMap<String, Vec<u8>>, report: &mut Report) -> BTreeMap<String, Vec<u8>> {
+    loop {
+        let Some(root) = files.keys().next().and_then(|it| it.split_once('/')).map(|it| format!("{}/", it.0)) else {
+            return files;
+        };
+        if !files.keys().all(|it| it.starts_with(&root)) {
+            return files;
+        }
+        report.warn(format!("nested root `{root}` is removed"));
+        files = files.into_iter().map(|(path, data)| (path[root.len()..].to_owned(), data)).collect();
+    }
+}
+
+/// Writes the files in order with fixed timestamps and permissions, so that the same content always gives the same archive
+fn write_zip(files: &BTreeMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut w = ZipWriter::new(Cursor::new(&mut buffer));
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default())
+        .unix_permissions(0o644);
+    for (path, data) in files {
+        w.start_file(path, options)?;
+        w.write_all(data)?;
+    }
+    w.finish()?;
+    drop(w);
+    Ok(buffer)
+}
+
+fn is_chart(path: &str) -> bool {
+    !path.contains('/') && path != "extra.json" && (path.ends_with(".json") || path.ends_with(".pec"))
+}
+
+/// The file the chart is loaded from. PEC charts are also looked up with the `.json` extension, see [`GameScene::load_chart_bytes`]
+fn chart_file(files: &BTreeMap<String, Vec<u8>>, chart: &str) -> String {
+    if !files.contains_key(chart) {
+        if let Some(name) = chart.strip_suffix(".pec") {
+            return format!("{name}.json");
+        }
+    }
+    chart.to_owned()
+}
+
+fn charts(info: &ChartInfo) -> Vec<String> {
+    if info.difficulties.is_empty() {
+        vec![info.chart.clone()]
+    } else {
+        info.difficulties.iter().map(|it| it.chart().to_owned()).collect()
+    }
+}
+
+/// Loads the chart info and fixes the references to files that do not exist, leaving the rest of it as written
+async fn prepare_info(fs: &mut dyn FileSystem, report: &mut Report) -> Result<ChartInfo> {
+    async fn chart_exists(fs: &mut dyn FileSystem, chart: &str) -> bool {
+        let info = ChartInfo {
+            chart: chart.to_owned(),
+            ..ChartInfo::default()
+        };
+        GameScene::load_chart_bytes(fs, &info).await.is_ok()
+    }
+    let mut info = fs::load_info(fs).await.context("Failed to load chart info")?;
+    // what would be inferred from the files alone
+    let mut inferred = None;
+    macro_rules! inferred {
+        () => {{
+            if inferred.is_none() {
+                let mut fixed = ChartInfo::default();
+                fs::fix_info(fs, &mut fixed).await?;
+                inferred = Some(fixed);
+            }
+            inferred.as_ref().unwrap()
+        }};
+    }
+    if info.difficulties.is_empty() {
+        if !chart_exists(fs, &info.chart).await {
+            let fixed = inferred!();
+            let chart = fixed.difficulties.first().map_or(fixed.chart.as_str(), |it| it.chart()).to_owned();
+            report.warn(format!("chart `{}` does not exist, using `{chart}`", info.chart));
+            info.chart = chart;
+        }
+    } else {
+        let mut difficulties = Vec::new();
+        for diff in std::mem::take(&mut info.difficulties) {
+            if chart_exists(fs, diff.chart()).await {
+                difficulties.push(diff);
+            } else {
+                report.warn(format!("chart `{}` of difficulty {} does not exist, removing the difficulty", diff.chart(), diff.level()));
+            }
+        }
+        if difficulties.is_empty() {
+            bail!("None of the charts of the difficulties exists");
+        }
+        info.difficulties = difficulties;
+        info.init_difficulties();
+    }
+    if !fs.exists(&info.music).await? {
+        let music = inferred!().music.clone();
+        report.warn(format!("music `{}` does not exist, using `{music}`", info.music));
+        info.music = music;
+    }
+    if !fs.exists(&info.illustration).await? {
+        let illustration = inferred!().illustration.clone();
+        report.warn(format!("illustration `{}` does not exist, using `{illustration}`", info.illustration));
+        info.illustration = illustration;
+    }
+    Ok(info)
+}
+
+async fn validate(fs: &mut dyn FileSystem, mut info: ChartInfo, report: &mut Report) -> Result<()> {
+    for (desc, path) in [("music", &info.music), ("illustration", &info.illustration)] {
+        if !fs.exists(path).await? {
+            report.error(format!("{desc} `{path}` does not exist"));
+        }
+    }
+    for index in 0..info.difficulties.len().max(1) {
+        info.select_difficulty(index);
+        let text = match GameScene::load_chart_bytes(fs, &info).await.map(String::from_utf8) {
+            Ok(Ok(text)) => text,
+            Ok(Err(_)) => {
+                report.error(format!("chart `{}` is not valid UTF-8", info.chart));
+                continue;
+            }
+            Err(_) => {
+                report.error(format!("chart `{}` does not exist", info.chart));
+                continue;
+            }
+        };
+        let format = info.format.clone().unwrap_or_else(|| infer_format(&text));
+        if let Err(err) = parse_chart(&text, &format, fs, ChartExtra::default()).await {
+            report.error(format!("chart `{}` does not parse: {err:?}", info.chart));
+        }
+    }
+    if let Ok(extra) = fs.load_file("extra.json").await {
+        let result = match String::from_utf8(extra) {
+            Ok(extra) => check_extra(&extra, fs).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            report.error(format!("extra.json is invalid: {err:?}"));
+        }
+    }
+    Ok(())
+}
+
+async fn pack(input: &Path, report: &mut Report) -> Result<Vec<u8>> {
+    let files = if input.is_dir() {
+        let mut files = BTreeMap::new();
+        read_folder(input, input, &mut files)?;
+        files
+    } else {
+        read_archive(std::fs::read(input).with_context(|| format!("Cannot read {}", input.display()))?)?
+    };
+    let mut files = strip_nested_root(files, report);
+
+    let mut fs = ZipFileSystem::new(write_zip(&files)?)?;
+    let mut info = prepare_info(&mut fs, report).await?;
+    if !files.contains_key("info.yml") {
+        report.warn("info.yml is generated");
+    }
+    for name in INFO_FILES {
+        files.remove(*name);
+    }
+    let charts: Vec<_> = charts(&info).iter().map(|it| chart_file(&files, it)).collect();
+    for chart in &charts {
+        if let Some(name) = chart.strip_suffix(".pec") {
+            let copy = format!("{name}.json");
+            if files.remove(&copy).is_some() {
+                report.warn(format!("`{copy}` is left out, since the chart is loaded from `{chart}`"));
+            }
+        }
+    }
+    for path in files.keys() {
+        if is_chart(path) && !charts.contains(path) {
+            report.warn(format!("`{path}` is not referenced by the info"));
+        }
+    }
+    info.sync_difficulty();
+    files.insert("info.yml".to_owned(), serde_yaml::to_string(&info)?.into_bytes());
+
+    let bytes = write_zip(&files)?;
+    validate(&mut ZipFileSystem::new(bytes.clone())?, info, report).await?;
+    Ok(bytes)
+}
+
+fn main() -> Result<()> {
+    let mut check = false;
+    let mut output = None;
+    let mut input = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-o" | "--output" => output = Some(PathBuf::from(args.next().context("Missing value for --output")?)),
+            _ if arg.starts_with('-') => bail!("Unknown option: {arg}"),
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("{USAGE}"),
+        }
+    }
+    let Some(input) = input else {
+        bail!("{USAGE}");
+    };
+
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    let mut report = Report::default();
+    let result = rt.block_on(pack(&input, &mut report));
+    for msg in &report.warnings {
+        println!("warning: {msg}");
+    }
+    for msg in &report.errors {
+        println!("error: {msg}");
+    }
+    let bytes = result?;
+    if !report.errors.is_empty() {
+        println!("{} error(s), nothing is written", report.errors.len());
+        std::process::exit(1);
+    }
+    if check {
+        return Ok(());
+    }
+    let output = match output {
+        Some(output) => output,
+        None if input.is_dir() => input.with_extension("pez"),
+        None => bail!("Output path is required when repacking an archive\n{USAGE}"),
+    };
+    if output == input {
+        bail!("Refusing to overwrite the input");
+    }
+    std::fs::write(&output, bytes).with_context(|| format!("Cannot write to {}", output.display()))?;
+    println!("written to {}", output.display());
+    Ok(())
+}
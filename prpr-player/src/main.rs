@@ -2,20 +2,41 @@ use anyhow::{Context, Result};
 use macroquad::prelude::*;
 use prpr::{
     build_conf,
+    calibrate::{calibration_chart, Calibration},
     core::init_assets,
     fs,
-    scene::{show_error, GameMode, LoadingScene, NextScene, Scene},
+    replay::Replay,
+    scene::{show_error, GameMode, LoadingScene, NextScene, Scene, REPLAY_DIR},
     time::TimeManager,
     ui::{FontArc, TextPainter, Ui},
     Main,
 };
-use std::ops::DerefMut;
+use std::{ops::DerefMut, rc::Rc};
 
 struct BaseScene(Option<NextScene>, bool);
 impl Scene for BaseScene {
     fn on_result(&mut self, _tm: &mut TimeManager, result: Box<dyn std::any::Any>) -> Result<()> {
-        show_error(result.downcast::<anyhow::Error>().unwrap().context("加载谱面失败"));
-        self.1 = true;
+        let result = match result.downcast::<anyhow::Error>() {
+            Ok(error) => {
+                show_error(error.context("加载谱面失败"));
+                self.1 = true;
+                return Ok(());
+            }
+            Err(res) => res,
+        };
+        if let Ok(calibration) = result.downcast::<Option<Calibration>>() {
+            match *calibration {
+                Some(it) => println!(
+                    "proposed offset: {:.3}s (confidence {:.0}%, std dev {:.1}ms, {} hits, {} rejected)",
+                    it.offset,
+                    it.confidence * 100.,
+                    it.std_dev * 1000.,
+                    it.samples,
+                    it.rejected
+                ),
+                None => println!("not enough hits to calibrate"),
+            }
+        }
         Ok(())
     }
     fn enter(&mut self, _tm: &mut TimeManager, _target: Option<RenderTarget>) -> Result<()> {
@@ -40,34 +61,60 @@ async fn main() -> Result<()> {
     init_assets();
 
     #[cfg(target_arch = "wasm32")]
-    let (mut fs, config) = {
+    let (fs, config, mode) = {
         fn js_err(err: wasm_bindgen::JsValue) -> anyhow::Error {
             anyhow::Error::msg(format!("{err:?}"))
         }
         let params = web_sys::UrlSearchParams::new_with_str(&web_sys::window().unwrap().location().search().map_err(js_err)?).map_err(js_err)?;
         let name = params.get("chart").unwrap_or_else(|| "nc".to_string());
         (
-            fs::fs_from_assets(format!("charts/{name}/"))?,
+            Some(fs::fs_from_assets(format!("charts/{name}/"))?),
             Some(prpr::config::Config {
                 autoplay: false,
                 ..Default::default()
             }),
+            GameMode::Normal,
         )
     };
     #[cfg(any(target_os = "android", target_os = "ios"))]
-    let (mut fs, config) = (fs::fs_from_assets("charts/moment/")?, None);
+    let (fs, config, mode) = (Some(fs::fs_from_assets("charts/moment/")?), None, GameMode::Normal);
     #[cfg(all(not(target_arch = "wasm32"), not(target_os = "android"), not(target_os = "ios")))]
-    let (mut fs, config) = {
+    let (fs, config, mode) = {
         let mut args = std::env::args();
         let program = args.next().unwrap();
-        let Some(path) = args.next() else {
-            anyhow::bail!("Usage: {program} <chart>");
+        let mut mode = GameMode::Normal;
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--calibrate" => {
+                    mode = GameMode::Calibrate;
+                }
+                "--replay" => {
+                    let path = args.next().context("Missing replay file")?;
+                    let replay = Replay::decode(&std::fs::read(&path).with_context(|| format!("Cannot read replay from {path}"))?)?;
+                    mode = GameMode::Replay(Rc::new(replay));
+                }
+                "--record" => {
+                    *REPLAY_DIR.lock().unwrap() = Some(args.next().context("Missing replay directory")?.into());
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let mut positional = positional.into_iter();
+        let usage = format!("Usage: {program} [--replay <file>] [--record <dir>] <chart> [config]\n       {program} --calibrate [config]");
+        let fs = if mode == GameMode::Calibrate {
+            None
+        } else {
+            let Some(path) = positional.next() else {
+                anyhow::bail!("{usage}");
+            };
+            Some(fs::fs_from_file(std::path::Path::new(&path))?)
         };
         let mut config = None;
-        if let Some(config_path) = args.next() {
+        if let Some(config_path) = positional.next() {
             config = Some(serde_yaml::from_str(&std::fs::read_to_string(config_path).context("Cannot read from config file")?)?);
         }
-        (fs::fs_from_file(std::path::Path::new(&path))?, config)
+        (fs, config, mode)
     };
 
     let _guard = {
@@ -90,7 +137,10 @@ async fn main() -> Result<()> {
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
-    let info = fs::load_info(fs.deref_mut()).await?;
+    let (info, fs) = match fs {
+        Some(mut fs) => (fs::load_info(fs.deref_mut()).await?, fs),
+        None => calibration_chart()?,
+    };
     let config = config.unwrap_or_default();
 
     let mut fps_time = -1;
@@ -98,10 +148,7 @@ async fn main() -> Result<()> {
     let tm = TimeManager::default();
     let ctm = TimeManager::from_config(&config); // strange variable name...
     let mut main = Main::new(
-        Box::new(BaseScene(
-            Some(NextScene::Overlay(Box::new(LoadingScene::new(GameMode::Normal, info, config, fs, (None, None), None, None).await?))),
-            false,
-        )),
+        Box::new(BaseScene(Some(NextScene::Overlay(Box::new(LoadingScene::new(mode, info, config, fs, (None, None), None, None).await?))), false)),
         ctm,
         None,
     )
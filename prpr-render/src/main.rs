@@ -1,6 +1,12 @@
+mod mix;
+mod output;
 mod scene;
 
-use crate::scene::MainScene;
+use crate::{
+    mix::{resample, Mixer},
+    output::{FfmpegOutput, ImageSequenceOutput, Output, RawOutput},
+    scene::MainScene,
+};
 use anyhow::{bail, Context, Result};
 use macroquad::{miniquad::TextureFormat, prelude::*};
 use prpr::{
@@ -8,17 +14,22 @@ use prpr::{
     config::Config,
     core::{init_assets, MSRenderTarget, NoteKind},
     fs::{self, PatchedFileSystem},
+    judge::Judgement,
+    parse::chart_fingerprint,
+    replay::Replay,
     scene::{GameMode, GameScene, LoadingScene, BILLBOARD},
+    simulate::{simulate, SimulationConfig},
     time::TimeManager,
     ui::{ChartInfoEdit, FontArc, TextPainter, Ui},
     Main,
 };
 use sasa::AudioClip;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
 use std::{
     cell::RefCell,
-    io::{BufWriter, Write},
-    ops::DerefMut,
-    process::{Command, Stdio},
+    ops::{DerefMut, Range},
+    path::Path,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -26,38 +37,258 @@ use std::{
     },
     time::Instant,
 };
-use std::{fmt::Write as _, path::Path};
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+enum OutputFormat {
+    /// Encoded by ffmpeg, see [`FfmpegOutput`]
+    #[default]
+    Video,
+    Png,
+    Raw,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
 struct VideoConfig {
+    format: OutputFormat,
     fps: u32,
     resolution: (u32, u32),
     hardware_accel: bool,
     ending_length: f64,
     bitrate: String,
+    sample_rate: u32,
+    /// Music time to start from. The intro still plays before it unless disabled.
+    start: Option<f64>,
+    duration: Option<f64>,
+    /// Starts from the preview time of the chart, rendering [`PREVIEW_LENGTH`] seconds by default
+    preview: bool,
+    intro: bool,
+    /// Only rendered when the chart is rendered till the end
+    ending: bool,
 }
 
 impl Default for VideoConfig {
     fn default() -> Self {
         Self {
+            format: OutputFormat::Video,
             fps: 60,
             resolution: (1920, 1080),
             hardware_accel: false,
             ending_length: 27.5,
             bitrate: "7M".to_string(),
+            sample_rate: 44100,
+            start: None,
+            duration: None,
+            preview: false,
+            intro: true,
+            ending: true,
         }
     }
 }
 
+impl VideoConfig {
+    fn validate(&self) -> Result<()> {
+        if self.fps == 0 {
+            bail!("帧率非法");
+        }
+        if self.resolution.0 == 0 || self.resolution.1 == 0 {
+            bail!("分辨率非法");
+        }
+        if !self.ending_length.is_finite() || self.ending_length < 0. {
+            bail!("结算时间非法");
+        }
+        if !(8000..=192000).contains(&self.sample_rate) {
+            bail!("采样率非法");
+        }
+        if self.start.map_or(false, |it| !it.is_finite() || it < 0.) {
+            bail!("起始时间非法");
+        }
+        if self.duration.map_or(false, |it| !it.is_finite() || it <= 0.) {
+            bail!("渲染时长非法");
+        }
+        Ok(())
+    }
+}
+
+/// A render job loaded from `--job`, for rendering without the GUI. Flags take precedence over it.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+struct RenderJob {
+    chart: Option<String>,
+    /// A file for videos and raw frames (`-` for stdout), or a folder for PNG sequences
+    output: Option<String>,
+    /// Where to write the audio as WAV along with raw frames
+    audio: Option<String>,
+    /// A recorded play to render instead of autoplay
+    replay: Option<String>,
+    video: VideoConfig,
+    /// Entries overriding `conf.yml`
+    config: Mapping,
+}
+
+/// Progress reported in headless mode, printed to stdout as one JSON object per line
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum Event<'a> {
+    Stage { stage: &'a str },
+    Progress { frame: u64, total: u64, fps: f64 },
+    Done { output: &'a str, elapsed: f64 },
+    Error { message: String },
+}
+
+const USAGE: &str = "用法：prpr-render [--headless] [--job <任务文件>] [-o <输出路径>] [--format video|png|raw] [--audio <音频输出路径>] [--fps <帧率>] [--resolution <宽x高>] [--bitrate <码率>] [--sample-rate <采样率>] \
+[--ending-length <结算时间>] [--hardware-accel] [--start <起始时间>] [--duration <时长>] [--preview] [--no-intro] [--no-ending] \
+[--replay <回放文件>] [--show-touches] [--judge-overlay] [--set <配置项>=<值>]... <谱面>
+（仅支持以 1 倍速录制的回放）";
+
+const O: f64 = LoadingScene::TOTAL_TIME as f64 + GameScene::BEFORE_TIME as f64;
+const A: f64 = 0.7 + 0.3 + 0.4;
+const PREVIEW_LENGTH: f64 = 30.;
+/// Length of the fades applied to the audio where it is cut, in seconds
+const FADE_LENGTH: f64 = 0.05;
+
 static INFO_EDIT: Mutex<Option<ChartInfoEdit>> = Mutex::new(None);
 static VIDEO_CONFIG: Mutex<Option<VideoConfig>> = Mutex::new(None);
+static HEADLESS: AtomicBool = AtomicBool::new(false);
+/// Set when stdout is taken by raw frames
+static EVENTS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+fn emit(event: Event) {
+    if HEADLESS.load(Ordering::SeqCst) {
+        let line = serde_json::to_string(&event).unwrap();
+        if EVENTS_TO_STDERR.load(Ordering::SeqCst) {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+/// Reports the stage as an event in headless mode, or logs the message otherwise
+fn stage(stage: &str, msg: &str) {
+    if HEADLESS.load(Ordering::SeqCst) {
+        emit(Event::Stage { stage });
+    } else {
+        info!("{msg}");
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32)> {
+    let Some((w, h)) = s.split_once(['x', 'X', '×', '*']) else {
+        bail!("分辨率的格式应当为 “宽x高”");
+    };
+    Ok((w.parse().context("分辨率非法")?, h.parse().context("分辨率非法")?))
+}
+
+/// Loads the job given by `--job` and applies the flags to it
+fn parse_args() -> Result<RenderJob> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut job: RenderJob = match args.iter().position(|it| it == "--job") {
+        Some(index) => {
+            let path = args.get(index + 1).context("--job 缺少任务文件路径")?;
+            let text = std::fs::read_to_string(path).with_context(|| format!("无法读取任务文件 {path}"))?;
+            serde_yaml::from_str(&text).context("任务文件格式错误")?
+        }
+        None => RenderJob::default(),
+    };
+    let mut chart = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{arg} 缺少参数值\n{USAGE}"));
+        match arg.as_str() {
+            "--headless" => {}
+            "--job" => {
+                value()?;
+            }
+            "-o" | "--output" => job.output = Some(value()?),
+            "--format" => job.video.format = serde_yaml::from_str(&value()?).context("输出格式应当为 video、png 或 raw")?,
+            "--audio" => job.audio = Some(value()?),
+            "--fps" => job.video.fps = value()?.parse().context("帧率非法")?,
+            "--resolution" => job.video.resolution = parse_resolution(&value()?)?,
+            "--bitrate" => job.video.bitrate = value()?,
+            "--sample-rate" => job.video.sample_rate = value()?.parse().context("采样率非法")?,
+            "--ending-length" => job.video.ending_length = value()?.parse().context("结算时间非法")?,
+            "--hardware-accel" => job.video.hardware_accel = true,
+            "--start" => job.video.start = Some(value()?.parse().context("起始时间非法")?),
+            "--duration" => job.video.duration = Some(value()?.parse().context("渲染时长非法")?),
+            "--preview" => job.video.preview = true,
+            "--no-intro" => job.video.intro = false,
+            "--no-ending" => job.video.ending = false,
+            "--replay" => job.replay = Some(value()?),
+            "--show-touches" => {
+                job.config.insert(Value::String("showTouches".to_owned()), Value::Bool(true));
+            }
+            "--judge-overlay" => {
+                job.config.insert(Value::String("judgeOverlay".to_owned()), Value::Bool(true));
+            }
+            "--set" => {
+                let entry = value()?;
+                let Some((key, value)) = entry.split_once('=') else {
+                    bail!("--set 的格式应当为 “配置项=值”");
+                };
+                job.config.insert(Value::String(key.to_owned()), serde_yaml::from_str(value)?);
+            }
+            _ if arg.starts_with('-') => bail!("未知的参数：{arg}\n{USAGE}"),
+            _ if chart.is_none() => chart = Some(arg),
+            _ => bail!("{USAGE}"),
+        }
+    }
+    if chart.is_some() {
+        job.chart = chart;
+    }
+    Ok(job)
+}
+
+/// Cuts the given ranges of frames out of interleaved stereo samples, fading in and out where the audio is cut
+fn cut_audio(samples: &[f32], ranges: &[Range<usize>], fade: usize) -> Vec<f32> {
+    let mut res = Vec::new();
+    for (i, range) in ranges.iter().enumerate() {
+        let fade_in = range.start != 0 && (i == 0 || ranges[i - 1].end != range.start);
+        let fade_out = range.end * 2 < samples.len() && ranges.get(i + 1).map_or(true, |it| it.start != range.end);
+        let len = range.len();
+        for j in 0..len {
+            let mut volume = 1f32;
+            if fade_in {
+                volume = volume.min(j as f32 / fade as f32);
+            }
+            if fade_out {
+                volume = volume.min((len - j) as f32 / fade as f32);
+            }
+            let pos = (range.start + j) * 2;
+            res.push(samples.get(pos).copied().unwrap_or_default() * volume);
+            res.push(samples.get(pos + 1).copied().unwrap_or_default() * volume);
+        }
+    }
+    res
+}
+
+fn apply_overrides(config: Config, overrides: Mapping) -> Result<Config> {
+    let mut value = serde_yaml::to_value(config)?;
+    let map = value.as_mapping_mut().unwrap();
+    for (key, value) in overrides {
+        if !map.contains_key(&key) {
+            bail!("未知的配置项：{}", serde_yaml::to_string(&key)?.trim());
+        }
+        map.insert(key, value);
+    }
+    serde_yaml::from_value(value).context("配置项的值非法")
+}
 
 #[cfg(target_arch = "wasm32")]
 compile_error!("WASM target is not supported");
 
 async fn the_main() -> Result<()> {
+    let headless = std::env::args().any(|it| it == "--headless");
+    HEADLESS.store(headless, Ordering::SeqCst);
     init_assets();
     set_panic_handler(|msg, backtrace| async move {
+        if HEADLESS.load(Ordering::SeqCst) {
+            emit(Event::Error { message: msg });
+            std::process::exit(1);
+        }
         let _ = std::fs::write("错误信息.txt", format!("发生错误：{msg}\n\n详细堆栈：\n{backtrace}"));
     });
 
@@ -82,9 +313,20 @@ async fn the_main() -> Result<()> {
     let font = FontArc::try_from_vec(load_file("font.ttf").await?)?;
     let mut painter = TextPainter::new(font);
 
+    let job = parse_args()?;
+    let RenderJob {
+        chart: path,
+        output: out_path,
+        audio: audio_path,
+        replay: replay_path,
+        video: v_config,
+        config: overrides,
+    } = job;
     let (path, config) = {
-        let mut args = std::env::args().skip(1);
-        let Some(path) = args.next() else {
+        let Some(path) = path else {
+            if headless {
+                bail!("{USAGE}");
+            }
             bail!("请将谱面文件或文件夹拖动到该软件上！");
         };
         let config =
@@ -95,13 +337,38 @@ async fn the_main() -> Result<()> {
                 }
                 Ok(config) => config,
             };
-        (path, config)
+        (path, apply_overrides(config, overrides)?)
     };
+    let out_path = out_path.unwrap_or_else(|| {
+        match v_config.format {
+            OutputFormat::Video => "out.mp4",
+            OutputFormat::Png => "out",
+            OutputFormat::Raw => "-",
+        }
+        .to_owned()
+    });
+    EVENTS_TO_STDERR.store(v_config.format == OutputFormat::Raw && out_path == "-", Ordering::SeqCst);
+
+    stage("load", "加载谱面…");
 
     let mut fs = fs::fs_from_file(std::path::Path::new(&path)).context("加载谱面失败")?;
     let info = fs::load_info(fs.deref_mut()).await.context("加载谱面信息失败")?;
 
-    let (chart, ..) = GameScene::load_chart(fs.deref_mut(), &info).await.context("加载谱面内容失败")?;
+    let (mut chart, ..) = GameScene::load_chart(fs.deref_mut(), &info).await.context("加载谱面内容失败")?;
+    let replay = match replay_path {
+        Some(path) => {
+            let replay = Replay::decode(&std::fs::read(&path).with_context(|| format!("无法读取回放 {path}"))?).context("回放文件格式错误")?;
+            if !replay.matches(&chart_fingerprint(&chart, &info)) {
+                bail!("回放与谱面不匹配");
+            }
+            // the music is mixed at its original speed
+            if replay.speed != 1. {
+                bail!("仅支持渲染以 1 倍速录制的回放，该回放的速度为 {}", replay.speed);
+            }
+            Some(Rc::new(replay))
+        }
+        None => None,
+    };
     macro_rules! ld {
         ($path:literal) => {
             AudioClip::new(load_file($path).await?).with_context(|| format!("加载音效 `{}` 失败", $path))?
@@ -117,151 +384,178 @@ async fn the_main() -> Result<()> {
 
     let mut gl = unsafe { get_internal_gl() };
 
-    let texture = miniquad::Texture::new_render_texture(
-        gl.quad_context,
-        miniquad::TextureParams {
-            width: 1080,
-            height: 608,
-            format: TextureFormat::RGB8,
-            ..Default::default()
-        },
-    );
-    let target = Some({
-        let render_pass = miniquad::RenderPass::new(gl.quad_context, texture, None);
-        RenderTarget {
-            texture: Texture2D::from_miniquad_texture(texture),
-            render_pass,
-        }
-    });
-    let tex = Texture2D::from_miniquad_texture(texture);
-    let mut main = Main::new(Box::new(MainScene::new(target, info, config.clone(), fs.clone_box())), TimeManager::default(), None).await?;
-    let width = texture.width as f32 / 2.;
-    loop {
-        if main.scenes.len() == 1 {
-            gl.quad_gl.viewport(Some((0, 0, texture.width as _, texture.height as _)));
-            let sw = screen_width();
-            let lf = (sw - width) / 2.;
-            main.update_with_mutate(|touch| {
-                touch.position.x -= lf / texture.width as f32 * 2.;
-            })?;
-            main.show_billboard = false;
-            main.render(&mut Ui::new(&mut painter))?;
-            gl.flush();
-            set_camera(&Camera2D {
-                zoom: vec2(1., -screen_width() / screen_height()),
+    let (edit, v_config) = if headless {
+        (ChartInfoEdit::new(info), v_config)
+    } else {
+        let texture = miniquad::Texture::new_render_texture(
+            gl.quad_context,
+            miniquad::TextureParams {
+                width: 1080,
+                height: 608,
+                format: TextureFormat::RGB8,
                 ..Default::default()
-            });
-            let mut ui = Ui::new(&mut painter);
-            clear_background(GRAY);
-            draw_texture_ex(
-                tex,
-                -1. + lf / sw * 2.,
-                -ui.top,
-                WHITE,
-                DrawTextureParams {
-                    flip_y: true,
-                    dest_size: Some(vec2(texture.width as f32, texture.height as f32) * (2. / sw)),
+            },
+        );
+        let target = Some({
+            let render_pass = miniquad::RenderPass::new(gl.quad_context, texture, None);
+            RenderTarget {
+                texture: Texture2D::from_miniquad_texture(texture),
+                render_pass,
+            }
+        });
+        let tex = Texture2D::from_miniquad_texture(texture);
+        let mut main =
+            Main::new(Box::new(MainScene::new(target, info, config.clone(), fs.clone_box(), v_config)), TimeManager::default(), None).await?;
+        let width = texture.width as f32 / 2.;
+        loop {
+            if main.scenes.len() == 1 {
+                gl.quad_gl.viewport(Some((0, 0, texture.width as _, texture.height as _)));
+                let sw = screen_width();
+                let lf = (sw - width) / 2.;
+                main.update_with_mutate(|touch| {
+                    touch.position.x -= lf / texture.width as f32 * 2.;
+                })?;
+                main.show_billboard = false;
+                main.render(&mut Ui::new(&mut painter))?;
+                gl.flush();
+                set_camera(&Camera2D {
+                    zoom: vec2(1., -screen_width() / screen_height()),
                     ..Default::default()
-                },
-            );
-            BILLBOARD.with(|it| {
-                let mut guard = it.borrow_mut();
-                let t = guard.1.now() as f32;
-                guard.0.render(&mut ui, t);
-            });
-        } else {
-            main.update()?;
-            gl.quad_gl.viewport(None);
-            gl.quad_gl.render_pass(None);
-            main.render(&mut Ui::new(&mut painter))?;
-        }
-        if main.should_exit() {
-            break;
-        }
+                });
+                let mut ui = Ui::new(&mut painter);
+                clear_background(GRAY);
+                draw_texture_ex(
+                    tex,
+                    -1. + lf / sw * 2.,
+                    -ui.top,
+                    WHITE,
+                    DrawTextureParams {
+                        flip_y: true,
+                        dest_size: Some(vec2(texture.width as f32, texture.height as f32) * (2. / sw)),
+                        ..Default::default()
+                    },
+                );
+                BILLBOARD.with(|it| {
+                    let mut guard = it.borrow_mut();
+                    let t = guard.1.now() as f32;
+                    guard.0.render(&mut ui, t);
+                });
+            } else {
+                main.update()?;
+                gl.quad_gl.viewport(None);
+                gl.quad_gl.render_pass(None);
+                main.render(&mut Ui::new(&mut painter))?;
+            }
+            if main.should_exit() {
+                break;
+            }
 
+            next_frame().await;
+        }
+        clear_background(BLACK);
         next_frame().await;
-    }
-    clear_background(BLACK);
-    next_frame().await;
 
-    let edit = INFO_EDIT.lock().unwrap().take().unwrap();
+        (INFO_EDIT.lock().unwrap().take().unwrap(), VIDEO_CONFIG.lock().unwrap().take().unwrap())
+    };
+    v_config.validate()?;
     let volume_music = config.volume_music;
     let volume_sfx = config.volume_sfx;
-    let config = Config {
+    let mut config = Config {
         autoplay: true,
         volume_music: 0.,
         volume_sfx: 0.,
         ..config
     };
+    if let Some(replay) = &replay {
+        let offset = config.offset;
+        replay.apply_config(&mut config);
+        // the frames are in chart time, and the offset of the player only lined the chart up with the music on their device
+        config.offset = offset;
+    }
 
-    let v_config = VIDEO_CONFIG.lock().unwrap().take().unwrap();
     let (vw, vh) = v_config.resolution;
 
     let length = track_length - chart.offset.min(0.) as f64 + 1.;
     let video_length = O + length + A + v_config.ending_length;
     let offset = chart.offset.max(0.);
 
-    let render_start_time = Instant::now();
+    let fps = v_config.fps;
+    let frame_delta = 1. / fps as f32;
 
-    info!("[1] 混音中…");
-    let sample_rate = 44100;
-    assert_eq!(sample_rate, ending.sample_rate());
-    assert_eq!(sample_rate, sfx_click.sample_rate());
-    assert_eq!(sample_rate, sfx_drag.sample_rate());
-    assert_eq!(sample_rate, sfx_flick.sample_rate());
-    let mut output = vec![0.0_f32; (video_length * sample_rate as f64).ceil() as usize * 2];
-    {
-        let pos = O - chart.offset.min(0.) as f64;
-        let count = (music.length() as f64 * sample_rate as f64) as usize;
-        let mut it = output[((pos * sample_rate as f64).round() as usize * 2)..].iter_mut();
-        let ratio = 1. / sample_rate as f64;
-        for frame in 0..count {
-            let position = frame as f64 * ratio;
-            let frame = music.sample(position as f32).unwrap_or_default();
-            *it.next().unwrap() += frame.0 * volume_music;
-            *it.next().unwrap() += frame.1 * volume_music;
-        }
+    // parts of the video to output, in seconds from the start of the loading scene
+    let mut segments = Vec::new();
+    if v_config.intro {
+        segments.push((0., O));
+    }
+    let music_start = O - chart.offset.min(0.) as f64;
+    let start = v_config.start.or(v_config.preview.then_some(edit.info.preview_time as f64));
+    let start = start.map_or(O, |it| (music_start + it).max(O));
+    if start >= O + length {
+        bail!("起始时间超出谱面长度");
     }
-    let mut place = |pos: f64, clip: &AudioClip, volume: f32| {
-        let position = (pos * sample_rate as f64).round() as usize * 2;
-        let slice = &mut output[position..];
-        let len = (slice.len() / 2).min(clip.frame_count());
-        let mut it = slice.iter_mut();
-        // TODO optimize?
-        for frame in clip.frames()[..len].iter() {
-            let dst = it.next().unwrap();
-            *dst += frame.0 * volume;
-            let dst = it.next().unwrap();
-            *dst += frame.1 * volume;
+    let duration = v_config.duration.or(v_config.preview.then_some(PREVIEW_LENGTH));
+    let end = duration.map_or(O + length, |it| (start + it).min(O + length));
+    segments.push((start, end));
+    if v_config.ending && end >= O + length {
+        segments.push((O + length, video_length));
+    }
+    let ranges: Vec<Range<u64>> = segments
+        .iter()
+        .map(|(start, end)| (start / frame_delta as f64).ceil() as u64..(end / frame_delta as f64).ceil() as u64)
+        .collect();
+
+    let render_start_time = Instant::now();
+    let mut sink: Box<dyn Output> = match v_config.format {
+        OutputFormat::Video => Box::new(FfmpegOutput::new(ffmpeg, v_config.clone(), out_path.clone().into())?),
+        OutputFormat::Png => Box::new(ImageSequenceOutput::new(out_path.clone().into(), (vw, vh))?),
+        OutputFormat::Raw => Box::new(RawOutput::new(&out_path, audio_path.map(Into::into), (vw, vh))?),
+    };
+
+    stage("mix", "[1] 混音中…");
+    let sample_rate = v_config.sample_rate;
+    let mut mixer = Mixer::new(sample_rate, video_length);
+    mixer.add(O - chart.offset.min(0.) as f64, &resample(&music, sample_rate), volume_music);
+    let [sfx_click, sfx_drag, sfx_flick, ending] = [&sfx_click, &sfx_drag, &sfx_flick, &ending].map(|it| resample(it, sample_rate));
+    // notes with hit sounds and when they are hit
+    let hits: Vec<(f32, NoteKind)> = match &replay {
+        Some(replay) => {
+            let result = simulate(&mut chart, &SimulationConfig::from_replay(replay), replay.frames.iter().cloned());
+            if !result.same_score(&replay.result) {
+                warn!("回放结果不一致：记录为 {:?}，模拟为 {:?}", replay.result, result);
+            }
+            result
+                .records
+                .into_iter()
+                .filter(|it| matches!(it.judgement, Judgement::Perfect | Judgement::Good))
+                .map(|it| (it.time + it.offset.unwrap_or_default(), it.kind))
+                .collect()
         }
+        None => chart
+            .lines
+            .iter()
+            .flat_map(|it| it.notes.iter())
+            .filter(|it| !it.fake)
+            .map(|it| (it.time, it.kind.clone()))
+            .collect(),
     };
-    for note in chart.lines.iter().flat_map(|it| it.notes.iter()).filter(|it| !it.fake) {
-        place(
-            O + note.time as f64 + offset as f64,
-            match note.kind {
+    for (time, kind) in hits {
+        mixer.add(
+            O + time as f64 + offset as f64,
+            match kind {
                 NoteKind::Click | NoteKind::Hold { .. } => &sfx_click,
                 NoteKind::Drag => &sfx_drag,
                 NoteKind::Flick => &sfx_flick,
             },
             volume_sfx,
-        )
-    }
-    place(O + length + A, &ending, volume_music);
-    let mut proc = Command::new(&ffmpeg)
-        .args("-y -f f32le -ar 44100 -ac 2 -i - -c:a mp3 t_audio.mp3".split_whitespace())
-        .stdin(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .context("无法执行 ffmpeg")?;
-    let input = proc.stdin.as_mut().unwrap();
-    let mut writer = BufWriter::new(input);
-    for sample in output.into_iter() {
-        writer.write_all(&sample.to_le_bytes())?;
+        );
     }
-    drop(writer);
-    proc.wait()?;
+    mixer.add(O + length + A, &ending, volume_music);
+    let output = mixer.finish();
+    let to_sample = |frame: u64| (frame as f64 * frame_delta as f64 * sample_rate as f64).round() as usize;
+    let sample_ranges: Vec<_> = ranges.iter().map(|it| to_sample(it.start)..to_sample(it.end)).collect();
+    sink.audio(&cut_audio(&output, &sample_ranges, (FADE_LENGTH * sample_rate as f64) as usize), sample_rate)?;
 
-    info!("[2] 渲染视频…");
+    stage("render", "[2] 渲染视频…");
     let mst = Rc::new(MSRenderTarget::new((vw, vh), config.sample_count));
     let my_time: Rc<RefCell<f64>> = Rc::new(RefCell::new(0.));
     let tm = TimeManager::manual(Box::new({
@@ -269,11 +563,10 @@ async fn the_main() -> Result<()> {
         move || *(*my_time).borrow()
     }));
     let fs = Box::new(PatchedFileSystem(fs, edit.to_patches().await?));
+    let mode = replay.map_or(GameMode::Normal, GameMode::Replay);
     static MSAA: AtomicBool = AtomicBool::new(false);
-    let mut main = Main::new(
-        Box::new(LoadingScene::new(GameMode::Normal, edit.info, config, fs, (None, None), Some(Rc::new(move || (vw, vh))), None).await?),
-        tm,
-        {
+    let mut main =
+        Main::new(Box::new(LoadingScene::new(mode, edit.info, config, fs, (None, None), Some(Rc::new(move || (vw, vh))), None).await?), tm, {
             let mut cnt = 0;
             let mst = Rc::clone(&mst);
             move || {
@@ -286,54 +579,25 @@ async fn the_main() -> Result<()> {
                     Some(mst.output())
                 }
             }
-        },
-    )
-    .await?;
+        })
+        .await?;
     main.show_billboard = false;
 
-    const O: f64 = LoadingScene::TOTAL_TIME as f64 + GameScene::BEFORE_TIME as f64;
-    const A: f64 = 0.7 + 0.3 + 0.4;
-
-    let fps = v_config.fps;
-    let frame_delta = 1. / fps as f32;
-
-    let codecs = String::from_utf8(Command::new(&ffmpeg).arg("-codecs").output().context("无法执行 ffmpeg")?.stdout)?;
-    let use_cuda = v_config.hardware_accel && codecs.contains("h264_nvenc");
-    let has_qsv = v_config.hardware_accel && codecs.contains("h264_qsv");
-
-    let mut args = "-y -f rawvideo -c:v rawvideo".to_owned();
-    if use_cuda {
-        args += " -hwaccel_output_format cuda";
-    }
-    write!(
-        &mut args,
-        " -s {vw}x{vh} -r {fps} -pix_fmt rgb24 -i - -i t_audio.mp3 -c:a copy -c:v {} -map 0:v:0 -map 1:a:0 -qp 0 -vf vflip t_video.mp4",
-        if use_cuda {
-            "h264_nvenc"
-        } else if has_qsv {
-            "h264_qsv"
-        } else if v_config.hardware_accel {
-            bail!("不支持硬件加速！");
-        } else {
-            "libx264 -preset ultrafast"
-        },
-    )?;
-
-    let mut proc = Command::new(&ffmpeg)
-        .args(args.split_whitespace())
-        .stdin(Stdio::piped())
-        .stderr(Stdio::null())
-        .spawn()
-        .context("无法执行 ffmpeg")?;
-    let mut input = proc.stdin.take().unwrap();
-
     let mut bytes = vec![0; vw as usize * vh as usize * 3];
 
-    let frames = (video_length / frame_delta as f64).ceil() as u64;
+    // the scenes are updated from the very start even when only a part is output, so that they end up in the same state, but only the
+    // output frames are rendered
+    let frames = ranges.last().unwrap().end;
+    let total: u64 = ranges.iter().map(|it| it.end - it.start).sum();
+    let mut written = 0;
     let start_time = Instant::now();
 
     for frame in 0..frames {
         *my_time.borrow_mut() = (frame as f32 * frame_delta).max(0.) as f64;
+        if !ranges.iter().any(|it| it.contains(&frame)) {
+            main.update()?;
+            continue;
+        }
         gl.quad_gl.render_pass(Some(mst.output().render_pass));
         clear_background(BLACK);
         main.update()?;
@@ -346,31 +610,36 @@ async fn the_main() -> Result<()> {
             mst.blit();
         }
         mst.output().texture.raw_miniquad_texture_handle().read_pixels(&mut bytes);
-        input.write_all(&bytes)?;
-        if frame % 100 == 0 {
-            info!("{frame} / {frames}, {:.2}fps", frame as f64 / start_time.elapsed().as_secs_f64());
+        sink.frame(&bytes)?;
+        if written % 100 == 0 {
+            let fps = written as f64 / start_time.elapsed().as_secs_f64();
+            if headless {
+                emit(Event::Progress { frame: written, total, fps });
+            } else {
+                info!("{written} / {total}, {fps:.2}fps");
+            }
         }
+        written += 1;
+    }
+    sink.finish()?;
+
+    let elapsed = render_start_time.elapsed().as_secs_f64();
+    if headless {
+        emit(Event::Done { output: &out_path, elapsed });
+    } else {
+        info!("渲染完成！耗时：{elapsed:.2}s");
     }
-    drop(input);
-    proc.wait()?;
-
-    info!("[3] 合并 & 转码 & 压制");
-    let _ = Command::new(&ffmpeg)
-        .args("-y -i t_video.mp4 -c:a copy -pix_fmt yuv420p -b:v".split_whitespace())
-        .arg(v_config.bitrate)
-        .arg("out.mp4")
-        .stdin(Stdio::null())
-        .stderr(Stdio::inherit())
-        .status()
-        .context("无法执行 ffmpeg")?;
-
-    info!("渲染完成！耗时：{:.2}s", render_start_time.elapsed().as_secs_f64());
     Ok(())
 }
 
 #[macroquad::main(build_conf)]
 async fn main() {
     if let Err(err) = the_main().await {
-        let _ = std::fs::write("错误信息.txt", format!("发生错误：{err:?}"));
+        if HEADLESS.load(Ordering::SeqCst) {
+            emit(Event::Error { message: format!("{err:?}") });
+        } else {
+            let _ = std::fs::write("错误信息.txt", format!("发生错误：{err:?}"));
+        }
+        std::process::exit(1);
     }
 }
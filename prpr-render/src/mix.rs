@@ -0,0 +1,134 @@
+//! Mixing the music and the sound effects into the audio of the video.
+
+use sasa::AudioClip;
+
+/// The level the limiter keeps peaks under
+const THRESHOLD: f32 = 0.95;
+/// How early the limiter starts lowering the gain before a peak, in seconds
+const ATTACK: f64 = 0.005;
+/// Time constant of the gain recovering after a peak, in seconds
+const RELEASE: f64 = 0.1;
+
+/// Zero crossings of the resampling kernel on each side
+const ZERO_CROSSINGS: usize = 32;
+/// Entries of the kernel table per zero crossing
+const KERNEL_RESOLUTION: usize = 512;
+/// Cutoff relative to the lower Nyquist frequency, leaving room for the transition band
+const ROLLOFF: f64 = 0.9;
+
+/// Blackman-windowed sinc from 0 to [`ZERO_CROSSINGS`], sampled [`KERNEL_RESOLUTION`] times per zero crossing
+fn kernel() -> Vec<f32> {
+    use std::f64::consts::PI;
+    let len = ZERO_CROSSINGS * KERNEL_RESOLUTION;
+    (0..=len + 1)
+        .map(|i| {
+            let x = i as f64 / KERNEL_RESOLUTION as f64;
+            let sinc = if i == 0 { 1. } else { (PI * x).sin() / (PI * x) };
+            let u = (x / ZERO_CROSSINGS as f64).min(1.);
+            let window = 0.42 + 0.5 * (PI * u).cos() + 0.08 * (2. * PI * u).cos();
+            (sinc * window) as f32
+        })
+        .collect()
+}
+
+/// Converts the clip to stereo frames at the given sample rate.
+///
+/// Uses a windowed-sinc filter whose cutoff follows the lower of the two rates, so that downsampling does not alias.
+pub fn resample(clip: &AudioClip, sample_rate: u32) -> Vec<(f32, f32)> {
+    let frames = clip.frames();
+    if clip.sample_rate() == sample_rate {
+        return frames.iter().map(|it| (it.0, it.1)).collect();
+    }
+    let table = kernel();
+    // input samples per output sample
+    let ratio = clip.sample_rate() as f64 / sample_rate as f64;
+    let cutoff = ratio.recip().min(1.) * ROLLOFF;
+    // the kernel is stretched when downsampling, covering more input samples
+    let half_width = ZERO_CROSSINGS as f64 / cutoff;
+    let count = (frames.len() as f64 / ratio) as usize;
+    (0..count)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let first = (pos - half_width).ceil().max(0.) as usize;
+            let last = ((pos + half_width).floor() as usize).min(frames.len() - 1);
+            let (mut left, mut right, mut total) = (0., 0., 0.);
+            for (j, frame) in frames.iter().enumerate().take(last + 1).skip(first) {
+                let x = (pos - j as f64).abs() * cutoff * KERNEL_RESOLUTION as f64;
+                let index = x as usize;
+                let t = (x - index as f64) as f32;
+                let weight = table[index] + (table[index + 1] - table[index]) * t;
+                left += frame.0 * weight;
+                right += frame.1 * weight;
+                total += weight;
+            }
+            // normalizing keeps the gain flat, also near the ends of the clip
+            if total.abs() > f32::EPSILON {
+                (left / total, right / total)
+            } else {
+                (0., 0.)
+            }
+        })
+        .collect()
+}
+
+pub struct Mixer {
+    sample_rate: u32,
+    /// Interleaved stereo samples
+    samples: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32, length: f64) -> Self {
+        Self {
+            sample_rate,
+            samples: vec![0.; (length * sample_rate as f64).ceil() as usize * 2],
+        }
+    }
+
+    /// Adds the frames (at the sample rate of the mixer) starting from `pos` seconds. Whatever falls outside the output is dropped.
+    pub fn add(&mut self, pos: f64, frames: &[(f32, f32)], volume: f32) {
+        let start = (pos * self.sample_rate as f64).round() as i64;
+        let skip = (-start).max(0) as usize;
+        let Some(output) = self.samples.get_mut(start.max(0) as usize * 2..) else {
+            return;
+        };
+        for (dst, frame) in output.chunks_exact_mut(2).zip(frames.iter().skip(skip)) {
+            dst[0] += frame.0 * volume;
+            dst[1] += frame.1 * volume;
+        }
+    }
+
+    /// Returns the mixed samples, with the peaks limited so that they do not clip
+    pub fn finish(mut self) -> Vec<f32> {
+        limit(&mut self.samples, self.sample_rate);
+        self.samples
+    }
+}
+
+/// Lowers the gain where the signal exceeds [`THRESHOLD`]. The gain ramps down over [`ATTACK`] before a peak and recovers afterwards,
+/// so that dense hit sounds are turned down smoothly instead of being cut off.
+fn limit(samples: &mut [f32], sample_rate: u32) {
+    let mut gain: Vec<f32> = samples
+        .chunks_exact(2)
+        .map(|it| {
+            let peak = it[0].abs().max(it[1].abs());
+            if peak > THRESHOLD {
+                THRESHOLD / peak
+            } else {
+                1.
+            }
+        })
+        .collect();
+    let attack = 1. / (ATTACK * sample_rate as f64) as f32;
+    for i in (0..gain.len().saturating_sub(1)).rev() {
+        gain[i] = gain[i].min(gain[i + 1] + attack);
+    }
+    let release = 1. - (-1. / (RELEASE * sample_rate as f64)).exp() as f32;
+    for i in 1..gain.len() {
+        gain[i] = gain[i].min(gain[i - 1] + (1. - gain[i - 1]) * release);
+    }
+    for (frame, gain) in samples.chunks_exact_mut(2).zip(gain) {
+        frame[0] *= gain;
+        frame[1] *= gain;
+    }
+}
@@ -0,0 +1,247 @@
+//! Where the rendered frames and the mixed audio go.
+
+use crate::{stage, VideoConfig};
+use anyhow::{bail, Context, Result};
+use image::RgbImage;
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+use tempfile::TempDir;
+
+pub trait Output {
+    /// Receives the mixed audio as interleaved stereo samples. Called once, before any frame.
+    fn audio(&mut self, samples: &[f32], sample_rate: u32) -> Result<()>;
+
+    /// Receives a frame in RGB24, with rows from bottom to top as read from OpenGL
+    fn frame(&mut self, data: &[u8]) -> Result<()>;
+
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Writes the samples as a 16-bit PCM WAV file
+pub fn write_wav(path: &Path, samples: &[f32], sample_rate: u32) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path).with_context(|| format!("无法写入 {}", path.display()))?);
+    let data_len = samples.len() as u32 * 2;
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_len).to_le_bytes())?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    // PCM, 2 channels
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&2u16.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    // bytes per second, bytes per frame and bits per sample
+    w.write_all(&(sample_rate * 4).to_le_bytes())?;
+    w.write_all(&4u16.to_le_bytes())?;
+    w.write_all(&16u16.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        w.write_all(&((sample.clamp(-1., 1.) * i16::MAX as f32) as i16).to_le_bytes())?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Encodes the video with ffmpeg. Outputs ending with `.gif` or `.webp` are written as silent looping animations.
+pub struct FfmpegOutput {
+    ffmpeg: String,
+    config: VideoConfig,
+    output: PathBuf,
+    temp_dir: TempDir,
+    proc: Option<(Child, ChildStdin)>,
+}
+
+impl FfmpegOutput {
+    pub fn new(ffmpeg: String, config: VideoConfig, output: PathBuf) -> Result<Self> {
+        Ok(Self {
+            ffmpeg,
+            config,
+            output,
+            temp_dir: tempfile::tempdir().context("无法创建临时文件夹")?,
+            proc: None,
+        })
+    }
+
+    fn audio_path(&self) -> PathBuf {
+        self.temp_dir.path().join("audio.mp3")
+    }
+
+    fn video_path(&self) -> PathBuf {
+        self.temp_dir.path().join("video.mp4")
+    }
+}
+
+impl Output for FfmpegOutput {
+    fn audio(&mut self, samples: &[f32], sample_rate: u32) -> Result<()> {
+        let mut proc = Command::new(&self.ffmpeg)
+            .args(format!("-y -f f32le -ar {sample_rate} -ac 2 -i - -c:a mp3").split_whitespace())
+            .arg(self.audio_path())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("无法执行 ffmpeg")?;
+        let input = proc.stdin.as_mut().unwrap();
+        let mut writer = BufWriter::new(input);
+        for sample in samples {
+            writer.write_all(&sample.to_le_bytes())?;
+        }
+        drop(writer);
+        if !proc.wait()?.success() {
+            bail!("混音失败");
+        }
+
+        let codecs = String::from_utf8(Command::new(&self.ffmpeg).arg("-codecs").output().context("无法执行 ffmpeg")?.stdout)?;
+        let use_cuda = self.config.hardware_accel && codecs.contains("h264_nvenc");
+        let has_qsv = self.config.hardware_accel && codecs.contains("h264_qsv");
+
+        let mut args = "-y -f rawvideo -c:v rawvideo".to_owned();
+        if use_cuda {
+            args += " -hwaccel_output_format cuda";
+        }
+        let (vw, vh) = self.config.resolution;
+        write!(&mut args, " -s {vw}x{vh} -r {} -pix_fmt rgb24 -i -", self.config.fps)?;
+        let encoder = if use_cuda {
+            "h264_nvenc"
+        } else if has_qsv {
+            "h264_qsv"
+        } else if self.config.hardware_accel {
+            bail!("不支持硬件加速！");
+        } else {
+            "libx264 -preset ultrafast"
+        };
+
+        let mut proc = Command::new(&self.ffmpeg)
+            .args(args.split_whitespace())
+            .arg("-i")
+            .arg(self.audio_path())
+            .args(format!("-c:a copy -c:v {encoder} -map 0:v:0 -map 1:a:0 -qp 0 -vf vflip").split_whitespace())
+            .arg(self.video_path())
+            .stdin(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("无法执行 ffmpeg")?;
+        let input = proc.stdin.take().unwrap();
+        self.proc = Some((proc, input));
+        Ok(())
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Result<()> {
+        self.proc.as_mut().unwrap().1.write_all(data)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let (mut proc, input) = self.proc.take().unwrap();
+        drop(input);
+        if !proc.wait()?.success() {
+            bail!("视频编码失败");
+        }
+
+        stage("encode", "[3] 合并 & 转码 & 压制");
+        let mut command = Command::new(&self.ffmpeg);
+        command.args("-y -i".split_whitespace()).arg(self.video_path());
+        // animated images are meant to loop silently
+        let extension = self.output.extension().and_then(|it| it.to_str()).map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("gif") => command
+                .args("-an -loop 0 -vf".split_whitespace())
+                .arg("split[a][b];[a]palettegen[p];[b][p]paletteuse"),
+            Some("webp") => command.args("-an -loop 0 -c:v libwebp -lossless 0 -q:v 75".split_whitespace()),
+            _ => command
+                .args("-c:a copy -pix_fmt yuv420p -b:v".split_whitespace())
+                .arg(&self.config.bitrate),
+        };
+        let status = command
+            .arg(&self.output)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .status()
+            .context("无法执行 ffmpeg")?;
+        if !status.success() {
+            bail!("转码失败");
+        }
+        Ok(())
+    }
+}
+
+/// Writes every frame as a numbered PNG file (`000000.png`, `000001.png`, ...) and the audio as `audio.wav` into a folder
+pub struct ImageSequenceOutput {
+    dir: PathBuf,
+    size: (u32, u32),
+    index: u32,
+}
+
+impl ImageSequenceOutput {
+    pub fn new(dir: PathBuf, size: (u32, u32)) -> Result<Self> {
+        std::fs::create_dir_all(&dir).with_context(|| format!("无法创建文件夹 {}", dir.display()))?;
+        Ok(Self { dir, size, index: 0 })
+    }
+}
+
+impl Output for ImageSequenceOutput {
+    fn audio(&mut self, samples: &[f32], sample_rate: u32) -> Result<()> {
+        write_wav(&self.dir.join("audio.wav"), samples, sample_rate)
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Result<()> {
+        let mut image = RgbImage::from_raw(self.size.0, self.size.1, data.to_vec()).context("帧大小不匹配")?;
+        image::imageops::flip_vertical_in_place(&mut image);
+        let path = self.dir.join(format!("{:06}.png", self.index));
+        image.save(&path).with_context(|| format!("无法写入 {}", path.display()))?;
+        self.index += 1;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the frames back to back as raw RGB24 (rows from top to bottom) to a file, a named pipe or stdout (`-`). The audio is written
+/// as WAV to a separate path, if any.
+pub struct RawOutput {
+    writer: Box<dyn Write>,
+    audio: Option<PathBuf>,
+    width: u32,
+}
+
+impl RawOutput {
+    pub fn new(output: &str, audio: Option<PathBuf>, size: (u32, u32)) -> Result<Self> {
+        let writer: Box<dyn Write> = if output == "-" {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(output).with_context(|| format!("无法写入 {output}"))?))
+        };
+        Ok(Self {
+            writer,
+            audio,
+            width: size.0,
+        })
+    }
+}
+
+impl Output for RawOutput {
+    fn audio(&mut self, samples: &[f32], sample_rate: u32) -> Result<()> {
+        if let Some(path) = &self.audio {
+            write_wav(path, samples, sample_rate)?;
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Result<()> {
+        for row in data.chunks_exact(self.width as usize * 3).rev() {
+            self.writer.write_all(row)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -22,10 +22,11 @@ pub struct MainScene {
     v_config: VideoConfig,
 
     loading_scene_task: LocalTask<Result<LoadingScene>>,
+    save_task: LocalTask<Result<()>>,
 }
 
 impl MainScene {
-    pub fn new(target: Option<RenderTarget>, info: ChartInfo, config: Config, fs: Box<dyn FileSystem>) -> Self {
+    pub fn new(target: Option<RenderTarget>, info: ChartInfo, config: Config, fs: Box<dyn FileSystem>, v_config: VideoConfig) -> Self {
         Self {
             target,
 
@@ -34,9 +35,10 @@ impl MainScene {
             config,
             fs,
             next_scene: None,
-            v_config: VideoConfig::default(),
+            v_config,
 
             loading_scene_task: None,
+            save_task: None,
         }
     }
 }
@@ -59,6 +61,17 @@ impl Scene for MainScene {
                 self.next_scene = Some(NextScene::Overlay(Box::new(scene?)));
             }
         }
+        if let Some(future) = &mut self.save_task {
+            if let Some(result) = poll_future(future.as_mut()) {
+                self.save_task = None;
+                match result {
+                    Ok(()) => {
+                        show_message("保存成功").ok();
+                    }
+                    Err(err) => show_error(err.context("保存失败")),
+                }
+            }
+        }
         Ok(())
     }
 
@@ -76,7 +89,7 @@ impl Scene for MainScene {
             let h = 0.1;
             let pad = 0.01;
             self.scroll.size((width, ui.top * 2. - h));
-            let dx = width / 2.;
+            let dx = width / 3.;
             let mut r = Rect::new(pad, ui.top * 2. - h + pad, dx - pad * 2., h - pad * 2.);
             if ui.button("preview", r, "预览") {
                 let info = self.edit.info.clone();
@@ -97,6 +110,12 @@ impl Scene for MainScene {
                 }));
             }
             r.x += dx;
+            if ui.button("save", r, "保存") && self.save_task.is_none() {
+                let fs = self.fs.clone_box();
+                let edit = self.edit.clone();
+                self.save_task = Some(Box::pin(async move { PatchedFileSystem(fs, edit.to_patches().await?).commit().await }));
+            }
+            r.x += dx;
             if ui.button("render", r, "渲染") {
                 *INFO_EDIT.lock().unwrap() = Some(self.edit.clone());
                 *VIDEO_CONFIG.lock().unwrap() = Some(self.v_config.clone());
@@ -174,6 +193,14 @@ impl Scene for MainScene {
                     let r = ui.checkbox("启用硬件加速", &mut self.v_config.hardware_accel);
                     ui.dy(r.h + pad);
                     h += r.h + pad;
+
+                    let r = ui.checkbox("渲染片头", &mut self.v_config.intro);
+                    ui.dy(r.h + pad);
+                    h += r.h + pad;
+
+                    let r = ui.checkbox("渲染结算画面", &mut self.v_config.ending);
+                    ui.dy(r.h + pad);
+                    h += r.h + pad;
                 });
                 (w, h)
             });
@@ -0,0 +1,172 @@
+use anyhow::{bail, Context, Result};
+use prpr::{
+    core::NoteKind,
+    fs,
+    judge::Judgement,
+    parse::chart_fingerprint,
+    replay::Replay,
+    simulate::{end_time, frames_from_script, load_chart, simulate, SimulationConfig},
+};
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JudgementReport {
+    line: usize,
+    note: u32,
+    kind: &'static str,
+    time: f32,
+    judgement: &'static str,
+    offset: Option<f32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report {
+    score: u32,
+    accuracy: f64,
+    max_combo: u32,
+    num_of_notes: u32,
+    counts: [u32; 4],
+    early: u32,
+    late: u32,
+    /// Whether the result matches the one recorded in the replay
+    verified: Option<bool>,
+    judgements: Vec<JudgementReport>,
+}
+
+fn kind_name(kind: &NoteKind) -> &'static str {
+    match kind {
+        NoteKind::Click => "click",
+        NoteKind::Hold { .. } => "hold",
+        NoteKind::Flick => "flick",
+        NoteKind::Drag => "drag",
+    }
+}
+
+fn judgement_name(judgement: Judgement) -> &'static str {
+    match judgement {
+        Judgement::Perfect => "perfect",
+        Judgement::Good => "good",
+        Judgement::Bad => "bad",
+        Judgement::Miss => "miss",
+    }
+}
+
+const USAGE: &str = "Usage: prpr-sim [--json] (--replay <file> | [--fps <fps>] [--speed <speed>] [--offset <offset>] --script <file>) <chart>";
+
+fn main() -> Result<()> {
+    let mut json = false;
+    let mut fps = None;
+    let mut speed = None;
+    let mut offset = None;
+    let mut replay = None;
+    let mut script = None;
+    let mut chart = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().with_context(|| format!("Missing value for {name}"));
+        match arg.as_str() {
+            "--json" => json = true,
+            "--fps" => fps = Some(value("--fps")?.parse().context("Invalid fps")?),
+            "--speed" => speed = Some(value("--speed")?.parse().context("Invalid speed")?),
+            "--offset" => offset = Some(value("--offset")?.parse().context("Invalid offset")?),
+            "--replay" => replay = Some(value("--replay")?),
+            "--script" => script = Some(value("--script")?),
+            _ if arg.starts_with("--") => bail!("Unknown option: {arg}"),
+            _ if chart.is_none() => chart = Some(arg),
+            _ => bail!("{USAGE}"),
+        }
+    }
+    let Some(chart) = chart else {
+        bail!("{USAGE}");
+    };
+
+    let rt = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+    let mut fs = fs::fs_from_file(Path::new(&chart))?;
+    let (info, mut chart) = rt.block_on(load_chart(fs.as_mut()))?;
+
+    let (config, frames, replay) = match (replay, script) {
+        (Some(path), None) => {
+            // the replay records its own speed, offset and frames
+            if fps.is_some() || speed.is_some() || offset.is_some() {
+                bail!("--fps, --speed and --offset cannot be used with --replay\n{USAGE}");
+            }
+            let replay = Replay::decode(&std::fs::read(&path).with_context(|| format!("Cannot read replay from {path}"))?)?;
+            if !replay.matches(&chart_fingerprint(&chart, &info)) {
+                bail!("The replay was recorded on a different chart");
+            }
+            let config = SimulationConfig::from_replay(&replay);
+            let frames = replay.frames.clone();
+            (config, frames, Some(replay))
+        }
+        (None, Some(path)) => {
+            let script = std::fs::read_to_string(&path).with_context(|| format!("Cannot read script from {path}"))?;
+            let config = SimulationConfig {
+                speed: speed.unwrap_or(1.),
+                aspect_ratio: info.aspect_ratio,
+                ..Default::default()
+            };
+            let end = end_time(&chart, config.speed);
+            let frames = frames_from_script(&script, &config, chart.offset + info.offset + offset.unwrap_or(0.), fps.unwrap_or(60.), end)?;
+            (config, frames, None)
+        }
+        _ => bail!("Exactly one of --replay and --script should be given\n{USAGE}"),
+    };
+
+    let result = simulate(&mut chart, &config, frames);
+    let verified = replay.map(|it| it.result.same_score(&result));
+    let report = Report {
+        score: result.score,
+        accuracy: result.accuracy,
+        max_combo: result.max_combo,
+        num_of_notes: result.num_of_notes,
+        counts: result.counts,
+        early: result.early,
+        late: result.late,
+        verified,
+        judgements: result
+            .records
+            .iter()
+            .map(|it| JudgementReport {
+                line: it.line,
+                note: it.note,
+                kind: kind_name(&it.kind),
+                time: it.time,
+                judgement: judgement_name(it.judgement),
+                offset: it.offset,
+            })
+            .collect(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("score: {:07}", report.score);
+        println!("accuracy: {:.2}%", report.accuracy * 100.);
+        println!("max combo: {}/{}", report.max_combo, report.num_of_notes);
+        let [perfect, good, bad, miss] = report.counts;
+        println!("perfect: {perfect}, good: {good}, bad: {bad}, miss: {miss}");
+        println!("early: {}, late: {}", report.early, report.late);
+        if let Some((mean, std_dev)) = result.offset_stats() {
+            println!("offset: mean {:+.1}ms, std dev {:.1}ms", mean * 1000., std_dev * 1000.);
+        }
+        if let Some(verified) = verified {
+            println!("matches recorded result: {}", if verified { "yes" } else { "no" });
+        }
+        println!("judgements:");
+        for it in &report.judgements {
+            print!("  line {} note {} ({}) at {:.3}s: {}", it.line, it.note, it.kind, it.time, it.judgement);
+            if let Some(offset) = it.offset {
+                print!(" ({:+}ms)", (offset * 1000.).round() as i32);
+            }
+            println!();
+        }
+    }
+
+    if verified == Some(false) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
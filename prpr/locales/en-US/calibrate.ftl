@@ -0,0 +1,2 @@
+name = Offset Calibration
+tip = Tap along with the beat. Listen rather than watch the notes.
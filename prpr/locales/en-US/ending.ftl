@@ -5,3 +5,7 @@ upload-cancel = Cancel upload
 upload-retry = Retry
 
 still-uploading = Uploading result, please wait…
+
+no-offsets = No timing data
+offset-stats = Mean offset { $mean }ms, std dev { $std }ms
+offset-suggestion = Suggested offset: { $offset }ms
@@ -7,3 +7,11 @@ offset-save = Save
 ex-time-out-of-range = Time is out of range
 ex-invalid-format = Invalid format
 ex-time-set = Time changed
+
+ex-auto = Auto:
+ex-auto-click = Tap
+ex-auto-drag = Drag
+ex-auto-hold = Hold
+ex-auto-flick = Flick
+
+chart-lines-skipped = Skipped { $count } malformed chart lines ({ $first })
@@ -0,0 +1,2 @@
+name = 延迟校准
+tip = 跟随节拍点击，请以声音而不是音符为准
@@ -5,3 +5,7 @@ upload-cancel = 取消上传
 upload-retry = 重试
 
 still-uploading = 尚在上传成绩
+
+no-offsets = 没有判定数据
+offset-stats = 平均偏差 { $mean }ms，标准差 { $std }ms
+offset-suggestion = 建议偏移：{ $offset }ms
@@ -7,3 +7,11 @@ offset-save = 保存
 ex-time-out-of-range = 时间不在范围内
 ex-invalid-format = 格式有误
 ex-time-set = 设置成功
+
+ex-auto = 自动：
+ex-auto-click = Tap
+ex-auto-drag = Drag
+ex-auto-hold = Hold
+ex-auto-flick = Flick
+
+chart-lines-skipped = 已跳过 { $count } 行有误的谱面内容（{ $first }）
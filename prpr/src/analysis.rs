@@ -0,0 +1,199 @@
+//! Chart statistics.
+//!
+//! Counts notes, measures how dense a chart gets over time and derives a rough difficulty estimate from that, independent of the
+//! level typed in by the uploader.
+
+use crate::{
+    core::{Chart, NoteKind},
+    ext::NotNanExt,
+};
+use serde::Serialize;
+
+/// Width of the buckets of [`ChartStats::density`], in seconds
+pub const DENSITY_INTERVAL: f32 = 1.;
+/// Width of the sliding window used to find density peaks, in seconds
+pub const PEAK_WINDOW: f32 = 5.;
+const PEAK_COUNT: usize = 3;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct NoteCounts {
+    pub click: u32,
+    pub hold: u32,
+    pub flick: u32,
+    pub drag: u32,
+}
+
+impl NoteCounts {
+    pub fn total(&self) -> u32 {
+        self.click + self.hold + self.flick + self.drag
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DensityPeak {
+    pub start: f32,
+    pub end: f32,
+    pub notes: u32,
+    pub nps: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartStats {
+    /// Judged notes by kind, fake notes excluded
+    pub counts: NoteCounts,
+    pub fake_notes: u32,
+    /// Share of fake notes among all notes
+    pub fake_ratio: f32,
+    pub line_count: usize,
+    /// Time from the first judged note to the last one (hold ends included)
+    pub duration: f32,
+    pub average_nps: f32,
+    /// Judged notes per second in consecutive buckets of [`DENSITY_INTERVAL`], starting from time 0
+    pub density: Vec<f32>,
+    /// The busiest non-overlapping windows of [`PEAK_WINDOW`], busiest first
+    pub peaks: Vec<DensityPeak>,
+    /// Judged notes sharing their time with another note, that is, the ones drawn with the multiple hint
+    pub simultaneous_notes: u32,
+    /// Share of the duration during which at least one hold has to be held
+    pub hold_coverage: f32,
+    /// Heuristic difficulty, roughly on the scale of usual Phigros levels
+    pub difficulty: f32,
+}
+
+/// How much a note contributes to the difficulty, compared to a tap
+fn weight(kind: &NoteKind) -> f32 {
+    match kind {
+        NoteKind::Click => 1.,
+        NoteKind::Hold { .. } => 1.2,
+        NoteKind::Flick => 0.8,
+        NoteKind::Drag => 0.4,
+    }
+}
+
+/// Calls `f` with the notes in `[times[i], times[i] + window)` for every `i`, `times` being sorted
+fn windows(times: &[(f32, f32)], window: f32, mut f: impl FnMut(usize, usize)) {
+    let mut end = 0;
+    for start in 0..times.len() {
+        while end < times.len() && times[end].0 < times[start].0 + window {
+            end += 1;
+        }
+        f(start, end);
+    }
+}
+
+pub fn analyze(chart: &Chart) -> ChartStats {
+    let mut counts = NoteCounts::default();
+    let mut fake_notes = 0;
+    let mut simultaneous_notes = 0;
+    // (time, weight)
+    let mut notes = Vec::new();
+    let mut holds = Vec::new();
+    for note in chart.lines.iter().flat_map(|it| it.notes.iter()) {
+        if note.fake {
+            fake_notes += 1;
+            continue;
+        }
+        match note.kind {
+            NoteKind::Click => counts.click += 1,
+            NoteKind::Hold { end_time, .. } => {
+                counts.hold += 1;
+                holds.push((note.time, end_time));
+            }
+            NoteKind::Flick => counts.flick += 1,
+            NoteKind::Drag => counts.drag += 1,
+        }
+        if note.multiple_hint {
+            simultaneous_notes += 1;
+        }
+        notes.push((note.time, weight(&note.kind)));
+    }
+    notes.sort_by_key(|it| it.0.not_nan());
+    holds.sort_by_key(|it| it.0.not_nan());
+
+    let total = counts.total();
+    let all = total + fake_notes;
+    let fake_ratio = if all == 0 { 0. } else { fake_notes as f32 / all as f32 };
+    let (first, last) = match (notes.first(), notes.last()) {
+        (Some(first), Some(last)) => (first.0, holds.iter().map(|it| it.1).fold(last.0, f32::max)),
+        _ => (0., 0.),
+    };
+    let duration = last - first;
+    let average_nps = if duration > 0. { total as f32 / duration } else { 0. };
+
+    let mut density = if notes.is_empty() {
+        Vec::new()
+    } else {
+        vec![0.; (last.max(0.) / DENSITY_INTERVAL) as usize + 1]
+    };
+    for (time, _) in &notes {
+        let index = ((time.max(0.) / DENSITY_INTERVAL) as usize).min(density.len() - 1);
+        density[index] += 1. / DENSITY_INTERVAL;
+    }
+
+    let mut candidates = Vec::new();
+    let mut peak_weight = 0f32;
+    windows(&notes, PEAK_WINDOW, |start, end| {
+        candidates.push((notes[start].0, (end - start) as u32));
+        peak_weight = peak_weight.max(notes[start..end].iter().map(|it| it.1).sum());
+    });
+    candidates.sort_by_key(|it| std::cmp::Reverse(it.1));
+    let mut peaks: Vec<DensityPeak> = Vec::new();
+    for (start, count) in candidates {
+        if peaks.len() == PEAK_COUNT {
+            break;
+        }
+        let end = start + PEAK_WINDOW;
+        if peaks.iter().all(|it| end <= it.start || start >= it.end) {
+            peaks.push(DensityPeak {
+                start,
+                end,
+                notes: count,
+                nps: count as f32 / PEAK_WINDOW,
+            });
+        }
+    }
+
+    let mut covered = 0.;
+    let mut current: Option<(f32, f32)> = None;
+    for &(start, end) in &holds {
+        match current {
+            Some((from, to)) if start <= to => current = Some((from, to.max(end))),
+            _ => {
+                if let Some((from, to)) = current {
+                    covered += to - from;
+                }
+                current = Some((start, end));
+            }
+        }
+    }
+    if let Some((start, end)) = current {
+        covered += end - start;
+    }
+    let hold_coverage = if duration > 0. { (covered / duration).min(1.) } else { 0. };
+
+    // short charts are averaged over a whole window so that a single burst is not taken as the average
+    let average_weight = notes.iter().map(|it| it.1).sum::<f32>() / duration.max(PEAK_WINDOW);
+    let multiple_ratio = if total == 0 { 0. } else { simultaneous_notes as f32 / total as f32 };
+    let difficulty = estimate_difficulty(average_weight, peak_weight / PEAK_WINDOW, multiple_ratio);
+
+    ChartStats {
+        counts,
+        fake_notes,
+        fake_ratio,
+        line_count: chart.lines.len(),
+        duration,
+        average_nps,
+        density,
+        peaks,
+        simultaneous_notes,
+        hold_coverage,
+        difficulty,
+    }
+}
+
+/// Maps weighted note rates to a level. The constants are fitted by hand so that common charts land near their official levels.
+fn estimate_difficulty(average: f32, peak: f32, multiple_ratio: f32) -> f32 {
+    let rate = average * 0.3 + peak * 0.7;
+    (rate * 1.25 * (1. + multiple_ratio * 0.5) * 10.).round() / 10.
+}
@@ -0,0 +1,169 @@
+//! Offset calibration.
+//!
+//! A calibration run plays a metronome chart with a click on every beat of `cali.ogg`. The hit errors recorded by [`Judge`] are turned
+//! into a proposed [`Config::offset`] after rejecting outliers. Hit errors of recent regular plays are kept as well so that an offset can
+//! be suggested without a dedicated run. They are only kept in memory here; see [`recent_offsets`] and [`set_recent_offsets`] for
+//! persisting them.
+//!
+//! [`Judge`]: crate::judge::Judge
+//! [`Config::offset`]: crate::config::Config::offset
+
+crate::tl_file!("calibrate");
+
+use crate::{
+    fs::{self, FileSystem, PatchedFileSystem},
+    info::{ChartFormat, ChartInfo},
+    judge::PlayResult,
+};
+use anyhow::Result;
+use sasa::AudioClip;
+use serde_json::json;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// `cali.ogg` is a two-second loop with the beat at one second
+const BEAT_INTERVAL: f32 = 2.;
+const FIRST_BEAT: f32 = 1.;
+pub const BEATS: usize = 16;
+
+const MIN_SAMPLES: usize = 5;
+/// Hits closer than this to the median are never rejected
+const MIN_THRESHOLD: f32 = 0.015;
+/// Standard error (in seconds) at which the confidence drops to about 37%
+const CONFIDENCE_SCALE: f32 = 0.01;
+const RECENT_PLAYS: usize = 10;
+
+static RECENT_OFFSETS: Mutex<VecDeque<Vec<f32>>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    /// The proposed value of `Config::offset`
+    pub offset: f32,
+    /// Ranges from 0 to 1, taking both the ratio of rejected hits and the standard error into account
+    pub confidence: f32,
+    /// Standard deviation of the accepted hits
+    pub std_dev: f32,
+    pub samples: usize,
+    pub rejected: usize,
+}
+
+/// The chart info and file system of the metronome chart.
+pub fn calibration_chart() -> Result<(ChartInfo, Box<dyn FileSystem>)> {
+    // 120 BPM, so that a time unit is 1/64 seconds
+    const UNIT: f32 = 60. / 120. / 32.;
+    let forever = |value: f32| json!([{ "startTime": 0, "endTime": 1e9, "start": value, "end": value, "start2": 0.5, "end2": 0.5 }]);
+    let notes = (0..BEATS)
+        .map(|i| {
+            let time = FIRST_BEAT + BEAT_INTERVAL * i as f32;
+            json!({ "type": 1, "time": time / UNIT, "positionX": 0, "holdTime": 0, "speed": 1, "floorPosition": time })
+        })
+        .collect::<Vec<_>>();
+    let chart = json!({
+        "formatVersion": 3,
+        "offset": 0,
+        "judgeLineList": [{
+            "bpm": 120,
+            "judgeLineDisappearEvents": forever(1.),
+            "judgeLineRotateEvents": forever(0.),
+            "judgeLineMoveEvents": forever(0.5),
+            "speedEvents": [{ "startTime": 0, "endTime": 1e9, "value": 1, "floorPosition": 0 }],
+            "notesAbove": notes,
+            "notesBelow": [],
+        }],
+    });
+    let info = ChartInfo {
+        name: tl!("name").to_string(),
+        level: String::new(),
+        chart: "chart.json".to_owned(),
+        format: Some(ChartFormat::Pgr),
+        music: "cali.ogg".to_owned(),
+        illustration: String::new(),
+        tip: Some(tl!("tip").to_string()),
+        ..Default::default()
+    };
+    let fs = PatchedFileSystem(fs::fs_from_assets("")?, HashMap::from([("chart.json".to_owned(), serde_json::to_vec(&chart)?)]));
+    Ok((info, Box::new(fs)))
+}
+
+/// Repeats the loop in `cali.ogg` so that it covers all the beats of the metronome chart.
+pub fn metronome_music(clip: &AudioClip) -> AudioClip {
+    let frames = clip.frames();
+    AudioClip::from_raw(frames.iter().copied().cycle().take(frames.len() * (BEATS + 1)).collect(), clip.sample_rate())
+}
+
+/// The `Config::offset` each hit of the play suggests, given the offset and speed it was played with.
+pub fn implied_offsets(result: &PlayResult, offset: f32, speed: f32) -> Vec<f32> {
+    // hit errors are in real time while the offset is in music time
+    result.records.iter().filter_map(|it| it.offset).map(|it| offset + it * speed).collect()
+}
+
+fn median(sorted: &[f32]) -> f32 {
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Estimates the offset from the implied offsets of single hits (see [`implied_offsets`]).
+///
+/// Hits further than three (scaled) median absolute deviations from the median are rejected as outliers.
+pub fn estimate(offsets: &[f32]) -> Option<Calibration> {
+    if offsets.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut sorted = offsets.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let med = median(&sorted);
+    let mut deviations = sorted.iter().map(|it| (it - med).abs()).collect::<Vec<_>>();
+    deviations.sort_by(|a, b| a.total_cmp(b));
+    let threshold = (median(&deviations) * 1.4826 * 3.).max(MIN_THRESHOLD);
+    let inliers = sorted.into_iter().filter(|it| (it - med).abs() <= threshold).collect::<Vec<_>>();
+    if inliers.len() < MIN_SAMPLES {
+        return None;
+    }
+    let n = inliers.len() as f32;
+    let mean = inliers.iter().sum::<f32>() / n;
+    let std_dev = (inliers.iter().map(|it| (it - mean).powi(2)).sum::<f32>() / (n - 1.)).sqrt();
+    let std_err = std_dev / n.sqrt();
+    Some(Calibration {
+        offset: mean,
+        confidence: n / offsets.len() as f32 * (-std_err / CONFIDENCE_SCALE).exp(),
+        std_dev,
+        samples: inliers.len(),
+        rejected: offsets.len() - inliers.len(),
+    })
+}
+
+/// Remembers the hits of a regular play for [`suggest_from_recent`].
+pub fn record_play(result: &PlayResult, offset: f32, speed: f32) {
+    let offsets = implied_offsets(result, offset, speed);
+    if offsets.is_empty() {
+        return;
+    }
+    let mut recent = RECENT_OFFSETS.lock().unwrap();
+    if recent.len() == RECENT_PLAYS {
+        recent.pop_front();
+    }
+    recent.push_back(offsets);
+}
+
+/// The hits of the last few regular plays, for saving them across restarts.
+pub fn recent_offsets() -> Vec<Vec<f32>> {
+    RECENT_OFFSETS.lock().unwrap().iter().cloned().collect()
+}
+
+/// Restores the hits saved from [`recent_offsets`].
+pub fn set_recent_offsets(offsets: Vec<Vec<f32>>) {
+    let skip = offsets.len().saturating_sub(RECENT_PLAYS);
+    *RECENT_OFFSETS.lock().unwrap() = offsets.into_iter().skip(skip).collect();
+}
+
+/// Suggests an offset from the hits of the last few regular plays.
+pub fn suggest_from_recent() -> Option<Calibration> {
+    let offsets = RECENT_OFFSETS.lock().unwrap().iter().flatten().copied().collect::<Vec<_>>();
+    estimate(&offsets)
+}
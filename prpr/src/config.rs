@@ -1,5 +1,6 @@
+use macroquad::prelude::KeyCode;
 use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 
 pub static TIPS: Lazy<Vec<String>> = Lazy::new(|| include_str!("tips.txt").split('\n').map(str::to_owned).collect());
 
@@ -14,6 +15,73 @@ pub enum ChallengeModeColor {
     Rainbow,
 }
 
+macro_rules! key_names {
+    ($($name:ident),* $(,)?) => {
+        const KEY_NAMES: &[(&str, KeyCode)] = &[$((stringify!($name), KeyCode::$name)),*];
+    };
+}
+
+key_names! {
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    Space, Apostrophe, Comma, Minus, Period, Slash, Semicolon, Equal, LeftBracket, Backslash, RightBracket, GraveAccent, Enter, Tab,
+    Backspace, Up, Down, Left, Right, Kp0, Kp1, Kp2, Kp3, Kp4, Kp5, Kp6, Kp7, Kp8, Kp9, KpDecimal, KpDivide, KpMultiply, KpSubtract,
+    KpAdd, KpEnter, LeftShift, LeftControl, LeftAlt, RightShift, RightControl, RightAlt,
+}
+
+/// A key, serialized by its name in [`KeyCode`] (e.g. `D`, `Key1`, `Space`).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Key(pub KeyCode);
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = KEY_NAMES.iter().find(|it| it.1 == self.0).map_or("Unknown", |it| it.0);
+        serializer.serialize_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        KEY_NAMES
+            .iter()
+            .find(|it| it.0.eq_ignore_ascii_case(&name))
+            .map(|it| Self(it.1))
+            .ok_or_else(|| D::Error::custom(format!("unknown key: {name}")))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyboardMapping {
+    /// A key hits the judgeable note closest in time
+    Nearest,
+    /// Keys split the judge area into lanes from left to right, and only hit notes in their lane
+    Lanes,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyboardConfig {
+    /// When disabled, any key acts as a tap on the earliest note
+    pub enabled: bool,
+    pub mapping: KeyboardMapping,
+    /// Keys for clicks, holds and drags
+    pub tap_keys: Vec<Key>,
+    pub flick_keys: Vec<Key>,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mapping: KeyboardMapping::Nearest,
+            tap_keys: vec![Key(KeyCode::D), Key(KeyCode::F), Key(KeyCode::J), Key(KeyCode::K)],
+            flick_keys: vec![Key(KeyCode::E), Key(KeyCode::I)],
+        }
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
@@ -31,6 +99,8 @@ pub struct Config {
     pub fix_aspect_ratio: bool,
     pub fxaa: bool,
     pub interactive: bool,
+    pub judge_overlay: bool,
+    pub keyboard: KeyboardConfig,
     pub multiple_hint: bool,
     pub note_scale: f32,
     pub offset: f32,
@@ -39,6 +109,7 @@ pub struct Config {
     pub player_rks: f32,
     pub sample_count: u32,
     pub res_pack_path: Option<String>,
+    pub show_touches: bool,
     pub speed: f32,
     pub volume_music: f32,
     pub volume_sfx: f32,
@@ -60,6 +131,8 @@ impl Default for Config {
             fix_aspect_ratio: false,
             fxaa: false,
             interactive: true,
+            judge_overlay: false,
+            keyboard: KeyboardConfig::default(),
             multiple_hint: true,
             note_scale: 1.0,
             offset: 0.,
@@ -68,6 +141,7 @@ impl Default for Config {
             player_name: "Mivik".to_string(),
             player_rks: 15.,
             sample_count: 4,
+            show_touches: false,
             speed: 1.,
             volume_music: 1.,
             volume_sfx: 1.,
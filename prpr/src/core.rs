@@ -22,7 +22,7 @@ mod effect;
 pub use effect::{Effect, Uniform};
 
 mod line;
-pub use line::{JudgeLine, JudgeLineCache, JudgeLineKind, UIElement};
+pub use line::{EventLink, JudgeLine, JudgeLineCache, JudgeLineKind, UIElement};
 
 mod note;
 use macroquad::prelude::set_pc_assets_folder;
@@ -55,7 +55,7 @@ pub fn init_assets() {
     set_pc_assets_folder("assets");
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Triple(i32, u32, u32);
 impl Default for Triple {
     fn default() -> Self {
@@ -67,6 +67,21 @@ impl Triple {
     pub fn beats(&self) -> f32 {
         self.0 as f32 + self.1 as f32 / self.2 as f32
     }
+
+    pub fn from_beats(beats: f32) -> Self {
+        let int = beats.floor();
+        let frac = beats - int;
+        // prefer small denominators, as charting tools do
+        let (num, den) = (1..=256)
+            .map(|den| ((frac * den as f32).round() as u32, den))
+            .find(|(num, den)| (frac - *num as f32 / *den as f32).abs() < 1e-4)
+            .unwrap_or_else(|| ((frac * 10000.).round() as u32, 10000));
+        if num == den {
+            Self(int as i32 + 1, 0, 1)
+        } else {
+            Self(int as i32, num, den)
+        }
+    }
 }
 
 #[derive(Default)] // the default is a dummy
@@ -92,6 +107,10 @@ impl BpmList {
         BpmList { elements, cursor: 0 }
     }
 
+    pub fn elements(&self) -> &[(f32, f32, f32)] {
+        &self.elements
+    }
+
     pub fn time_beats(&mut self, beats: f32) -> f32 {
         while let Some(kf) = self.elements.get(self.cursor + 1) {
             if kf.0 > beats {
@@ -1,6 +1,7 @@
 use super::{BpmList, Effect, JudgeLine, Matrix, Resource, UIElement, Vector, Video};
 use crate::{judge::JudgeStatus, ui::Ui};
 use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
 #[derive(Default)]
@@ -10,7 +11,9 @@ pub struct ChartExtra {
     pub videos: Vec<Video>,
 }
 
-#[derive(Default)]
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
 pub struct ChartSettings {
     pub pe_alpha_extension: bool,
     pub hold_partial_cover: bool,
@@ -77,6 +80,16 @@ impl Chart {
         }
     }
 
+    /// Marks notes before `time` as judged and resets the rest, so that the chart can be played from `time` on.
+    pub fn seek(&mut self, time: f32) {
+        for line in &mut self.lines {
+            for note in &mut line.notes {
+                note.judge = if note.time < time { JudgeStatus::Judged } else { JudgeStatus::NotJudged };
+            }
+            line.cache.reset(&mut line.notes);
+        }
+    }
+
     pub fn update(&mut self, res: &mut Resource) {
         for line in &mut self.lines {
             line.object.set_time(res.time);
@@ -7,10 +7,10 @@ use crate::{
 use macroquad::prelude::*;
 use miniquad::{RenderPass, Texture, TextureParams, TextureWrap};
 use nalgebra::Rotation2;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
 
-#[derive(Clone, Copy, Deserialize)]
+#[derive(Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
 #[repr(usize)]
 pub enum UIElement {
@@ -27,7 +27,7 @@ pub enum UIElement {
 pub enum JudgeLineKind {
     #[default]
     Normal,
-    Texture(SafeTexture),
+    Texture(SafeTexture, String),
     Text(Anim<String>),
     Paint(Anim<f32>, RefCell<(Option<RenderPass>, bool)>),
 }
@@ -81,6 +81,19 @@ impl JudgeLineCache {
     }
 }
 
+/// Membership of an RPE event in a link group. RPE writes every linked event in full, so linked lines already play as authored
+/// without propagating anything; the groups are kept for exporting.
+#[derive(Clone, Debug)]
+pub struct EventLink {
+    /// Kind of the event, like `move X`
+    pub kind: &'static str,
+    /// Index of the event layer
+    pub layer: usize,
+    /// Start time of the event
+    pub time: f32,
+    pub group: i32,
+}
+
 pub struct JudgeLine {
     pub object: Object,
     pub ctrl_obj: RefCell<CtrlObject>,
@@ -93,6 +106,10 @@ pub struct JudgeLine {
     pub z_index: i32,
     pub show_below: bool,
     pub attach_ui: Option<UIElement>,
+    /// Name of the judge line group (RPE only)
+    pub group: Option<String>,
+    /// Link groups of the events (RPE only)
+    pub links: Vec<EventLink>,
 
     pub cache: JudgeLineCache,
 }
@@ -147,13 +164,17 @@ impl JudgeLine {
     }
 
     pub fn now_transform(&self, res: &Resource, lines: &[JudgeLine]) -> Matrix {
+        self.now_transform_with(res.aspect_ratio, lines)
+    }
+
+    pub fn now_transform_with(&self, aspect_ratio: f32, lines: &[JudgeLine]) -> Matrix {
         if let Some(parent) = self.parent {
             let po = &lines[parent].object;
-            let mut tr = Rotation2::new(po.rotation.now().to_radians()) * self.object.now_translation(res);
-            tr += po.now_translation(res);
+            let mut tr = Rotation2::new(po.rotation.now().to_radians()) * self.object.now_translation_with(aspect_ratio);
+            tr += po.now_translation_with(aspect_ratio);
             self.object.now_rotation().append_translation(&tr)
         } else {
-            self.object.now(res)
+            self.object.now_with(aspect_ratio)
         }
     }
 
@@ -163,7 +184,11 @@ impl JudgeLine {
         res.with_model(self.now_transform(res, lines), |res| {
             if res.config.debug {
                 res.apply_model(|_| {
-                    ui.text(id.to_string()).pos(0., -0.01).anchor(0.5, 1.).size(0.8).draw();
+                    let text = match &self.group {
+                        Some(group) => format!("{id} ({group})"),
+                        None => id.to_string(),
+                    };
+                    ui.text(text).pos(0., -0.01).anchor(0.5, 1.).size(0.8).draw();
                 });
             }
             res.with_model(self.object.now_scale(), |res| {
@@ -174,7 +199,7 @@ impl JudgeLine {
                         let len = res.info.line_length;
                         draw_line(-len, 0., len, 0., 0.01, color);
                     }
-                    JudgeLineKind::Texture(texture) => {
+                    JudgeLineKind::Texture(texture, _) => {
                         let mut color = color.unwrap_or(WHITE);
                         color.a = alpha.max(0.0);
                         let hf = vec2(texture.width() / res.aspect_ratio, texture.height() / res.aspect_ratio);
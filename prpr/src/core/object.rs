@@ -39,7 +39,11 @@ impl Object {
     }
 
     pub fn now(&self, res: &Resource) -> Matrix {
-        self.now_rotation().append_translation(&self.now_translation(res))
+        self.now_with(res.aspect_ratio)
+    }
+
+    pub fn now_with(&self, aspect_ratio: f32) -> Matrix {
+        self.now_rotation().append_translation(&self.now_translation_with(aspect_ratio))
     }
 
     #[inline]
@@ -49,8 +53,13 @@ impl Object {
 
     #[inline]
     pub fn now_translation(&self, res: &Resource) -> Vector {
+        self.now_translation_with(res.aspect_ratio)
+    }
+
+    #[inline]
+    pub fn now_translation_with(&self, aspect_ratio: f32) -> Vector {
         let mut tr = self.translation.now();
-        tr.y /= res.aspect_ratio;
+        tr.y /= aspect_ratio;
         tr
     }
 
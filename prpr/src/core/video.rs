@@ -6,7 +6,7 @@ use macroquad::prelude::*;
 use miniquad::{Texture, TextureFormat, TextureParams, TextureWrap};
 use std::{
     cell::RefCell,
-    io::{BufRead, Read, Write},
+    io::{BufRead, Read},
     path::Path,
     process::{Child, ChildStdout, Command, Stdio},
 };
@@ -50,10 +50,7 @@ fn new_tex(w: u32, h: u32) -> Texture2D {
 }
 
 impl Video {
-    pub fn new(ffmpeg: &Path, data: Vec<u8>, start_time: f32, scale_type: ScaleType, alpha: Anim<f32>, dim: Anim<f32>) -> Result<Self> {
-        let mut video_file = NamedTempFile::new()?;
-        video_file.write_all(&data)?;
-        drop(data);
+    pub fn new(ffmpeg: &Path, video_file: NamedTempFile, start_time: f32, scale_type: ScaleType, alpha: Anim<f32>, dim: Anim<f32>) -> Result<Self> {
         let (fps, (w, h)) = || -> Result<(f64, (u32, u32))> {
             for line in Command::new(ffmpeg)
                 .arg("-i")
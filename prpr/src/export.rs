@@ -0,0 +1,10 @@
+mod pec;
+pub use pec::{export_pec, PecExportOptions};
+
+mod pgr;
+pub use pgr::{export_pgr, PgrExportOptions};
+
+mod rpe;
+pub use rpe::export_rpe;
+
+mod sample;
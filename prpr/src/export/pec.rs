@@ -0,0 +1,307 @@
+use super::sample::{bpm_ranges, line_positions, max_time, value, Sampler, END_TIME, EPS};
+use crate::{
+    core::{AnimFloat, BpmList, Chart, JudgeLine, JudgeLineKind, NoteKind, StaticTween},
+    parse::RPE_TWEEN_MAP,
+};
+use anyhow::Result;
+
+pub struct PecExportOptions {
+    /// Samples per second used when baking events PEC cannot express directly (Bezier easings, parent lines, ...)
+    pub sample_rate: f32,
+    /// Aspect ratio used to bake parent line transforms
+    pub aspect_ratio: f32,
+}
+
+impl Default for PecExportOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 32.,
+            aspect_ratio: 16. / 9.,
+        }
+    }
+}
+
+/// A piece of animation, `easing` being the PEC easing type (1 for linear)
+struct Segment<const N: usize> {
+    start_time: f32,
+    end_time: f32,
+    start: [f32; N],
+    end: [f32; N],
+    easing: u8,
+}
+
+/// Converts keyframes into segments as they are, which requires all the animations to share keyframe times and PEC easings.
+fn exact_segments<const N: usize>(anims: [&AnimFloat; N], linear_only: bool) -> Option<Vec<Segment<N>>> {
+    let first = anims[0];
+    if anims.iter().any(|it| it.next.is_some() || it.keyframes.len() != first.keyframes.len()) {
+        return None;
+    }
+    let mut res = Vec::new();
+    for (i, kf) in first.keyframes.iter().enumerate() {
+        let ids = anims.map(|it| it.keyframes[i].tween.as_any().downcast_ref::<StaticTween>().map(|it| it.0));
+        if anims.iter().any(|it| it.keyframes[i].time != kf.time) || ids.iter().any(|it| it.is_none() || *it != ids[0]) {
+            return None;
+        }
+        let start = anims.map(|it| it.keyframes[i].value);
+        let Some(next) = first.keyframes.get(i + 1) else {
+            res.push(Segment {
+                start_time: kf.time,
+                end_time: END_TIME,
+                start,
+                end: start,
+                easing: 1,
+            });
+            break;
+        };
+        let end = anims.map(|it| it.keyframes[i + 1].value);
+        res.push(match ids[0].unwrap() {
+            // holds the start value
+            0 => Segment {
+                start_time: kf.time,
+                end_time: next.time,
+                start,
+                end: start,
+                easing: 1,
+            },
+            // jumps to the end value at once
+            1 => Segment {
+                start_time: kf.time,
+                end_time: next.time,
+                start: end,
+                end,
+                easing: 1,
+            },
+            id => {
+                let easing = RPE_TWEEN_MAP.iter().position(|it| *it == id)?.max(1) as u8;
+                if linear_only && easing != 1 {
+                    return None;
+                }
+                Segment {
+                    start_time: kf.time,
+                    end_time: next.time,
+                    start,
+                    end,
+                    easing,
+                }
+            }
+        });
+    }
+    Some(res)
+}
+
+/// Whether sampling the animation loses nothing, that is, all of its tweens are linear or constant
+fn is_linear(anim: &AnimFloat) -> bool {
+    let mut anim = Some(anim);
+    while let Some(now) = anim {
+        if !now
+            .keyframes
+            .iter()
+            .all(|kf| kf.tween.as_any().downcast_ref::<StaticTween>().map_or(false, |it| it.0 <= 2))
+        {
+            return false;
+        }
+        anim = now.next.as_deref();
+    }
+    true
+}
+
+fn sampled_segments<const N: usize>(samples: Vec<(f32, f32, [f32; N], [f32; N])>) -> Vec<Segment<N>> {
+    samples
+        .into_iter()
+        .map(|(start_time, end_time, start, end)| Segment {
+            start_time,
+            end_time,
+            start,
+            end,
+            easing: 1,
+        })
+        .collect()
+}
+
+/// Writes set events (`cp`, `cd`, ...) whenever the value jumps and change events (`cm`, `cr`, ...) for the rest.
+fn write_segments<const N: usize>(
+    out: &mut Vec<String>,
+    r: &mut BpmList,
+    segments: &[Segment<N>],
+    set: impl Fn(f32, [f32; N]) -> String,
+    change: impl Fn(f32, f32, [f32; N], u8) -> String,
+) {
+    let differs = |a: &[f32; N], b: &[f32; N]| a.iter().zip(b).any(|(a, b)| (a - b).abs() > EPS);
+    let mut last = None;
+    for seg in segments {
+        let start = r.beat(seg.start_time);
+        if last.map_or(true, |it| differs(&it, &seg.start)) {
+            out.push(set(start, seg.start));
+        }
+        if seg.end_time != END_TIME && differs(&seg.start, &seg.end) {
+            out.push(change(start, r.beat(seg.end_time), seg.end, seg.easing));
+        }
+        last = Some(seg.end);
+    }
+}
+
+fn export_line(chart: &Chart, line: &JudgeLine, id: usize, sampler: &Sampler, r: &mut BpmList, out: &mut Vec<String>, warnings: &mut Vec<String>) {
+    let mut warn = |msg: &str| warnings.push(format!("Judge line #{id}: {msg}"));
+    let obj = &line.object;
+    if !obj.scale.0.is_default() || !obj.scale.1.is_default() {
+        warn("scale events are dropped");
+    }
+    if !line.incline.is_default() {
+        warn("incline events are dropped");
+    }
+    if !line.color.is_default() {
+        warn("color events are dropped");
+    }
+    match &line.kind {
+        JudgeLineKind::Normal => {}
+        JudgeLineKind::Texture(_, path) => warn(&format!("texture `{path}` is dropped")),
+        JudgeLineKind::Text(_) => warn("text events are dropped"),
+        JudgeLineKind::Paint(..) => warn("paint events are dropped"),
+    }
+    if line.attach_ui.is_some() {
+        warn("attached UI element is dropped");
+    }
+    let ctrl = line.ctrl_obj.borrow();
+    if !(ctrl.alpha.is_default() && ctrl.size.is_default() && ctrl.pos.is_default() && ctrl.y.is_default()) {
+        warn("control events are dropped");
+    }
+    drop(ctrl);
+
+    let alpha = exact_segments([&obj.alpha], true).unwrap_or_else(|| {
+        if !is_linear(&obj.alpha) {
+            warn("alpha events with easings are sampled");
+        }
+        let mut points = Vec::new();
+        sampler.breakpoints(&obj.alpha, &mut points);
+        let mut alpha = obj.alpha.clone();
+        sampled_segments(sampler.sample(points, |t| [value(&mut alpha, t, 1.)]))
+    });
+    let pe_alpha = chart.settings.pe_alpha_extension;
+    // negative alpha is kept as is by PEC, which is what the PE alpha extension means
+    let alpha_value = move |alpha: f32| if alpha < 0. && pe_alpha { alpha } else { alpha.max(0.) * 255. };
+    write_segments(
+        out,
+        r,
+        &alpha,
+        |time, [alpha]| format!("ca {id} {time} {}", alpha_value(alpha)),
+        |start, end, [alpha], _| format!("cf {id} {start} {end} {}", alpha_value(alpha)),
+    );
+
+    let rotation = exact_segments([&obj.rotation], false).unwrap_or_else(|| {
+        if !is_linear(&obj.rotation) {
+            warn("rotate events with Bezier or clamped easings are sampled");
+        }
+        let mut points = Vec::new();
+        sampler.breakpoints(&obj.rotation, &mut points);
+        let mut rotation = obj.rotation.clone();
+        sampled_segments(sampler.sample(points, |t| [value(&mut rotation, t, 0.)]))
+    });
+    write_segments(
+        out,
+        r,
+        &rotation,
+        |time, [deg]| format!("cd {id} {time} {}", -deg),
+        |start, end, [deg], easing| format!("cr {id} {start} {end} {} {easing}", -deg),
+    );
+
+    let position = if line.parent.is_some() {
+        warn("parent line is baked into sampled move events");
+        None
+    } else {
+        exact_segments([&obj.translation.0, &obj.translation.1], false)
+    }
+    .unwrap_or_else(|| {
+        if line.parent.is_none() && !(is_linear(&obj.translation.0) && is_linear(&obj.translation.1)) {
+            warn("move events with Bezier or clamped easings are sampled");
+        }
+        sampled_segments(line_positions(chart, line, sampler))
+    });
+    let to_pec = |[x, y]: [f32; 2]| ((x + 1.) / 2. * 2048., (y + 1.) / 2. * 1400.);
+    write_segments(
+        out,
+        r,
+        &position,
+        |time, pos| {
+            let (x, y) = to_pec(pos);
+            format!("cp {id} {time} {x} {y}")
+        },
+        |start, end, pos, easing| {
+            let (x, y) = to_pec(pos);
+            format!("cm {id} {start} {end} {x} {y} {easing}")
+        },
+    );
+
+    // PEC speed events are constant, which makes the height piecewise linear
+    let mut points = Vec::new();
+    sampler.breakpoints(&line.height, &mut points);
+    let mut height = line.height.clone();
+    let mut last_speed = None;
+    for (start_time, end_time, [start], [end]) in sampler.sample(points, |t| [value(&mut height, t, 0.)]) {
+        if end_time == END_TIME && last_speed.is_some() {
+            break;
+        }
+        let speed = if end_time == END_TIME {
+            0.
+        } else {
+            (end - start) / (end_time - start_time)
+        };
+        if last_speed.map_or(true, |it: f32| (it - speed).abs() > EPS) {
+            out.push(format!("cv {id} {} {}", r.beat(start_time), speed * 5.85));
+            last_speed = Some(speed);
+        }
+    }
+
+    let mut offset = 0;
+    let mut note_alpha = 0;
+    for note in &line.notes {
+        if !note.object.translation.1.is_default() {
+            offset += 1;
+        }
+        if !note.object.alpha.is_default() {
+            note_alpha += 1;
+        }
+        let x = note.object.translation.0.keyframes.first().map_or(0., |it| it.value) * 1024.;
+        let above = if note.above { 1 } else { 2 };
+        let fake = note.fake as u8;
+        let time = r.beat(note.time);
+        out.push(match note.kind {
+            NoteKind::Click => format!("n1 {id} {time} {x} {above} {fake}"),
+            NoteKind::Hold { end_time, .. } => format!("n2 {id} {time} {} {x} {above} {fake}", r.beat(end_time)),
+            NoteKind::Flick => format!("n3 {id} {time} {x} {above} {fake}"),
+            NoteKind::Drag => format!("n4 {id} {time} {x} {above} {fake}"),
+        });
+        out.push(format!("# {}", note.speed));
+        out.push(format!("& {}", note.object.scale.0.keyframes.first().map_or(1., |it| it.value)));
+    }
+    for (count, desc) in [(offset, "note Y offsets are dropped"), (note_alpha, "note alpha is dropped")] {
+        if count != 0 {
+            warnings.push(format!("Judge line #{id}: {count} {desc}"));
+        }
+    }
+}
+
+/// Writes the chart as PEC text, returning it along with the features that had to be approximated or dropped
+pub fn export_pec(chart: &Chart, options: &PecExportOptions) -> Result<(String, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let ranges = bpm_ranges(chart);
+    let mut r = BpmList::new(ranges.clone());
+    let sampler = Sampler {
+        sample_rate: options.sample_rate,
+        aspect_ratio: options.aspect_ratio,
+        max_time: max_time(chart),
+    };
+    if !chart.extra.effects.is_empty() || !chart.extra.global_effects.is_empty() {
+        warnings.push("Effects are dropped".to_owned());
+    }
+    if !chart.extra.videos.is_empty() {
+        warnings.push("Videos are dropped".to_owned());
+    }
+    // the parser reads the offset in milliseconds and shifts it by 150ms
+    let mut out = vec![format!("{}", ((chart.offset + 0.15) * 1000.).round() as i32)];
+    out.extend(ranges.into_iter().map(|(beats, bpm)| format!("bp {beats} {bpm}")));
+    for (id, line) in chart.lines.iter().enumerate() {
+        export_line(chart, line, id, &sampler, &mut r, &mut out, &mut warnings);
+    }
+    out.push(String::new());
+    Ok((out.join("\n"), warnings))
+}
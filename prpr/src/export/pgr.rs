@@ -0,0 +1,282 @@
+use super::sample::{line_positions, max_time, value, Sampler, END_TIME, EPS};
+use crate::{
+    core::{Chart, JudgeLine, JudgeLineKind, NoteKind, HEIGHT_RATIO},
+    ext::NotNanExt,
+};
+use anyhow::Result;
+use serde::Serialize;
+
+pub struct PgrExportOptions {
+    /// Samples per second used when baking events PGR cannot express directly (easings, parent lines, ...)
+    pub sample_rate: f32,
+    /// BPM written to every judge line, defaults to the first BPM of the chart
+    pub bpm: Option<f32>,
+    /// Aspect ratio used to bake parent line transforms
+    pub aspect_ratio: f32,
+}
+
+impl Default for PgrExportOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 32.,
+            bpm: None,
+            aspect_ratio: 16. / 9.,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PgrEvent {
+    start_time: f32,
+    end_time: f32,
+    start: f32,
+    end: f32,
+    start2: f32,
+    end2: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PgrSpeedEvent {
+    start_time: f32,
+    end_time: f32,
+    value: f32,
+    floor_position: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PgrNote {
+    #[serde(rename = "type")]
+    kind: u8,
+    time: f32,
+    position_x: f32,
+    hold_time: f32,
+    speed: f32,
+    floor_position: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PgrJudgeLine {
+    bpm: f32,
+    #[serde(rename = "judgeLineDisappearEvents")]
+    alpha_events: Vec<PgrEvent>,
+    #[serde(rename = "judgeLineRotateEvents")]
+    rotate_events: Vec<PgrEvent>,
+    #[serde(rename = "judgeLineMoveEvents")]
+    move_events: Vec<PgrEvent>,
+    speed_events: Vec<PgrSpeedEvent>,
+
+    notes_above: Vec<PgrNote>,
+    notes_below: Vec<PgrNote>,
+    num_of_notes: usize,
+    num_of_notes_above: usize,
+    num_of_notes_below: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PgrChart {
+    format_version: u32,
+    offset: f32,
+    num_of_notes: usize,
+    judge_line_list: Vec<PgrJudgeLine>,
+}
+
+fn export_line(chart: &Chart, line: &JudgeLine, id: usize, sampler: &Sampler, r: f32, warnings: &mut Vec<String>) -> PgrJudgeLine {
+    let mut warn = |msg: &str| warnings.push(format!("Judge line #{id}: {msg}"));
+    let obj = &line.object;
+    if !obj.scale.0.is_default() || !obj.scale.1.is_default() {
+        warn("scale events are dropped");
+    }
+    if !line.incline.is_default() {
+        warn("incline events are dropped");
+    }
+    if !line.color.is_default() {
+        warn("color events are dropped");
+    }
+    match &line.kind {
+        JudgeLineKind::Normal => {}
+        JudgeLineKind::Texture(_, path) => warn(&format!("texture `{path}` is dropped")),
+        JudgeLineKind::Text(_) => warn("text events are dropped"),
+        JudgeLineKind::Paint(..) => warn("paint events are dropped"),
+    }
+    if line.attach_ui.is_some() {
+        warn("attached UI element is dropped");
+    }
+    let ctrl = line.ctrl_obj.borrow();
+    if !(ctrl.alpha.is_default() && ctrl.size.is_default() && ctrl.pos.is_default() && ctrl.y.is_default()) {
+        warn("control events are dropped");
+    }
+    drop(ctrl);
+    if !line.show_below {
+        warn("notes below the line are always shown");
+    }
+    if chart.settings.pe_alpha_extension && obj.alpha.keyframes.iter().any(|it| it.value < 0.) {
+        warn("negative alpha extensions are dropped");
+    }
+
+    let to_units = |t: f32| t / r;
+    let event = |(start_time, end_time, start, end): (f32, f32, [f32; 2], [f32; 2])| PgrEvent {
+        start_time: to_units(start_time),
+        end_time: if end_time == END_TIME { END_TIME } else { to_units(end_time) },
+        start: start[0],
+        end: end[0],
+        start2: start[1],
+        end2: end[1],
+    };
+
+    let mut alpha = obj.alpha.clone();
+    let mut points = Vec::new();
+    sampler.breakpoints(&obj.alpha, &mut points);
+    let alpha_events = sampler
+        .sample(points, |t| [value(&mut alpha, t, 1.).max(0.), 0.])
+        .into_iter()
+        .map(event)
+        .collect();
+
+    let mut rotation = obj.rotation.clone();
+    let mut points = Vec::new();
+    sampler.breakpoints(&obj.rotation, &mut points);
+    let rotate_events = sampler
+        .sample(points, |t| [value(&mut rotation, t, 0.), 0.])
+        .into_iter()
+        .map(event)
+        .collect();
+
+    let move_events = line_positions(chart, line, sampler)
+        .into_iter()
+        .map(|(start_time, end_time, start, end)| (start_time, end_time, start.map(|it| (it + 1.) / 2.), end.map(|it| (it + 1.) / 2.)))
+        .map(event)
+        .collect();
+
+    let mut height = line.height.clone();
+    let mut points = Vec::new();
+    sampler.breakpoints(&line.height, &mut points);
+    let speed_events: Vec<_> = sampler
+        .sample(points, |t| [value(&mut height, t, 0.)])
+        .into_iter()
+        .map(|(start_time, end_time, start, end)| PgrSpeedEvent {
+            start_time: to_units(start_time),
+            end_time: if end_time == END_TIME { END_TIME } else { to_units(end_time) },
+            value: if end_time == END_TIME {
+                0.
+            } else {
+                (end[0] - start[0]) / (end_time - start_time) * HEIGHT_RATIO
+            },
+            floor_position: start[0] * HEIGHT_RATIO,
+        })
+        .collect();
+    let speed_at = |t: f32| {
+        let t = to_units(t);
+        speed_events
+            .iter()
+            .rfind(|it| it.start_time <= t)
+            .map_or(1., |it| if it.value.abs() < EPS { 1. } else { it.value })
+    };
+
+    let mut notes_above = Vec::new();
+    let mut notes_below = Vec::new();
+    let (mut fake, mut offset, mut alpha, mut scale) = (0, 0, 0, 0);
+    for note in &line.notes {
+        if note.fake {
+            fake += 1;
+            continue;
+        }
+        if !note.object.translation.1.is_default() {
+            offset += 1;
+        }
+        if !note.object.alpha.is_default() {
+            alpha += 1;
+        }
+        if !note.object.scale.0.is_default() {
+            scale += 1;
+        }
+        let pgr = PgrNote {
+            kind: match note.kind {
+                NoteKind::Click => 1,
+                NoteKind::Drag => 2,
+                NoteKind::Hold { .. } => 3,
+                NoteKind::Flick => 4,
+            },
+            time: to_units(note.time),
+            position_x: note.object.translation.0.keyframes.first().map_or(0., |it| it.value) / (2. * 9. / 160.),
+            hold_time: match note.kind {
+                NoteKind::Hold { end_time, .. } => to_units(end_time - note.time),
+                _ => 0.,
+            },
+            speed: if matches!(note.kind, NoteKind::Hold { .. }) {
+                note.speed * speed_at(note.time)
+            } else {
+                note.speed
+            },
+            floor_position: note.height * HEIGHT_RATIO,
+        };
+        if note.above {
+            notes_above.push(pgr);
+        } else {
+            notes_below.push(pgr);
+        }
+    }
+    for (count, desc) in [
+        (fake, "fake notes are dropped"),
+        (offset, "note Y offsets are dropped"),
+        (alpha, "note alpha is dropped"),
+        (scale, "note sizes are dropped"),
+    ] {
+        if count != 0 {
+            warnings.push(format!("Judge line #{id}: {count} {desc}"));
+        }
+    }
+    notes_above.sort_by_key(|it| it.time.not_nan());
+    notes_below.sort_by_key(|it| it.time.not_nan());
+
+    PgrJudgeLine {
+        bpm: 60. / 32. / r,
+        alpha_events,
+        rotate_events,
+        move_events,
+        speed_events,
+
+        num_of_notes: notes_above.len() + notes_below.len(),
+        num_of_notes_above: notes_above.len(),
+        num_of_notes_below: notes_below.len(),
+        notes_above,
+        notes_below,
+    }
+}
+
+/// Bakes the chart into official PGR (formatVersion 3) JSON, returning it along with the features that had to be dropped
+pub fn export_pgr(chart: &Chart, options: &PgrExportOptions) -> Result<(String, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let bpm = options
+        .bpm
+        .unwrap_or_else(|| chart.bpm_list.borrow().elements().first().map_or(120., |it| it.2));
+    let r = 60. / bpm / 32.;
+    let sampler = Sampler {
+        sample_rate: options.sample_rate,
+        aspect_ratio: options.aspect_ratio,
+        max_time: max_time(chart),
+    };
+    if !chart.extra.effects.is_empty() || !chart.extra.global_effects.is_empty() {
+        warnings.push("Effects are dropped".to_owned());
+    }
+    if !chart.extra.videos.is_empty() {
+        warnings.push("Videos are dropped".to_owned());
+    }
+    let judge_line_list: Vec<_> = chart
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(id, line)| export_line(chart, line, id, &sampler, r, &mut warnings))
+        .collect();
+    let pgr = PgrChart {
+        format_version: 3,
+        offset: chart.offset,
+        num_of_notes: judge_line_list.iter().map(|it| it.num_of_notes).sum(),
+        judge_line_list,
+    };
+    Ok((serde_json::to_string(&pgr)?, warnings))
+}
@@ -0,0 +1,491 @@
+use super::sample::bpm_ranges;
+use crate::{
+    core::{
+        Anim, AnimFloat, BezierTween, BpmList, Chart, ChartSettings, ClampedTween, CtrlObject, JudgeLine, JudgeLineKind, Keyframe, Note, NoteKind,
+        StaticTween, Triple, TweenFunction, Tweenable, UIElement,
+    },
+    info::ChartInfo,
+    parse::{RPE_HEIGHT, RPE_SPEED_RATIO, RPE_TWEEN_MAP, RPE_WIDTH},
+};
+use anyhow::Result;
+use macroquad::prelude::Color;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How far (in seconds) the start time of an event may be from the one recorded in its [`EventLink`](crate::core::EventLink)
+const LINK_TOLERANCE: f32 = 1e-3;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEBpmItem {
+    bpm: f32,
+    start_time: Triple,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEEvent<T> {
+    bezier: u8,
+    bezier_points: [f32; 4],
+    easing_left: f32,
+    easing_right: f32,
+    easing_type: i32,
+    start: T,
+    end: T,
+    start_time: Triple,
+    end_time: Triple,
+    linkgroup: i32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPESpeedEvent {
+    start_time: Triple,
+    end_time: Triple,
+    start: f32,
+    end: f32,
+    linkgroup: i32,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEEventLayer {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alpha_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    move_x_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    move_y_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotate_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed_events: Option<Vec<RPESpeedEvent>>,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEExtendedEvents {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color_events: Option<Vec<RPEEvent<[u8; 3]>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text_events: Option<Vec<RPEEvent<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale_x_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scale_y_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    incline_events: Option<Vec<RPEEvent<f32>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    paint_events: Option<Vec<RPEEvent<f32>>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPENote {
+    #[serde(rename = "type")]
+    kind: u8,
+    above: u8,
+    start_time: Triple,
+    end_time: Triple,
+    position_x: f32,
+    y_offset: f32,
+    alpha: u16,
+    size: f32,
+    speed: f32,
+    is_fake: u8,
+    visible_time: f32,
+}
+
+#[derive(Serialize)]
+struct RPECtrlEvent {
+    easing: u8,
+    x: f32,
+    #[serde(flatten)]
+    value: BTreeMap<&'static str, f32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEJudgeLine {
+    #[serde(rename = "Group")]
+    group: i32,
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Texture")]
+    texture: String,
+    #[serde(rename = "father")]
+    parent: isize,
+    bpmfactor: f32,
+    event_layers: Vec<RPEEventLayer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extended: Option<RPEExtendedEvents>,
+    notes: Vec<RPENote>,
+    num_of_notes: usize,
+    is_cover: u8,
+    z_order: i32,
+    #[serde(rename = "attachUI", skip_serializing_if = "Option::is_none")]
+    attach_ui: Option<UIElement>,
+
+    pos_control: Vec<RPECtrlEvent>,
+    size_control: Vec<RPECtrlEvent>,
+    alpha_control: Vec<RPECtrlEvent>,
+    y_control: Vec<RPECtrlEvent>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEMetadata {
+    #[serde(rename = "RPEVersion")]
+    rpe_version: i32,
+    background: String,
+    charter: String,
+    composer: String,
+    id: String,
+    illustrator: String,
+    level: String,
+    name: String,
+    offset: i32,
+    song: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RPEChart<'a> {
+    #[serde(rename = "BPMList")]
+    bpm_list: Vec<RPEBpmItem>,
+    #[serde(rename = "META")]
+    meta: RPEMetadata,
+    judge_line_group: Vec<String>,
+    judge_line_list: Vec<RPEJudgeLine>,
+    prpr_settings: &'a ChartSettings,
+}
+
+struct Easing {
+    bezier: Option<[f32; 4]>,
+    easing_type: i32,
+    left: f32,
+    right: f32,
+}
+
+fn rpe_easing(tween: u8) -> i32 {
+    RPE_TWEEN_MAP.iter().position(|it| *it == tween).map_or(1, |it| it.max(1) as i32)
+}
+
+fn easing(tween: &dyn TweenFunction) -> Easing {
+    let any = tween.as_any();
+    let (bezier, easing_type, left, right) = if let Some(tween) = any.downcast_ref::<StaticTween>() {
+        (None, rpe_easing(tween.0), 0., 1.)
+    } else if let Some(tween) = any.downcast_ref::<ClampedTween>() {
+        (None, rpe_easing(tween.0), tween.1.start, tween.1.end)
+    } else if let Some(bezier) = any.downcast_ref::<BezierTween>() {
+        (Some([bezier.p1.0, bezier.p1.1, bezier.p2.0, bezier.p2.1]), 1, 0., 1.)
+    } else {
+        (None, 1, 0., 1.)
+    };
+    Easing {
+        bezier,
+        easing_type,
+        left,
+        right,
+    }
+}
+
+fn static_id(tween: &dyn TweenFunction) -> Option<u8> {
+    tween.as_any().downcast_ref::<StaticTween>().map(|it| it.0)
+}
+
+fn triple(r: &mut BpmList, time: f32) -> Triple {
+    Triple::from_beats(r.beat(time))
+}
+
+fn layers<T: Tweenable>(anim: &Anim<T>) -> Vec<&Anim<T>> {
+    let mut res = Vec::new();
+    let mut anim = Some(anim);
+    while let Some(now) = anim {
+        res.push(now);
+        anim = now.next.as_deref();
+    }
+    res
+}
+
+/// The link group of the event of `kind` in `layer` that starts at `time`, see [`EventLink`](crate::core::EventLink)
+fn link_group(line: &JudgeLine, kind: &str, layer: usize, time: f32) -> i32 {
+    line.links
+        .iter()
+        .find(|it| it.kind == kind && it.layer == layer && (it.time - time).abs() < LINK_TOLERANCE)
+        .map_or(0, |it| it.group)
+}
+
+fn events<T: Tweenable, V: Clone + PartialEq>(
+    r: &mut BpmList,
+    kfs: &[Keyframe<T>],
+    f: impl Fn(&T) -> V,
+    link: impl Fn(f32) -> i32,
+) -> Option<Vec<RPEEvent<V>>> {
+    if kfs.is_empty() {
+        return None;
+    }
+    let mut res: Vec<RPEEvent<V>> = Vec::new();
+    let mut last_end: Option<(f32, V)> = None;
+    for (i, kf) in kfs.iter().enumerate() {
+        let value = f(&kf.value);
+        let (end_time, start, end, ease) = match kfs.get(i + 1) {
+            Some(next) if next.time <= kf.time => continue,
+            Some(next) => match static_id(kf.tween.as_ref()) {
+                // RPE holds the last value between events, so plain holds don't need an event
+                Some(0) => {
+                    if last_end.as_ref() == Some(&(kf.time, value.clone())) {
+                        continue;
+                    }
+                    (next.time, value.clone(), value, easing(&StaticTween(2)))
+                }
+                Some(1) => {
+                    let value = f(&next.value);
+                    (next.time, value.clone(), value, easing(&StaticTween(2)))
+                }
+                _ => (next.time, value, f(&next.value), easing(kf.tween.as_ref())),
+            },
+            None => {
+                if last_end.as_ref() == Some(&(kf.time, value.clone())) {
+                    continue;
+                }
+                (kf.time, value.clone(), value, easing(&StaticTween(2)))
+            }
+        };
+        last_end = Some((end_time, end.clone()));
+        res.push(RPEEvent {
+            bezier: ease.bezier.is_some() as u8,
+            bezier_points: ease.bezier.unwrap_or_default(),
+            easing_left: ease.left,
+            easing_right: ease.right,
+            easing_type: ease.easing_type,
+            start,
+            end,
+            start_time: triple(r, kf.time),
+            end_time: triple(r, end_time),
+            linkgroup: link(kf.time),
+        });
+    }
+    Some(res)
+}
+
+fn float_events(r: &mut BpmList, anim: Option<&AnimFloat>, factor: f32, link: impl Fn(f32) -> i32) -> Option<Vec<RPEEvent<f32>>> {
+    anim.and_then(|anim| events(r, &anim.keyframes, |v| v * factor, link))
+}
+
+fn speed_events(r: &mut BpmList, line: &JudgeLine) -> Option<Vec<RPESpeedEvent>> {
+    let height = &line.height;
+    // the height is the integral of speed; recover speed at both ends of every segment
+    const DELTA: f32 = 1e-3;
+    let kfs = &height.keyframes;
+    if kfs.is_empty() {
+        return None;
+    }
+    let mut res = Vec::new();
+    for pair in kfs.windows(2) {
+        let (kf, next) = (&pair[0], &pair[1]);
+        if next.time <= kf.time {
+            continue;
+        }
+        let slope = (next.value - kf.value) / (next.time - kf.time);
+        let start = slope * (kf.tween.y(DELTA) - kf.tween.y(0.)) / DELTA;
+        let end = slope * (kf.tween.y(1.) - kf.tween.y(1. - DELTA)) / DELTA;
+        res.push(RPESpeedEvent {
+            start_time: triple(r, kf.time),
+            end_time: triple(r, next.time),
+            start: start / RPE_SPEED_RATIO,
+            end: end / RPE_SPEED_RATIO,
+            linkgroup: link_group(line, "speed", 0, kf.time),
+        });
+    }
+    if res.is_empty() {
+        res.push(RPESpeedEvent {
+            start_time: triple(r, kfs[0].time),
+            end_time: triple(r, kfs[0].time),
+            start: 0.,
+            end: 0.,
+            linkgroup: 0,
+        });
+    }
+    Some(res)
+}
+
+fn ctrl_events(anim: &AnimFloat, key: &'static str) -> Vec<RPECtrlEvent> {
+    let event = |easing: u8, x: f32, value: f32| RPECtrlEvent {
+        easing,
+        x,
+        value: BTreeMap::from([(key, value)]),
+    };
+    if anim.keyframes.is_empty() {
+        return vec![event(1, 0., 1.), event(1, 9999999., 1.)];
+    }
+    anim.keyframes
+        .iter()
+        .map(|kf| event(easing(kf.tween.as_ref()).easing_type as u8, kf.time, kf.value))
+        .collect()
+}
+
+fn first_value(anim: &AnimFloat) -> Option<f32> {
+    anim.keyframes.first().map(|it| it.value)
+}
+
+fn export_note(r: &mut BpmList, note: &Note) -> RPENote {
+    let (alpha, visible_time) = match &note.object.alpha.keyframes[..] {
+        [] => (1., 999999.),
+        [kf] => (kf.value, 999999.),
+        [.., kf] => (kf.value, note.time - kf.time),
+    };
+    let y_offset = first_value(&note.object.translation.1).unwrap_or_default();
+    RPENote {
+        kind: match note.kind {
+            NoteKind::Click => 1,
+            NoteKind::Hold { .. } => 2,
+            NoteKind::Flick => 3,
+            NoteKind::Drag => 4,
+        },
+        above: if note.above { 1 } else { 2 },
+        start_time: triple(r, note.time),
+        end_time: triple(
+            r,
+            match note.kind {
+                NoteKind::Hold { end_time, .. } => end_time,
+                _ => note.time,
+            },
+        ),
+        position_x: first_value(&note.object.translation.0).unwrap_or_default() * (RPE_WIDTH / 2.),
+        y_offset: if note.speed == 0. {
+            0.
+        } else {
+            y_offset / note.speed * (RPE_HEIGHT / 2.)
+        },
+        alpha: (alpha * 255.).round().clamp(0., 255.) as u16,
+        size: first_value(&note.object.scale.0).unwrap_or(1.),
+        speed: note.speed,
+        is_fake: note.fake as u8,
+        visible_time,
+    }
+}
+
+fn export_line(r: &mut BpmList, line: &JudgeLine, id: usize, group: usize) -> RPEJudgeLine {
+    let obj = &line.object;
+    let (alpha, rotate, move_x, move_y) = (layers(&obj.alpha), layers(&obj.rotation), layers(&obj.translation.0), layers(&obj.translation.1));
+    let count = alpha.len().max(rotate.len()).max(move_x.len()).max(move_y.len());
+    let mut event_layers: Vec<_> = (0..count)
+        .map(|i| RPEEventLayer {
+            alpha_events: float_events(r, alpha.get(i).copied(), 255., |t| link_group(line, "alpha", i, t)),
+            move_x_events: float_events(r, move_x.get(i).copied(), RPE_WIDTH / 2., |t| link_group(line, "move X", i, t)),
+            move_y_events: float_events(r, move_y.get(i).copied(), RPE_HEIGHT / 2., |t| link_group(line, "move Y", i, t)),
+            rotate_events: float_events(r, rotate.get(i).copied(), -1., |t| link_group(line, "rotate", i, t)),
+            speed_events: None,
+        })
+        .collect();
+    if event_layers.is_empty() {
+        event_layers.push(RPEEventLayer::default());
+    }
+    event_layers[0].speed_events = speed_events(r, line);
+
+    let (texture, text, paint) = match &line.kind {
+        JudgeLineKind::Normal => ("line.png".to_owned(), None, None),
+        JudgeLineKind::Texture(_, path) => (path.clone(), None, None),
+        JudgeLineKind::Text(anim) => ("line.png".to_owned(), events(r, &anim.keyframes, String::clone, |t| link_group(line, "text", 0, t)), None),
+        JudgeLineKind::Paint(anim, _) => ("line.png".to_owned(), None, events(r, &anim.keyframes, |v| *v, |t| link_group(line, "paint", 0, t))),
+    };
+    let (scale_x, scale_y) = if matches!(line.kind, JudgeLineKind::Texture(..)) {
+        (RPE_WIDTH / 2.57, RPE_WIDTH / 2.57)
+    } else if text.is_some() || line.attach_ui.is_some() {
+        (1., 1.)
+    } else {
+        (2., 1.)
+    };
+    let extended = RPEExtendedEvents {
+        color_events: events(
+            r,
+            &line.color.keyframes,
+            |c: &Color| {
+                let c: [u8; 4] = (*c).into();
+                [c[0], c[1], c[2]]
+            },
+            |t| link_group(line, "color", 0, t),
+        ),
+        text_events: text,
+        scale_x_events: float_events(r, Some(&obj.scale.0), scale_x, |t| link_group(line, "scale X", 0, t)),
+        scale_y_events: float_events(r, Some(&obj.scale.1), scale_y, |t| link_group(line, "scale Y", 0, t)),
+        incline_events: float_events(r, Some(&line.incline), 1., |t| link_group(line, "incline", 0, t)),
+        paint_events: paint,
+    };
+    let has_extended = extended.color_events.is_some()
+        || extended.text_events.is_some()
+        || extended.scale_x_events.is_some()
+        || extended.scale_y_events.is_some()
+        || extended.incline_events.is_some()
+        || extended.paint_events.is_some();
+
+    let ctrl: &CtrlObject = &line.ctrl_obj.borrow();
+    let notes: Vec<_> = line.notes.iter().map(|note| export_note(r, note)).collect();
+    RPEJudgeLine {
+        group: group as i32,
+        name: format!("Line #{id}"),
+        texture,
+        parent: line.parent.map_or(-1, |it| it as isize),
+        bpmfactor: 1.,
+        event_layers,
+        extended: if has_extended { Some(extended) } else { None },
+        num_of_notes: line.notes.iter().filter(|it| !it.fake).count(),
+        notes,
+        is_cover: !line.show_below as u8,
+        z_order: line.z_index,
+        attach_ui: line.attach_ui,
+
+        pos_control: ctrl_events(&ctrl.pos, "pos"),
+        size_control: ctrl_events(&ctrl.size, "size"),
+        alpha_control: ctrl_events(&ctrl.alpha, "alpha"),
+        y_control: ctrl_events(&ctrl.y, "y"),
+    }
+}
+
+pub fn export_rpe(chart: &Chart, info: &ChartInfo) -> Result<String> {
+    let ranges = bpm_ranges(chart);
+    let mut r = BpmList::new(ranges.clone());
+    let mut groups = vec!["Default".to_owned()];
+    let line_groups: Vec<_> = chart
+        .lines
+        .iter()
+        .map(|line| match &line.group {
+            Some(group) => groups.iter().position(|it| it == group).unwrap_or_else(|| {
+                groups.push(group.clone());
+                groups.len() - 1
+            }),
+            None => 0,
+        })
+        .collect();
+    let rpe = RPEChart {
+        bpm_list: ranges
+            .into_iter()
+            .map(|(beats, bpm)| RPEBpmItem {
+                bpm,
+                start_time: Triple::from_beats(beats),
+            })
+            .collect(),
+        meta: RPEMetadata {
+            rpe_version: 100,
+            background: info.illustration.clone(),
+            charter: info.charter.clone(),
+            composer: info.composer.clone(),
+            id: info.id.clone().unwrap_or_default(),
+            illustrator: info.illustrator.clone(),
+            level: info.level.clone(),
+            name: info.name.clone(),
+            offset: (chart.offset * 1000.).round() as i32,
+            song: info.music.clone(),
+        },
+        judge_line_group: groups,
+        judge_line_list: chart
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(id, line)| export_line(&mut r, line, id, line_groups[id]))
+            .collect(),
+        prpr_settings: &chart.settings,
+    };
+    Ok(serde_json::to_string(&rpe)?)
+}
@@ -0,0 +1,144 @@
+//! Sampling shared by exporters, which bake animations their formats can't express into piecewise linear events.
+
+use crate::{
+    core::{AnimFloat, Chart, JudgeLine, NoteKind, StaticTween, Vector},
+    ext::NotNanExt,
+};
+use nalgebra::Rotation2;
+
+pub(super) const END_TIME: f32 = 999999999.;
+pub(super) const EPS: f32 = 1e-4;
+
+/// The BPM changes as (beats, BPM). A chart without any (like a PEC one with no `bp` line) gets 60 BPM, so that beats equal seconds.
+pub(super) fn bpm_ranges(chart: &Chart) -> Vec<(f32, f32)> {
+    let bpm_list = chart.bpm_list.borrow();
+    if bpm_list.elements().is_empty() {
+        vec![(0., 60.)]
+    } else {
+        bpm_list.elements().iter().map(|(beats, _, bpm)| (*beats, *bpm)).collect()
+    }
+}
+
+pub(super) struct Sampler {
+    pub sample_rate: f32,
+    /// Used to bake parent line transforms
+    pub aspect_ratio: f32,
+    pub max_time: f32,
+}
+
+impl Sampler {
+    pub fn grid(&self, from: f32, to: f32, out: &mut Vec<f32>) {
+        let step = 1. / self.sample_rate;
+        let mut t = (from / step).ceil() * step;
+        while t < to.min(self.max_time) {
+            out.push(t);
+            t += step;
+        }
+    }
+
+    /// Collects the times at which the animation stops being linear
+    pub fn breakpoints(&self, anim: &AnimFloat, out: &mut Vec<f32>) {
+        let mut anim = Some(anim);
+        while let Some(now) = anim {
+            for (i, kf) in now.keyframes.iter().enumerate() {
+                out.push(kf.time);
+                if let Some(next) = now.keyframes.get(i + 1) {
+                    let linear = kf.tween.as_any().downcast_ref::<StaticTween>().map_or(false, |it| it.0 <= 2);
+                    if !linear {
+                        self.grid(kf.time, next.time, out);
+                    }
+                }
+            }
+            anim = now.next.as_deref();
+        }
+    }
+
+    /// Samples `f` into piecewise linear segments, returning `(start time, end time, start values, end values)`
+    pub fn sample<const N: usize>(&self, mut points: Vec<f32>, mut f: impl FnMut(f32) -> [f32; N]) -> Vec<(f32, f32, [f32; N], [f32; N])> {
+        points.push(0.);
+        points.retain(|it| *it >= 0. && *it <= self.max_time);
+        points.sort_by_key(|it| it.not_nan());
+        points.dedup_by(|a, b| (*a - *b).abs() < EPS);
+        let mut res: Vec<(f32, f32, [f32; N], [f32; N])> = Vec::new();
+        for (i, &time) in points.iter().enumerate() {
+            let start = f(time);
+            let (end_time, end) = match points.get(i + 1) {
+                Some(&next) => (next, f(next - EPS)),
+                None => (END_TIME, start),
+            };
+            if let Some(last) = res.last_mut() {
+                // merge collinear segments
+                let collinear = (0..N).all(|k| {
+                    let slope = (last.3[k] - last.2[k]) / (last.1 - last.0);
+                    (last.3[k] - start[k]).abs() < EPS && (last.3[k] + slope * (end_time - time) - end[k]).abs() < EPS
+                });
+                if collinear && end_time != END_TIME {
+                    last.1 = end_time;
+                    last.3 = end;
+                    continue;
+                }
+            }
+            res.push((time, end_time, start, end));
+        }
+        res
+    }
+}
+
+pub(super) fn value(anim: &mut AnimFloat, time: f32, default: f32) -> f32 {
+    anim.set_time(time);
+    anim.now_opt().unwrap_or(default)
+}
+
+/// The time after which nothing changes in the chart
+pub(super) fn max_time(chart: &Chart) -> f32 {
+    *chart
+        .lines
+        .iter()
+        .flat_map(|line| {
+            let obj = &line.object;
+            [&obj.alpha, &obj.rotation, &obj.translation.0, &obj.translation.1, &line.height]
+                .into_iter()
+                .flat_map(|it| it.keyframes.last().map(|it| it.time.not_nan()))
+                .chain(line.notes.iter().map(|note| match note.kind {
+                    NoteKind::Hold { end_time, .. } => end_time.not_nan(),
+                    _ => note.time.not_nan(),
+                }))
+        })
+        .max()
+        .unwrap_or_default()
+        + 1.
+}
+
+/// Samples the position of the line in chart coordinates, with the transform of its parent baked in.
+pub(super) fn line_positions(chart: &Chart, line: &JudgeLine, sampler: &Sampler) -> Vec<(f32, f32, [f32; 2], [f32; 2])> {
+    let obj = &line.object;
+    let (mut x, mut y) = (obj.translation.0.clone(), obj.translation.1.clone());
+    let mut points = Vec::new();
+    sampler.breakpoints(&obj.translation.0, &mut points);
+    sampler.breakpoints(&obj.translation.1, &mut points);
+    let mut parent = line.parent.map(|parent| {
+        let po = &chart.lines[parent].object;
+        sampler.breakpoints(&po.translation.0, &mut points);
+        sampler.breakpoints(&po.translation.1, &mut points);
+        sampler.breakpoints(&po.rotation, &mut points);
+        // rotating the child's translation is never linear
+        for pair in po.rotation.keyframes.windows(2) {
+            if pair[0].value != pair[1].value {
+                sampler.grid(pair[0].time, pair[1].time, &mut points);
+            }
+        }
+        (po.translation.0.clone(), po.translation.1.clone(), po.rotation.clone())
+    });
+    let ar = sampler.aspect_ratio;
+    sampler.sample(points, |t| {
+        let mut pos = Vector::new(value(&mut x, t, 0.), value(&mut y, t, 0.));
+        if let Some((px, py, rot)) = &mut parent {
+            // mirrors `JudgeLine::now_transform`, which works in screen space
+            pos.y /= ar;
+            let mut tr = Rotation2::new(value(rot, t, 0.).to_radians()) * pos;
+            tr += Vector::new(value(px, t, 0.), value(py, t, 0.) / ar);
+            pos = Vector::new(tr.x, tr.y * ar);
+        }
+        [pos.x, pos.y]
+    })
+}
@@ -7,7 +7,7 @@ use anyhow::Result;
 use image::DynamicImage;
 use macroquad::prelude::*;
 use miniquad::{BlendFactor, BlendState, BlendValue, CompareFunc, Equation, PrimitiveType, StencilFaceState, StencilOp, StencilState};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use ordered_float::{Float, NotNan};
 use sasa::AudioManager;
 use serde::Deserialize;
@@ -59,19 +59,25 @@ impl RectExt for Rect {
     }
 }
 
-struct SafeTextureInner(Texture2D);
+struct SafeTextureInner(OnceCell<Texture2D>, Mutex<Option<DynamicImage>>);
 impl Drop for SafeTextureInner {
     fn drop(&mut self) {
-        self.0.delete()
+        if let Some(tex) = self.0.get() {
+            tex.delete();
+        }
     }
 }
 
 pub struct SafeTexture(Arc<SafeTextureInner>);
 impl SafeTexture {
+    /// Delays uploading to the GPU until the texture is first used, so charts can be parsed without a window.
+    pub fn lazy(image: DynamicImage) -> Self {
+        Self(Arc::new(SafeTextureInner(OnceCell::new(), Mutex::new(Some(image)))))
+    }
+
     pub fn into_inner(self) -> Texture2D {
-        let arc = self.0;
-        let res = arc.0;
-        std::mem::forget(arc);
+        let res = *self;
+        std::mem::forget(self.0);
         res
     }
 }
@@ -86,7 +92,10 @@ impl Deref for SafeTexture {
     type Target = Texture2D;
 
     fn deref(&self) -> &Self::Target {
-        &self.0.as_ref().0
+        self.0 .0.get_or_init(|| {
+            let image = self.0 .1.lock().unwrap().take().unwrap();
+            Texture2D::from_rgba8(image.width() as _, image.height() as _, &image.into_rgba8())
+        })
     }
 }
 
@@ -99,7 +108,7 @@ impl Eq for SafeTexture {}
 
 impl From<Texture2D> for SafeTexture {
     fn from(tex: Texture2D) -> Self {
-        Self(Arc::new(SafeTextureInner(tex)))
+        Self(Arc::new(SafeTextureInner(OnceCell::with_value(tex), Mutex::default())))
     }
 }
 
@@ -1,4 +1,8 @@
-use crate::{ext::spawn_task, info::ChartInfo};
+use crate::{
+    ext::spawn_task,
+    info::{infer_difficulty, ChartInfo, DifficultyInfo},
+    parse::{chart_fingerprint, Fingerprint},
+};
 use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use chardetng::EncodingDetector;
@@ -9,12 +13,13 @@ use serde::Deserialize;
 use serde_json::Value;
 use std::{
     any::Any,
-    collections::HashMap,
-    fs,
-    io::{Cursor, Read, Seek, Write},
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{BufReader, Cursor, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
+use tempfile::NamedTempFile;
 use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
 pub fn update_zip<R: Read + Seek>(zip: &mut ZipArchive<R>, patches: HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
@@ -53,6 +58,44 @@ pub trait FileSystem: Send {
     fn list_root(&self) -> Result<Vec<String>>;
     fn clone_box(&self) -> Box<dyn FileSystem>;
     fn as_any(&mut self) -> &mut dyn Any;
+
+    /// Opens the file for reading. File systems backed by disk stream it instead of loading it into memory at once.
+    async fn open_file(&mut self, path: &str) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Cursor::new(self.load_file(path).await?)))
+    }
+
+    /// Copies the file to a temporary file, for consumers that need a path (like ffmpeg)
+    async fn extract_file(&mut self, path: &str) -> Result<NamedTempFile> {
+        let mut reader = self.open_file(path).await?;
+        let mut file = NamedTempFile::new()?;
+        std::io::copy(&mut reader, &mut file)?;
+        Ok(file)
+    }
+
+    /// Writes the files back to where this file system reads from, replacing the existing ones. Either all of them are written or none:
+    /// if writing fails midway, the files already written are restored.
+    async fn write_files(&mut self, _files: &HashMap<String, Vec<u8>>) -> Result<()> {
+        bail!("This file system is read-only")
+    }
+}
+
+/// Writes `data` next to `path` and moves it in place, so that a failure never leaves a half-written file behind
+fn write_temp(path: &Path, data: &[u8]) -> Result<NamedTempFile> {
+    let dir = path.parent().filter(|it| !it.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut file = NamedTempFile::new_in(dir).with_context(|| format!("Failed to create temporary file in {}", dir.display()))?;
+    file.write_all(data)?;
+    Ok(file)
+}
+
+fn persist(file: NamedTempFile, path: &Path) -> Result<()> {
+    file.persist(path)
+        .map_err(|it| it.error)
+        .with_context(|| format!("Failed to write to {}", path.display()))?;
+    Ok(())
+}
+
+fn with_root(root: &str, files: &HashMap<String, Vec<u8>>) -> HashMap<String, Vec<u8>> {
+    files.iter().map(|(path, data)| (concat_string!(root, path), data.clone())).collect()
 }
 
 #[derive(Clone)]
@@ -103,6 +146,44 @@ impl FileSystem for ExternalFileSystem {
         Ok(self.0.join(path).exists())
     }
 
+    async fn open_file(&mut self, path: &str) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(BufReader::new(File::open(self.0.join(path))?)))
+    }
+
+    async fn write_files(&mut self, files: &HashMap<String, Vec<u8>>) -> Result<()> {
+        // everything that can fail before replacing a file is done first, including reading the original content to restore it
+        let mut temps = Vec::new();
+        for (path, data) in files {
+            let path = self.0.join(path);
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let backup = if path.exists() {
+                Some(fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?)
+            } else {
+                None
+            };
+            temps.push((write_temp(&path, data)?, path, backup));
+        }
+        let mut written: Vec<(PathBuf, Option<Vec<u8>>)> = Vec::new();
+        for (file, path, backup) in temps {
+            if let Err(err) = persist(file, &path) {
+                for (path, backup) in written {
+                    let restored = match backup {
+                        Some(data) => write_temp(&path, &data).and_then(|it| persist(it, &path)),
+                        None => fs::remove_file(&path).map_err(Into::into),
+                    };
+                    if let Err(err) = restored {
+                        warn!("Failed to restore {}: {:?}", path.display(), err);
+                    }
+                }
+                return Err(err);
+            }
+            written.push((path, backup));
+        }
+        Ok(())
+    }
+
     fn list_root(&self) -> Result<Vec<String>> {
         Ok(std::fs::read_dir(&self.0)?
             .filter_map(|res| res.ok()?.file_name().into_string().ok())
@@ -121,14 +202,22 @@ impl FileSystem for ExternalFileSystem {
 #[derive(Clone)]
 pub struct ZipFileSystem(pub Arc<Mutex<ZipArchive<Cursor<Vec<u8>>>>>, String);
 
+/// The folder all the files are in, if the archive has a single one at its root
+fn zip_root<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let root_dirs = names
+        .filter(|it| it.ends_with('/') && it.find('/') == Some(it.len() - 1))
+        .collect::<Vec<_>>();
+    if root_dirs.len() == 1 {
+        root_dirs[0].to_owned()
+    } else {
+        String::new()
+    }
+}
+
 impl ZipFileSystem {
     pub fn new(bytes: Vec<u8>) -> Result<Self> {
         let zip = ZipArchive::new(Cursor::new(bytes))?;
-        let root_dirs = zip
-            .file_names()
-            .filter(|it| it.ends_with('/') && it.find('/') == Some(it.len() - 1))
-            .collect::<Vec<_>>();
-        let root = if root_dirs.len() == 1 { root_dirs[0].to_owned() } else { String::new() };
+        let root = zip_root(zip.file_names());
         Ok(Self(Arc::new(Mutex::new(zip)), root))
     }
 }
@@ -172,8 +261,181 @@ impl FileSystem for ZipFileSystem {
     }
 }
 
+type DiskArchive = ZipArchive<BufReader<File>>;
+
+/// What is known about the archive file. Shared by all the clones and replaced when the archive is rewritten.
+struct DiskZipState {
+    names: HashSet<String>,
+    root: String,
+    /// Handles to the archive not in use. Each read takes one (or opens a new one), so that reads do not wait for each other.
+    idle: Vec<DiskArchive>,
+    /// Increased when the archive is rewritten, so that handles to the old file are not put back
+    version: usize,
+}
+
+impl DiskZipState {
+    fn new(zip: DiskArchive, version: usize) -> Self {
+        let names: HashSet<_> = zip.file_names().map(str::to_owned).collect();
+        let root = zip_root(names.iter().map(String::as_str));
+        Self {
+            names,
+            root,
+            idle: vec![zip],
+            version,
+        }
+    }
+}
+
+struct DiskZip {
+    path: PathBuf,
+    state: Mutex<DiskZipState>,
+}
+
+/// A zip archive on disk, whose entries are read on demand instead of loading the whole archive into memory.
+#[derive(Clone)]
+pub struct DiskZipFileSystem(Arc<DiskZip>);
+
+impl DiskZipFileSystem {
+    pub fn open(path: &Path) -> Result<Self> {
+        let zip = ZipArchive::new(BufReader::new(File::open(path)?))?;
+        Ok(Self(Arc::new(DiskZip {
+            path: path.to_owned(),
+            state: Mutex::new(DiskZipState::new(zip, 0)),
+        })))
+    }
+
+    fn path_of(&self, path: &str) -> String {
+        concat_string!(self.0.state.lock().unwrap().root, path)
+    }
+
+    fn take_archive(&self) -> Result<(DiskArchive, usize)> {
+        let (idle, version) = {
+            let mut state = self.0.state.lock().unwrap();
+            (state.idle.pop(), state.version)
+        };
+        Ok(match idle {
+            Some(zip) => (zip, version),
+            None => (ZipArchive::new(BufReader::new(File::open(&self.0.path)?))?, version),
+        })
+    }
+
+    fn put_archive(&self, zip: DiskArchive, version: usize) {
+        let mut state = self.0.state.lock().unwrap();
+        if state.version == version {
+            state.idle.push(zip);
+        }
+    }
+
+    fn with_archive<R>(&self, f: impl FnOnce(&mut DiskArchive) -> Result<R>) -> Result<R> {
+        let (mut zip, version) = self.take_archive()?;
+        let res = f(&mut zip);
+        self.put_archive(zip, version);
+        res
+    }
+
+    /// Builds the archive with the given files replaced, see [`update_zip`].
+    pub fn update(&self, patches: &HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+        let root = self.0.state.lock().unwrap().root.clone();
+        self.with_archive(|zip| update_zip(zip, with_root(&root, patches)))
+    }
+}
+
+#[async_trait]
+impl FileSystem for DiskZipFileSystem {
+    async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
+        let zip = self.clone();
+        let path = self.path_of(path);
+        spawn_task(async move {
+            zip.with_archive(|zip| {
+                let mut entry = zip.by_name(&path)?;
+                let mut res = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut res)?;
+                Ok(res)
+            })
+        })
+        .await?
+    }
+
+    async fn exists(&mut self, path: &str) -> Result<bool> {
+        let state = self.0.state.lock().unwrap();
+        Ok(state.names.contains(&concat_string!(state.root, path)))
+    }
+
+    fn list_root(&self) -> Result<Vec<String>> {
+        let state = self.0.state.lock().unwrap();
+        Ok(state
+            .names
+            .iter()
+            .filter_map(|it| it.strip_prefix(&state.root))
+            .filter(|it| !it.is_empty() && !it.contains('/'))
+            .map(str::to_owned)
+            .collect())
+    }
+
+    fn clone_box(&self) -> Box<dyn FileSystem> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    async fn open_file(&mut self, path: &str) -> Result<Box<dyn Read + Send>> {
+        let zip = self.clone();
+        let path = self.path_of(path);
+        spawn_task(async move {
+            let (mut archive, version) = zip.take_archive()?;
+            let mut entry = archive.by_name(&path)?;
+            if entry.compression() == CompressionMethod::Stored {
+                // read right from the archive file, whose handle now belongs to the reader and is not put back
+                let (start, size) = (entry.data_start(), entry.size());
+                drop(entry);
+                let mut file = archive.into_inner().into_inner();
+                file.seek(SeekFrom::Start(start))?;
+                Ok(Box::new(BufReader::new(file).take(size)) as Box<dyn Read + Send>)
+            } else {
+                let mut file = tempfile::tempfile()?;
+                std::io::copy(&mut entry, &mut file)?;
+                drop(entry);
+                zip.put_archive(archive, version);
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+        })
+        .await?
+    }
+
+    async fn write_files(&mut self, files: &HashMap<String, Vec<u8>>) -> Result<()> {
+        let path = &self.0.path;
+        let file = write_temp(path, &self.update(files)?)?;
+        let mut state = self.0.state.lock().unwrap();
+        // open handles keep the archive from being replaced on Windows
+        state.idle.clear();
+        persist(file, path)?;
+        // every clone sees the new archive, and reads still holding a handle to the old one do not put it back
+        *state = DiskZipState::new(ZipArchive::new(BufReader::new(File::open(path)?))?, state.version + 1);
+        Ok(())
+    }
+}
+
+/// Overlays files on another file system. The files only live in memory until [`PatchedFileSystem::commit`] is called.
 pub struct PatchedFileSystem(pub Box<dyn FileSystem>, pub HashMap<String, Vec<u8>>);
 
+impl Clone for PatchedFileSystem {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_box(), self.1.clone())
+    }
+}
+
+impl PatchedFileSystem {
+    /// Writes the patches to the underlying file system
+    pub async fn commit(&mut self) -> Result<()> {
+        self.0.write_files(&self.1).await?;
+        self.1.clear();
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl FileSystem for PatchedFileSystem {
     async fn load_file(&mut self, path: &str) -> Result<Vec<u8>> {
@@ -191,31 +453,40 @@ impl FileSystem for PatchedFileSystem {
     fn list_root(&self) -> Result<Vec<String>> {
         let mut res = self.0.list_root()?;
         res.extend(self.1.keys().cloned());
+        res.sort();
         res.dedup();
         Ok(res)
     }
 
     fn clone_box(&self) -> Box<dyn FileSystem> {
-        unimplemented!()
+        Box::new(self.clone())
     }
 
     fn as_any(&mut self) -> &mut dyn Any {
         self
     }
+
+    async fn open_file(&mut self, path: &str) -> Result<Box<dyn Read + Send>> {
+        if let Some(data) = self.1.get(path) {
+            Ok(Box::new(Cursor::new(data.clone())))
+        } else {
+            self.0.open_file(path).await
+        }
+    }
+
+    async fn write_files(&mut self, files: &HashMap<String, Vec<u8>>) -> Result<()> {
+        self.0.write_files(files).await?;
+        // the written files are no longer shadowed by older patches
+        for path in files.keys() {
+            self.1.remove(path);
+        }
+        Ok(())
+    }
 }
 
 fn infer_diff(info: &mut ChartInfo, level: &str) {
-    if let Ok(val) = level
-        .chars()
-        .rev()
-        .take_while(|it| it.is_ascii_digit())
-        .collect::<String>()
-        .chars()
-        .rev()
-        .collect::<String>()
-        .parse::<u32>()
-    {
-        info.difficulty = val as f32;
+    if let Some(val) = infer_difficulty(level) {
+        info.difficulty = val;
     }
 }
 
@@ -294,16 +565,31 @@ fn info_from_csv(text: &str) -> Result<ChartInfo> {
     )
 }
 
+#[derive(Deserialize)]
+struct RPEMeta {
+    name: String,
+    level: String,
+    background: String,
+    charter: String,
+    composer: Option<String>,
+    illustrator: Option<String>,
+    song: String,
+}
+
+async fn load_rpe_meta(fs: &mut dyn FileSystem, chart: &str) -> Result<Option<RPEMeta>> {
+    let Ok(s) = String::from_utf8(fs.load_file(chart).await?) else {
+        return Ok(None);
+    };
+    let Ok(mut value) = serde_json::from_str::<Value>(&s) else {
+        return Ok(None);
+    };
+    Ok(serde_json::from_value(value["META"].take()).ok())
+}
+
 pub async fn fix_info(fs: &mut dyn FileSystem, info: &mut ChartInfo) -> Result<()> {
-    enum FileStatus {
-        None,
-        One(String),
-        Multiple,
-    }
     async fn get(fs: &mut dyn FileSystem, path: &mut String) -> Result<Option<String>> {
         Ok(if fs.exists(path).await? { Some(std::mem::take(path)) } else { None })
     }
-    let mut chart = get(fs, &mut info.chart).await?;
     let mut music = get(fs, &mut info.music).await?;
     let mut illustration = get(fs, &mut info.illustration).await?;
     fn put(desc: &str, status: &mut Option<String>, value: String) {
@@ -316,52 +602,107 @@ pub async fn fix_info(fs: &mut dyn FileSystem, info: &mut ChartInfo) -> Result<(
             *status = Some(value);
         }
     }
+    fn stem(file: &str) -> &str {
+        file.rsplit_once('.').map_or(file, |it| it.0)
+    }
+    // listed difficulties are kept as they are, as long as their charts exist
+    let listed = !info.difficulties.is_empty();
+    let selected = info.chart.clone();
+    info.sync_difficulty();
+    if listed {
+        // fills in what the difficulties leave out
+        info.init_difficulties();
+    }
+    let mut difficulties = Vec::new();
+    for diff in std::mem::take(&mut info.difficulties) {
+        if fs.exists(diff.chart()).await? {
+            difficulties.push(diff);
+        } else {
+            warn!("Chart {} of difficulty {} not found, removing it", diff.chart(), diff.level());
+        }
+    }
+    // otherwise the chart given in the info comes first
+    let single = if listed { None } else { get(fs, &mut info.chart).await? };
+    let mut others = Vec::new();
     for file in fs.list_root().context("Cannot list files")? {
         if let Some((_, ext)) = file.rsplit_once('.') {
-            match ext.to_ascii_lowercase().as_str() {
-                "json" | "pec" => {
-                    put("charts", &mut chart, file);
-                }
-                _ => {}
+            if matches!(ext.to_ascii_lowercase().as_str(), "json" | "pec")
+                && file != "extra.json"
+                && single.as_ref() != Some(&file)
+                && !difficulties.iter().any(|it| it.chart() == file)
+            {
+                others.push(file);
             }
         }
     }
-    if let Some(chart) = &chart {
-        info.chart = chart.to_owned();
-        if let Ok(s) = String::from_utf8(fs.load_file(&info.chart).await?) {
-            if let Ok(mut value) = serde_json::from_str::<Value>(&s) {
-                #[derive(Deserialize)]
-                struct RPEMeta {
-                    name: String,
-                    level: String,
-                    background: String,
-                    charter: String,
-                    composer: Option<String>,
-                    illustrator: Option<String>,
-                    song: String,
+    others.sort();
+    let mut charts: Vec<String> = single.into_iter().collect();
+    for file in others {
+        // like `chart.pec` and the `chart.json` converted from it
+        if charts
+            .iter()
+            .map(String::as_str)
+            .chain(difficulties.iter().map(DifficultyInfo::chart))
+            .any(|it| stem(it) == stem(&file))
+        {
+            warn!("Found multiple charts named {}, using the first one", stem(&file));
+            continue;
+        }
+        charts.push(file);
+    }
+    if charts.is_empty() && difficulties.is_empty() {
+        bail!("Cannot find chart");
+    }
+    let first = difficulties.len();
+    for chart in charts {
+        let mut diff = DifficultyInfo {
+            level: Some(stem(&chart).to_uppercase()),
+            chart: Some(chart),
+            ..Default::default()
+        };
+        if let Some(mut meta) = load_rpe_meta(fs, diff.chart()).await? {
+            if difficulties.len() == first && !listed {
+                info.name = meta.name;
+                if let Some(val) = meta.composer {
+                    info.composer = val;
                 }
-                if let Ok(mut meta) = serde_json::from_value::<RPEMeta>(value["META"].take()) {
-                    info.name = meta.name;
-                    infer_diff(info, &meta.level);
-                    info.level = meta.level;
-                    info.charter = meta.charter;
-                    if let Some(val) = meta.composer {
-                        info.composer = val;
-                    }
-                    if let Some(val) = meta.illustrator {
-                        info.illustrator = val;
-                    }
-                    if illustration.is_none() {
-                        illustration = get(fs, &mut meta.background).await?;
-                    }
-                    if music.is_none() {
-                        music = get(fs, &mut meta.song).await?;
-                    }
+                if let Some(val) = meta.illustrator {
+                    info.illustrator = val;
+                }
+                if illustration.is_none() {
+                    illustration = get(fs, &mut meta.background).await?;
+                }
+                if music.is_none() {
+                    music = get(fs, &mut meta.song).await?;
                 }
             }
+            diff.level = Some(meta.level);
+            diff.charter = Some(meta.charter);
+        }
+        difficulties.push(diff);
+    }
+    if !listed && difficulties.len() == 1 {
+        // a single chart needs no difficulty list
+        let diff = difficulties.pop().unwrap();
+        info.chart = diff.chart().to_owned();
+        // only RPE charts tell their level and charter
+        if let Some(charter) = &diff.charter {
+            infer_diff(info, diff.level());
+            info.level = diff.level().to_owned();
+            info.charter = charter.clone();
         }
     } else {
-        bail!("Cannot find chart");
+        if !listed {
+            // the info describes the chart given in it, which becomes the first difficulty
+            if let Some(diff) = difficulties.first_mut().filter(|it| it.chart() == selected && it.charter.is_none()) {
+                diff.level = Some(info.level.clone());
+            }
+        }
+        info.difficulties = difficulties;
+        info.init_difficulties();
+        if let Some(index) = info.difficulties.iter().position(|it| it.chart() == selected) {
+            info.select_difficulty(index);
+        }
     }
     for file in fs.list_root().context("Cannot list files")? {
         if let Some((_, ext)) = file.rsplit_once('.') {
@@ -394,7 +735,7 @@ fn bytes_to_text_auto(data: &[u8]) -> String {
 }
 
 pub async fn load_info(fs: &mut dyn FileSystem) -> Result<ChartInfo> {
-    let info = if let Ok(bytes) = fs.load_file("info.yml").await {
+    let mut info: ChartInfo = if let Ok(bytes) = fs.load_file("info.yml").await {
         serde_yaml::from_str(&bytes_to_text_auto(&bytes))?
     } else if let Ok(bytes) = fs.load_file("info.txt").await {
         info_from_txt(&bytes_to_text_auto(&bytes))?
@@ -404,16 +745,24 @@ pub async fn load_info(fs: &mut dyn FileSystem) -> Result<ChartInfo> {
         warn!("None of info.yml, info.txt and info.csv is found, inferring");
         let mut info = ChartInfo::default();
         fix_info(fs, &mut info).await?;
-        info
+        return Ok(info);
     };
+    if !info.difficulties.is_empty() {
+        info.init_difficulties();
+    }
     Ok(info)
 }
 
+/// Loads the chart and computes its [`Fingerprint`], which identifies it regardless of how it is packed
+pub async fn load_fingerprint(fs: &mut dyn FileSystem) -> Result<Fingerprint> {
+    let (info, chart) = crate::simulate::load_chart(fs).await?;
+    Ok(chart_fingerprint(&chart, &info))
+}
+
 pub fn fs_from_file(path: &Path) -> Result<Box<dyn FileSystem>> {
     let meta = fs::metadata(path)?;
     Ok(if meta.is_file() {
-        let bytes = fs::read(path).with_context(|| format!("Failed to read from {}", path.display()))?;
-        Box::new(ZipFileSystem::new(bytes).with_context(|| format!("Cannot open {} as zip archive", path.display()))?)
+        Box::new(DiskZipFileSystem::open(path).with_context(|| format!("Cannot open {} as zip archive", path.display()))?)
     } else {
         Box::new(ExternalFileSystem(fs::canonicalize(path)?))
     })
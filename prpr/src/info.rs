@@ -9,6 +9,31 @@ pub enum ChartFormat {
     Pgr,
 }
 
+/// One difficulty of a package. Fields left out are taken from the top level of the info when it is loaded.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(rename_all = "camelCase")]
+pub struct DifficultyInfo {
+    pub chart: Option<String>,
+    pub format: Option<ChartFormat>,
+    pub level: Option<String>,
+    pub difficulty: Option<f32>,
+    pub charter: Option<String>,
+    pub offset: Option<f32>,
+}
+
+impl DifficultyInfo {
+    /// The chart file, empty until [`ChartInfo::init_difficulties`] fills it in if left out
+    pub fn chart(&self) -> &str {
+        self.chart.as_deref().unwrap_or_default()
+    }
+
+    /// The level, empty until [`ChartInfo::init_difficulties`] fills it in if left out
+    pub fn level(&self) -> &str {
+        self.level.as_deref().unwrap_or_default()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(default)]
 #[serde(rename_all = "camelCase")]
@@ -38,6 +63,12 @@ pub struct ChartInfo {
     pub tags: Vec<String>,
 
     pub hold_partial_cover: bool,
+
+    /// Difficulties of a package that ships several charts. The top-level chart, format, level, difficulty, charter and offset are
+    /// those of the selected one.
+    pub difficulties: Vec<DifficultyInfo>,
+    #[serde(skip)]
+    pub difficulty_index: usize,
 }
 
 impl Default for ChartInfo {
@@ -68,6 +99,79 @@ impl Default for ChartInfo {
             tags: Vec::new(),
 
             hold_partial_cover: false,
+
+            difficulties: Vec::new(),
+            difficulty_index: 0,
+        }
+    }
+}
+
+impl ChartInfo {
+    /// Fills the fields the difficulties leave out with the top-level ones, then selects the first difficulty.
+    pub fn init_difficulties(&mut self) {
+        for diff in &mut self.difficulties {
+            diff.chart.get_or_insert_with(|| self.chart.clone());
+            let level = diff.level.get_or_insert_with(|| self.level.clone());
+            diff.difficulty.get_or_insert(infer_difficulty(level).unwrap_or(self.difficulty));
+            diff.charter.get_or_insert_with(|| self.charter.clone());
+            diff.offset.get_or_insert(self.offset);
+        }
+        self.difficulty_index = 0;
+        self.apply_difficulty();
+    }
+
+    /// Writes the top-level fields back to the selected difficulty, so that edits to them are kept.
+    pub fn sync_difficulty(&mut self) {
+        if let Some(diff) = self.difficulties.get_mut(self.difficulty_index) {
+            diff.chart = Some(self.chart.clone());
+            diff.format = self.format.clone();
+            diff.level = Some(self.level.clone());
+            diff.difficulty = Some(self.difficulty);
+            diff.charter = Some(self.charter.clone());
+            diff.offset = Some(self.offset);
         }
     }
+
+    pub fn select_difficulty(&mut self, index: usize) {
+        self.sync_difficulty();
+        self.difficulty_index = index;
+        self.apply_difficulty();
+    }
+
+    fn apply_difficulty(&mut self) {
+        let Some(diff) = self.difficulties.get(self.difficulty_index) else {
+            return;
+        };
+        if let Some(chart) = &diff.chart {
+            self.chart = chart.clone();
+        }
+        self.format = diff.format.clone();
+        if let Some(level) = &diff.level {
+            self.level = level.clone();
+        }
+        if let Some(difficulty) = diff.difficulty {
+            self.difficulty = difficulty;
+        }
+        if let Some(charter) = &diff.charter {
+            self.charter = charter.clone();
+        }
+        if let Some(offset) = diff.offset {
+            self.offset = offset;
+        }
+    }
+}
+
+/// Takes the number at the end of a level string, like 15 in `IN Lv.15`
+pub fn infer_difficulty(level: &str) -> Option<f32> {
+    level
+        .chars()
+        .rev()
+        .take_while(|it| it.is_ascii_digit())
+        .collect::<String>()
+        .chars()
+        .rev()
+        .collect::<String>()
+        .parse::<u32>()
+        .ok()
+        .map(|it| it as f32)
 }
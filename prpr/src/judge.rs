@@ -1,7 +1,8 @@
 use crate::{
-    config::Config,
+    config::{Config, KeyboardConfig, KeyboardMapping},
     core::{BadNote, Chart, NoteKind, Point, Resource, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{get_viewport, NotNanExt},
+    replay::ReplayFrame,
 };
 use macroquad::prelude::{
     utils::{register_input_subscriber, repeat_all_miniquad_input},
@@ -112,10 +113,10 @@ impl VelocityTracker {
         b
     }
 
-    pub fn has_flick(&mut self, res: &Resource) -> bool {
+    pub fn has_flick(&mut self, dpi: u32) -> bool {
         let spd = self.speed();
         let norm = spd.norm();
-        let threshold = FLICK_SPEED_THRESHOLD * (res.dpi as f32 / 275.);
+        let threshold = FLICK_SPEED_THRESHOLD * (dpi as f32 / 275.);
         if self.wait && (norm <= threshold * (1.2 / 1.8) || (self.last_dir.dot(&spd.unscale(norm)) - 1.).abs() > 0.4) {
             self.wait = false;
         }
@@ -145,6 +146,54 @@ pub enum Judgement {
     Miss,
 }
 
+/// Everything [`Judge::judge_frame`] needs to know about the environment
+pub struct JudgeContext {
+    pub time: f32,
+    pub speed: f32,
+    pub aspect_ratio: f32,
+    pub note_width: f32,
+    pub dpi: u32,
+}
+
+impl JudgeContext {
+    pub fn new(res: &Resource) -> Self {
+        Self {
+            time: res.time,
+            speed: res.config.speed,
+            aspect_ratio: res.aspect_ratio,
+            note_width: res.note_width,
+            dpi: res.dpi,
+        }
+    }
+}
+
+/// Note kinds that are judged (as perfect) automatically even if autoplay is off. Used in practice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoPlayKinds {
+    pub click: bool,
+    pub drag: bool,
+    pub hold: bool,
+    pub flick: bool,
+}
+
+impl AutoPlayKinds {
+    pub fn contains(&self, kind: &NoteKind) -> bool {
+        match kind {
+            NoteKind::Click => self.click,
+            NoteKind::Drag => self.drag,
+            NoteKind::Hold { .. } => self.hold,
+            NoteKind::Flick => self.flick,
+        }
+    }
+}
+
+pub struct FrameJudgements {
+    /// (judgement, line id, note id, diff)
+    pub judgements: Vec<(Judgement, usize, u32, Option<f32>)>,
+    /// Number of holds that started being held in this frame
+    pub hold_starts: u32,
+}
+
 #[cfg(not(feature = "closed"))]
 #[derive(Default)]
 pub(crate) struct JudgeInner {
@@ -219,6 +268,7 @@ impl JudgeInner {
             counts: self.counts,
             early,
             late: self.diffs.len() as u32 - early,
+            records: Vec::new(),
         }
     }
 
@@ -245,13 +295,20 @@ pub struct Judge {
     pub last_time: f32,
 
     key_down_count: u32,
+    /// Hold notes being sustained by each key in keyboard mode
+    key_holds: HashMap<u8, (usize, u32)>,
 
     pub(crate) inner: JudgeInner,
+
+    recording: Option<Vec<ReplayFrame>>,
+    records: Vec<NoteRecord>,
+
+    pub auto_kinds: AutoPlayKinds,
 }
 
 static SUBSCRIBER_ID: Lazy<usize> = Lazy::new(register_input_subscriber);
 thread_local! {
-    static TOUCHES: RefCell<(Vec<Touch>, i32, u32)> = RefCell::default();
+    static TOUCHES: RefCell<(Vec<Touch>, i32, u32, Vec<(KeyCode, bool)>)> = RefCell::default();
 }
 
 impl Judge {
@@ -271,15 +328,46 @@ impl Judge {
             last_time: 0.,
 
             key_down_count: 0,
+            key_holds: HashMap::new(),
 
             inner: JudgeInner::new(chart.lines.iter().map(|it| it.notes.iter().filter(|it| !it.fake).count() as u32).sum()),
+
+            recording: None,
+            records: Vec::new(),
+
+            auto_kinds: AutoPlayKinds::default(),
         }
     }
 
     pub fn reset(&mut self) {
         self.notes.iter_mut().for_each(|it| it.1 = 0);
         self.trackers.clear();
+        self.key_holds.clear();
         self.inner.reset();
+        self.records.clear();
+        if let Some(frames) = &mut self.recording {
+            frames.clear();
+        }
+    }
+
+    /// Resets the statistics and skips notes that are already judged. Should be called after [`Chart::seek`].
+    pub fn seek(&mut self, chart: &Chart) {
+        self.reset();
+        for (line, (idx, st)) in chart.lines.iter().zip(self.notes.iter_mut()) {
+            *st = idx
+                .iter()
+                .position(|id| !matches!(line.notes[*id as usize].judge, JudgeStatus::Judged))
+                .unwrap_or(idx.len());
+        }
+    }
+
+    /// Starts recording the input of every judged frame, see [`Judge::take_recording`].
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    pub fn take_recording(&mut self) -> Option<Vec<ReplayFrame>> {
+        self.recording.take()
     }
 
     pub fn commit(&mut self, what: Judgement, diff: Option<f32>) {
@@ -297,11 +385,11 @@ impl Judge {
     }
 
     pub(crate) fn on_new_frame() {
-        let mut handler = Handler(Vec::new(), 0, 0);
+        let mut handler = Handler(Vec::new(), 0, 0, Vec::new());
         repeat_all_miniquad_input(&mut handler, *SUBSCRIBER_ID);
         handler.finalize();
         TOUCHES.with(|it| {
-            *it.borrow_mut() = (handler.0, handler.1, handler.2);
+            *it.borrow_mut() = (handler.0, handler.1, handler.2, handler.3);
         });
     }
 
@@ -334,12 +422,7 @@ impl Judge {
             self.auto_play_update(res, chart);
             return;
         }
-        const X_DIFF_MAX: f32 = 0.21 / (16. / 9.) * 2.;
-        let spd = res.config.speed;
-
-        let t = res.time;
-        // TODO optimize
-        let mut touches: HashMap<u64, Touch> = {
+        let frame = {
             let mut touches = touches();
             let btn = MouseButton::Left;
             let id = button_to_id(btn);
@@ -366,29 +449,118 @@ impl Judge {
                 });
             }
             let tr = Self::touch_transform();
-            touches
-                .into_iter()
-                .map(|mut it| {
-                    tr(&mut it);
-                    (it.id, it)
-                })
-                .collect()
+            touches.iter_mut().for_each(tr);
+            let (mut events, key_delta, keys_down, keys) = TOUCHES.with(|it| {
+                let guard = it.borrow();
+                let keyboard = &res.config.keyboard;
+                if keyboard.enabled {
+                    (guard.0.clone(), 0, 0, keyboard_input(keyboard, &guard.3))
+                } else {
+                    (guard.0.clone(), guard.1, guard.2, Vec::new())
+                }
+            });
+            events.iter_mut().for_each(|it| {
+                it.position = vec2(it.position.x / screen_width() * 2. - 1., it.position.y / screen_height() * 2. - 1.);
+            });
+            ReplayFrame {
+                time: res.time,
+                touches,
+                events,
+                key_delta,
+                keys_down,
+                keys,
+            }
         };
-        let (events, keys_down) = TOUCHES.with(|it| {
-            let guard = it.borrow();
-            (guard.0.clone(), guard.2)
-        });
-        self.key_down_count = self.key_down_count.saturating_add_signed(TOUCHES.with(|it| it.borrow().1));
-        {
-            fn to_local(Vec2 { x, y }: Vec2) -> Point {
-                Point::new(x / screen_width() * 2. - 1., y / screen_height() * 2. - 1.)
+        if let Some(frames) = &mut self.recording {
+            frames.push(frame.clone());
+        }
+        self.update_input(res, chart, bad_notes, frame);
+    }
+
+    /// Judges a single frame of input. `frame.time` overrides the current time of `res`.
+    pub fn update_input(&mut self, res: &mut Resource, chart: &mut Chart, bad_notes: &mut Vec<BadNote>, frame: ReplayFrame) {
+        res.time = frame.time;
+        let t = res.time;
+        let judged = self.judge_frame(&JudgeContext::new(res), chart, frame);
+        for _ in 0..judged.hold_starts {
+            play_sfx(&mut res.sfx_click, &res.config);
+        }
+        for (judgement, line_id, id, _) in judged.judgements.into_iter() {
+            let line = &mut chart.lines[line_id];
+            let note = &mut line.notes[id as usize];
+            line.object.set_time(t);
+            note.object.set_time(t);
+            let line = &chart.lines[line_id];
+            let note = &line.notes[id as usize];
+            let line_tr = line.now_transform(res, &chart.lines);
+            if matches!(note.kind, NoteKind::Hold { .. }) {
+                continue;
             }
+            if match judgement {
+                Judgement::Perfect => {
+                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_PERFECT_COLOR));
+                    true
+                }
+                Judgement::Good => {
+                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_GOOD_COLOR));
+                    true
+                }
+                Judgement::Bad => {
+                    if !matches!(note.kind, NoteKind::Hold { .. }) {
+                        bad_notes.push(BadNote {
+                            time: t,
+                            kind: note.kind.clone(),
+                            matrix: {
+                                let mut mat = line_tr;
+                                if !note.above {
+                                    mat.append_nonuniform_scaling_mut(&Vector::new(1., -1.));
+                                }
+                                let incline_sin = line.incline.now_opt().map(|it| it.to_radians().sin()).unwrap_or_default();
+                                mat *= note.now_transform(
+                                    res,
+                                    &line.ctrl_obj.borrow_mut(),
+                                    (note.height - line.height.now()) / res.aspect_ratio * note.speed,
+                                    incline_sin,
+                                );
+                                mat
+                            },
+                        });
+                    }
+                    false
+                }
+                _ => false,
+            } {
+                if let Some(sfx) = match note.kind {
+                    NoteKind::Click => Some(&mut res.sfx_click),
+                    NoteKind::Drag => Some(&mut res.sfx_drag),
+                    NoteKind::Flick => Some(&mut res.sfx_flick),
+                    _ => None,
+                } {
+                    play_sfx(sfx, &res.config);
+                }
+            }
+        }
+    }
+
+    /// Judges a single frame of input without touching any rendering or audio state.
+    pub fn judge_frame(&mut self, ctx: &JudgeContext, chart: &mut Chart, frame: ReplayFrame) -> FrameJudgements {
+        const X_DIFF_MAX: f32 = 0.21 / (16. / 9.) * 2.;
+        let spd = ctx.speed;
+
+        let t = ctx.time;
+        let mut hold_starts = 0;
+        // TODO optimize
+        let mut touches: HashMap<u64, Touch> = frame.touches.into_iter().map(|it| (it.id, it)).collect();
+        let (events, keys_down, keys) = (frame.events, frame.keys_down, frame.keys);
+        self.key_down_count = self.key_down_count.saturating_add_signed(frame.key_delta);
+        self.key_holds.retain(|key, _| keys.iter().any(|it| it.id == *key));
+        {
             let delta = (t / spd - self.last_time) as f64 / (events.len() + 1) as f64;
             let mut t = self.last_time as f64;
             for Touch { id, phase, position: p } in events.into_iter() {
                 t += delta;
                 let t = t as f32;
-                let p = to_local(p);
+                let p = Point::new(p.x, p.y);
                 match phase {
                     TouchPhase::Started => {
                         self.trackers.insert(id, VelocityTracker::new(t, p));
@@ -415,9 +587,12 @@ impl Judge {
         let touches: Vec<Touch> = touches.into_values().collect();
         // pos[line][touch]
         let mut pos = Vec::<Vec<Option<Point>>>::with_capacity(chart.lines.len());
+        let mut trs = Vec::with_capacity(chart.lines.len());
         for id in 0..pos.capacity() {
             chart.lines[id].object.set_time(t);
-            let inv = chart.lines[id].now_transform(res, &chart.lines).try_inverse().unwrap();
+            let tr = chart.lines[id].now_transform_with(ctx.aspect_ratio, &chart.lines);
+            let inv = tr.try_inverse().unwrap();
+            trs.push(tr);
             pos.push(
                 touches
                     .iter()
@@ -441,16 +616,18 @@ impl Judge {
         for (id, touch) in touches.iter().enumerate() {
             let click = touch.phase == TouchPhase::Started;
             let flick = matches!(touch.phase, TouchPhase::Moved | TouchPhase::Stationary)
-                && self.trackers.get_mut(&touch.id).map_or(false, |it| it.has_flick(res));
+                && self.trackers.get_mut(&touch.id).map_or(false, |it| it.has_flick(ctx.dpi));
             if !(click || flick) {
                 continue;
             }
             let mut closest = (None, X_DIFF_MAX, LIMIT_BAD);
             for (line_id, ((line, pos), (idx, st))) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter_mut()).enumerate() {
-                let Some(pos) = pos[id] else { continue; };
+                let Some(pos) = pos[id] else {
+                    continue;
+                };
                 for id in &idx[*st..] {
                     let note = &mut line.notes[*id as usize];
-                    if !matches!(note.judge, JudgeStatus::NotJudged | JudgeStatus::PreJudge) {
+                    if !matches!(note.judge, JudgeStatus::NotJudged | JudgeStatus::PreJudge) || self.auto_kinds.contains(&note.kind) {
                         continue;
                     }
                     if !click && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. }) {
@@ -480,8 +657,8 @@ impl Judge {
                     } else {
                         dt
                     };
-                    if dt + (dist / res.note_width - 1.).max(0.) * DIST_FACTOR
-                        < closest.2 - 0.01 + (closest.1 / res.note_width - 1.).max(0.) * DIST_FACTOR
+                    if dt + (dist / ctx.note_width - 1.).max(0.) * DIST_FACTOR
+                        < closest.2 - 0.01 + (closest.1 / ctx.note_width - 1.).max(0.) * DIST_FACTOR
                     {
                         closest = (Some((line_id, *id)), dist, dt + 0.01);
                     }
@@ -506,7 +683,7 @@ impl Judge {
                                 judgements.push((if dt <= LIMIT_PERFECT { Judgement::Perfect } else { Judgement::Good }, line_id, id, None));
                             }
                             NoteKind::Hold { .. } => {
-                                play_sfx(&mut res.sfx_click, &res.config);
+                                hold_starts += 1;
                                 note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
                             }
                             _ => unreachable!(),
@@ -537,7 +714,9 @@ impl Judge {
                         .cloned()
                         .find(|id| {
                             let note = &line.notes[*id as usize];
-                            matches!(note.judge, JudgeStatus::NotJudged) && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. })
+                            matches!(note.judge, JudgeStatus::NotJudged)
+                                && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. })
+                                && !self.auto_kinds.contains(&note.kind)
                         })
                         .map(|id| (line_id, id))
                 })
@@ -563,7 +742,7 @@ impl Judge {
                             ));
                         }
                         NoteKind::Hold { .. } => {
-                            play_sfx(&mut res.sfx_click, &res.config);
+                            hold_starts += 1;
                             note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
                         }
                         _ => unreachable!(),
@@ -573,20 +752,91 @@ impl Judge {
                 break;
             }
         }
+        let in_lane = |lane: Option<(f32, f32)>, line_id: usize, x: f32| {
+            lane.map_or(true, |(left, right)| {
+                let x = trs[line_id].transform_point(&Point::new(x, 0.)).x.clamp(-1., 1.);
+                left <= x && x <= right
+            })
+        };
+        for key in keys.iter().filter(|it| it.pressed) {
+            // the note closest in time among the ones this key can hit
+            let mut closest: Option<(usize, u32, f32)> = None;
+            for (line_id, (line, (idx, st))) in chart.lines.iter_mut().zip(self.notes.iter()).enumerate() {
+                for id in &idx[*st..] {
+                    let note = &mut line.notes[*id as usize];
+                    let dt = ((t - note.time) / spd).abs();
+                    if note.time > t && dt > LIMIT_BAD {
+                        break;
+                    }
+                    if !matches!(note.judge, JudgeStatus::NotJudged)
+                        || matches!(note.kind, NoteKind::Drag)
+                        || key.flick != matches!(note.kind, NoteKind::Flick)
+                        || self.auto_kinds.contains(&note.kind)
+                    {
+                        continue;
+                    }
+                    let limit = if matches!(note.kind, NoteKind::Hold { .. }) {
+                        LIMIT_GOOD
+                    } else {
+                        LIMIT_BAD
+                    };
+                    if dt > limit || closest.map_or(false, |it| it.2 <= dt) {
+                        continue;
+                    }
+                    let x = &mut note.object.translation.0;
+                    x.set_time(t);
+                    if in_lane(key.lane, line_id, x.now()) {
+                        closest = Some((line_id, *id, dt));
+                    }
+                }
+            }
+            let Some((line_id, id, dt)) = closest else {
+                continue;
+            };
+            let note = &mut chart.lines[line_id].notes[id as usize];
+            match note.kind {
+                NoteKind::Click => {
+                    note.judge = JudgeStatus::Judged;
+                    judgements.push((
+                        if dt <= LIMIT_PERFECT {
+                            Judgement::Perfect
+                        } else if dt <= LIMIT_GOOD {
+                            Judgement::Good
+                        } else {
+                            Judgement::Bad
+                        },
+                        line_id,
+                        id,
+                        None,
+                    ));
+                }
+                NoteKind::Hold { .. } => {
+                    hold_starts += 1;
+                    note.judge = JudgeStatus::Hold(dt <= LIMIT_PERFECT, t, (t - note.time) / spd, false, f32::INFINITY);
+                    self.key_holds.insert(key.id, (line_id, id));
+                }
+                _ => {
+                    note.judge = JudgeStatus::PreJudge;
+                }
+            }
+        }
         for (line_id, ((line, pos), (idx, st))) in chart.lines.iter_mut().zip(pos.iter()).zip(self.notes.iter()).enumerate() {
             line.object.set_time(t);
             for id in &idx[*st..] {
                 let note = &mut line.notes[*id as usize];
                 if let NoteKind::Hold { end_time, .. } = &note.kind {
                     if let JudgeStatus::Hold(.., ref mut pre_judge, ref mut up_time) = note.judge {
-                        if (*end_time - t) / spd <= LIMIT_BAD {
+                        if *pre_judge || (*end_time - t) / spd <= LIMIT_BAD {
                             *pre_judge = true;
                             continue;
                         }
                         let x = &mut note.object.translation.0;
                         x.set_time(t);
                         let x = x.now();
-                        if self.key_down_count == 0 && !pos.iter().any(|it| it.map_or(false, |it| (it.x - x).abs() <= X_DIFF_MAX)) {
+                        if self.key_down_count == 0
+                            && !self.key_holds.values().any(|it| *it == (line_id, *id))
+                            && !pos.iter().any(|it| it.map_or(false, |it| (it.x - x).abs() <= X_DIFF_MAX))
+                        {
                             if t > *up_time + UP_TOLERANCE {
                                 note.judge = JudgeStatus::Judged;
                                 judgements.push((Judgement::Miss, line_id, *id, None));
@@ -612,6 +862,17 @@ impl Judge {
                 if -dt > LIMIT_BAD {
                     break;
                 }
+                if self.auto_kinds.contains(&note.kind) {
+                    if dt >= 0. {
+                        note.judge = if matches!(note.kind, NoteKind::Hold { .. }) {
+                            hold_starts += 1;
+                            JudgeStatus::Hold(true, t, dt, true, f32::INFINITY)
+                        } else {
+                            JudgeStatus::PreJudge
+                        };
+                    }
+                    continue;
+                }
                 if !matches!(note.kind, NoteKind::Drag) && (self.key_down_count == 0 || !matches!(note.kind, NoteKind::Flick)) {
                     continue;
                 }
@@ -620,6 +881,7 @@ impl Judge {
                 x.set_time(t);
                 let x = x.now();
                 if self.key_down_count != 0
+                    || keys.iter().any(|key| !key.flick && in_lane(key.lane, line_id, x))
                     || pos.iter().any(|it| {
                         it.map_or(false, |it| {
                             let dx = (it.x - x).abs();
@@ -659,69 +921,35 @@ impl Judge {
                 }
             }
         }
-        for (judgement, line_id, id, diff) in judgements.into_iter() {
-            let line = &mut chart.lines[line_id];
-            let note = &mut line.notes[id as usize];
-            line.object.set_time(t);
-            note.object.set_time(t);
-            let line = &chart.lines[line_id];
-            let note = &line.notes[id as usize];
-            let line_tr = line.now_transform(res, &chart.lines);
-            self.commit(
-                judgement,
-                if matches!(judgement, Judgement::Good | Judgement::Bad) {
+        let judgements = judgements
+            .into_iter()
+            .map(|(judgement, line_id, id, diff)| {
+                let note = &chart.lines[line_id].notes[id as usize];
+                let offset = if !matches!(judgement, Judgement::Miss)
+                    && matches!(note.kind, NoteKind::Click | NoteKind::Hold { .. })
+                    && !self.auto_kinds.contains(&note.kind)
+                {
                     Some(diff.unwrap_or((t - note.time) / spd))
                 } else {
                     None
-                },
-            );
-            if matches!(note.kind, NoteKind::Hold { .. }) {
-                continue;
-            }
-            if match judgement {
-                Judgement::Perfect => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_PERFECT_COLOR));
-                    true
-                }
-                Judgement::Good => {
-                    res.with_model(line_tr * note.object.now(res), |res| res.emit_at_origin(note.rotation(line), JUDGE_LINE_GOOD_COLOR));
-                    true
-                }
-                Judgement::Bad => {
-                    if !matches!(note.kind, NoteKind::Hold { .. }) {
-                        bad_notes.push(BadNote {
-                            time: t,
-                            kind: note.kind.clone(),
-                            matrix: {
-                                let mut mat = line_tr;
-                                if !note.above {
-                                    mat.append_nonuniform_scaling_mut(&Vector::new(1., -1.));
-                                }
-                                let incline_sin = line.incline.now_opt().map(|it| it.to_radians().sin()).unwrap_or_default();
-                                mat *= note.now_transform(
-                                    res,
-                                    &line.ctrl_obj.borrow_mut(),
-                                    (note.height - line.height.now()) / res.aspect_ratio * note.speed,
-                                    incline_sin,
-                                );
-                                mat
-                            },
-                        });
-                    }
-                    false
-                }
-                _ => false,
-            } {
-                if let Some(sfx) = match note.kind {
-                    NoteKind::Click => Some(&mut res.sfx_click),
-                    NoteKind::Drag => Some(&mut res.sfx_drag),
-                    NoteKind::Flick => Some(&mut res.sfx_flick),
-                    _ => None,
-                } {
-                    play_sfx(sfx, &res.config);
-                }
-            }
-        }
+                };
+                let diff = if matches!(judgement, Judgement::Good | Judgement::Bad) {
+                    Some(diff.unwrap_or((t - note.time) / spd))
+                } else {
+                    None
+                };
+                self.commit(judgement, diff);
+                self.records.push(NoteRecord {
+                    line: line_id,
+                    note: id,
+                    kind: note.kind.clone(),
+                    judgement,
+                    time: note.time,
+                    offset,
+                });
+                (judgement, line_id, id, diff)
+            })
+            .collect();
         for (line, (idx, st)) in chart.lines.iter().zip(self.notes.iter_mut()) {
             while idx
                 .get(*st)
@@ -731,6 +959,7 @@ impl Judge {
             }
         }
         self.last_time = t / spd;
+        FrameJudgements { judgements, hold_starts }
     }
 
     fn auto_play_update(&mut self, res: &mut Resource, chart: &mut Chart) {
@@ -775,6 +1004,14 @@ impl Judge {
             let (note_transform, note_kind) = {
                 let line = &mut chart.lines[line_id];
                 let note = &mut line.notes[id as usize];
+                self.records.push(NoteRecord {
+                    line: line_id,
+                    note: id,
+                    kind: note.kind.clone(),
+                    judgement: Judgement::Perfect,
+                    time: note.time,
+                    offset: None,
+                });
                 let nt = if matches!(note.kind, NoteKind::Hold { .. }) { t } else { note.time };
                 line.object.set_time(nt);
                 note.object.set_time(nt);
@@ -795,9 +1032,11 @@ impl Judge {
         }
     }
 
-    #[inline]
     pub fn result(&self) -> PlayResult {
-        self.inner.result()
+        PlayResult {
+            records: self.records.clone(),
+            ..self.inner.result()
+        }
     }
 
     #[inline]
@@ -809,9 +1048,14 @@ impl Judge {
     pub fn counts(&self) -> [u32; 4] {
         self.inner.counts()
     }
+
+    /// Judgements made so far, in the order they are made
+    pub fn records(&self) -> &[NoteRecord] {
+        &self.records
+    }
 }
 
-struct Handler(Vec<Touch>, i32, u32);
+struct Handler(Vec<Touch>, i32, u32, Vec<(KeyCode, bool)>);
 impl Handler {
     fn finalize(&mut self) {
         if is_mouse_button_down(MouseButton::Left) {
@@ -861,19 +1105,68 @@ impl EventHandler for Handler {
         });
     }
 
-    fn key_down_event(&mut self, _ctx: &mut miniquad::Context, _keycode: KeyCode, _keymods: miniquad::KeyMods, repeat: bool) {
+    fn key_down_event(&mut self, _ctx: &mut miniquad::Context, keycode: KeyCode, _keymods: miniquad::KeyMods, repeat: bool) {
         if !repeat {
             self.1 += 1;
             self.2 += 1;
+            self.3.push((keycode, true));
         }
     }
 
-    fn key_up_event(&mut self, _ctx: &mut miniquad::Context, _keycode: KeyCode, _keymods: miniquad::KeyMods) {
+    fn key_up_event(&mut self, _ctx: &mut miniquad::Context, keycode: KeyCode, _keymods: miniquad::KeyMods) {
         self.1 -= 1;
+        self.3.push((keycode, false));
     }
 }
 
-#[derive(Default)]
+/// State of a bound key in keyboard mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyInput {
+    /// Index of the binding, tap keys come before flick keys
+    pub id: u8,
+    pub flick: bool,
+    /// The range of x (in chart coordinates) this key covers when mapped to lanes
+    pub lane: Option<(f32, f32)>,
+    /// Whether the key is pressed in this frame, otherwise it's being held
+    pub pressed: bool,
+}
+
+fn keyboard_input(config: &KeyboardConfig, events: &[(KeyCode, bool)]) -> Vec<KeyInput> {
+    let mut res = Vec::new();
+    for (flick, keys, base) in [(false, &config.tap_keys, 0), (true, &config.flick_keys, config.tap_keys.len())] {
+        let width = 2. / keys.len() as f32;
+        for (index, key) in keys.iter().enumerate() {
+            let pressed = events.contains(&(key.0, true));
+            if !pressed && !is_key_down(key.0) {
+                continue;
+            }
+            res.push(KeyInput {
+                id: (base + index) as u8,
+                flick,
+                lane: match config.mapping {
+                    KeyboardMapping::Nearest => None,
+                    KeyboardMapping::Lanes => Some((index as f32 * width - 1., (index + 1) as f32 * width - 1.)),
+                },
+                pressed,
+            });
+        }
+    }
+    res
+}
+
+#[derive(Clone, Debug)]
+pub struct NoteRecord {
+    pub line: usize,
+    pub note: u32,
+    pub kind: NoteKind,
+    pub judgement: Judgement,
+    /// The time the note should be hit at
+    pub time: f32,
+    /// Signed hit error in seconds (negative for early hits), only available for hit clicks and holds
+    pub offset: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct PlayResult {
     pub score: u32,
     pub accuracy: f64,
@@ -882,4 +1175,31 @@ pub struct PlayResult {
     pub counts: [u32; 4],
     pub early: u32,
     pub late: u32,
+    /// Judgements of each note, in the order they are judged
+    pub records: Vec<NoteRecord>,
+}
+
+impl PlayResult {
+    /// Compares everything but the per-note records.
+    pub fn same_score(&self, other: &Self) -> bool {
+        self.score == other.score
+            && self.accuracy == other.accuracy
+            && self.max_combo == other.max_combo
+            && self.num_of_notes == other.num_of_notes
+            && self.counts == other.counts
+            && self.early == other.early
+            && self.late == other.late
+    }
+
+    /// Mean and standard deviation of hit offsets, in seconds
+    pub fn offset_stats(&self) -> Option<(f32, f32)> {
+        let offsets: Vec<f32> = self.records.iter().filter_map(|it| it.offset).collect();
+        if offsets.is_empty() {
+            return None;
+        }
+        let n = offsets.len() as f32;
+        let mean = offsets.iter().sum::<f32>() / n;
+        let variance = offsets.iter().map(|it| (it - mean).powi(2)).sum::<f32>() / n;
+        Some((mean, variance.sqrt()))
+    }
 }
@@ -1,5 +1,8 @@
+pub mod analysis;
+pub mod calibrate;
 pub mod config;
 pub mod core;
+pub mod export;
 pub mod ext;
 pub mod fs;
 pub mod info;
@@ -7,7 +10,9 @@ pub mod judge;
 pub mod l10n;
 pub mod parse;
 pub mod particle;
+pub mod replay;
 pub mod scene;
+pub mod simulate;
 pub mod task;
 pub mod time;
 pub mod ui;
@@ -1,15 +1,54 @@
 mod extra;
-pub use extra::parse_extra;
+pub use extra::{check_extra, parse_extra};
+
+mod fingerprint;
+pub use fingerprint::{chart_fingerprint, Fingerprint};
 
 mod pec;
-pub use pec::parse_pec;
+pub use pec::{parse_pec, parse_pec_tolerant, Diagnostic, PEC_MAX_LINE};
 
 mod pgr;
 pub use pgr::parse_phigros;
 
 mod rpe;
+pub(crate) use rpe::SPEED_RATIO as RPE_SPEED_RATIO;
 pub use rpe::{parse_rpe, RPE_HEIGHT, RPE_WIDTH};
 
+use crate::{
+    core::{Chart, ChartExtra},
+    fs::FileSystem,
+    info::ChartFormat,
+};
+use anyhow::Result;
+
+pub fn infer_format(text: &str) -> ChartFormat {
+    if text.starts_with('{') {
+        if text.contains("\"META\"") {
+            ChartFormat::Rpe
+        } else {
+            ChartFormat::Pgr
+        }
+    } else {
+        ChartFormat::Pec
+    }
+}
+
+pub async fn parse_chart(text: &str, format: &ChartFormat, fs: &mut dyn FileSystem, extra: ChartExtra) -> Result<Chart> {
+    match format {
+        ChartFormat::Rpe => parse_rpe(text, fs, extra).await,
+        ChartFormat::Pgr => parse_phigros(text, extra),
+        ChartFormat::Pec => parse_pec(text, extra),
+    }
+}
+
+/// Like [`parse_chart`], but malformed lines of PEC charts are skipped instead of failing, and returned along with the chart.
+pub async fn parse_chart_tolerant(text: &str, format: &ChartFormat, fs: &mut dyn FileSystem, extra: ChartExtra) -> Result<(Chart, Vec<Diagnostic>)> {
+    match format {
+        ChartFormat::Pec => parse_pec_tolerant(text, extra),
+        _ => Ok((parse_chart(text, format, fs, extra).await?, Vec::new())),
+    }
+}
+
 fn process_lines(v: &mut [crate::core::JudgeLine]) {
     use crate::ext::NotNanExt;
     let mut times = Vec::new();
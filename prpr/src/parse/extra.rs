@@ -3,7 +3,7 @@ use crate::{
     ext::ScaleType,
     fs::FileSystem,
 };
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use macroquad::prelude::{Color, Vec2};
 use serde::Deserialize;
 use std::{collections::HashMap, path::Path, rc::Rc};
@@ -185,7 +185,7 @@ pub async fn parse_extra(source: &str, fs: &mut dyn FileSystem, ffmpeg: Option<&
             videos.push(
                 Video::new(
                     ffmpeg,
-                    fs.load_file(&video.path)
+                    fs.extract_file(&video.path)
                         .await
                         .with_context(|| format!("Failed to read video from {}", video.path))?,
                     r.time(&video.time),
@@ -203,3 +203,26 @@ pub async fn parse_extra(source: &str, fs: &mut dyn FileSystem, ffmpeg: Option<&
         videos,
     })
 }
+
+/// Runs every check [`parse_extra`] does except for compiling shaders and decoding videos, so no window is required.
+pub async fn check_extra(source: &str, fs: &mut dyn FileSystem) -> Result<()> {
+    let ext: Extra = serde_json::from_str(source).context("Failed to parse JSON")?;
+    for (id, effect) in ext.effects.into_iter().enumerate() {
+        async {
+            if let Some(path) = effect.shader.strip_prefix('/') {
+                String::from_utf8(fs.load_file(path).await?).with_context(|| format!("Cannot load shader from {path}"))?;
+            } else if Effect::get_preset(&effect.shader).is_none() {
+                bail!("Cannot find preset shader {}", effect.shader);
+            }
+            Ok(())
+        }
+        .await
+        .with_context(|| format!("In effect #{id}"))?;
+    }
+    for video in ext.videos {
+        if !fs.exists(&video.path).await? {
+            bail!("Failed to read video from {}", video.path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Content hash of charts.
+//!
+//! The fingerprint covers what affects gameplay: notes, timings, the motion of judge lines sampled at a fixed rate and the gameplay
+//! fields of [`ChartInfo`]. It does not depend on the chart format, the layout of the package or how the chart text is formatted, so the
+//! same chart keeps its identity when converted or uploaded again.
+
+use crate::{
+    core::{AnimFloat, Chart, NoteKind},
+    info::ChartInfo,
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::{fmt, str::FromStr};
+
+/// Bumped whenever the hashed content changes, so that fingerprints of different versions never collide
+const VERSION: &[u8] = b"prpr-fingerprint-1";
+const SAMPLE_RATE: f32 = 16.;
+// values are quantized so that differences in float formatting or rounding do not matter
+const TIME_UNIT: f32 = 1e-3;
+const VALUE_UNIT: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 64 || !s.bytes().all(|it| it.is_ascii_hexdigit()) {
+            bail!("Invalid fingerprint: {s}");
+        }
+        let mut res = [0; 32];
+        for (i, byte) in res.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        Ok(Self(res))
+    }
+}
+
+impl Serialize for Fingerprint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn time(t: f32) -> i64 {
+    (t / TIME_UNIT).round() as i64
+}
+
+fn value(v: f32) -> i64 {
+    (v / VALUE_UNIT).round() as i64
+}
+
+fn sample(anim: &mut AnimFloat, t: f32, default: f32) -> i64 {
+    anim.set_time(t);
+    value(anim.now_opt().unwrap_or(default))
+}
+
+pub fn chart_fingerprint(chart: &Chart, info: &ChartInfo) -> Fingerprint {
+    let mut sha = Sha256::new();
+    sha.update(VERSION);
+    let mut put = |v: i64| sha.update(v.to_le_bytes());
+    put(time(chart.offset + info.offset));
+    put(value(info.aspect_ratio));
+    put((info.hold_partial_cover || chart.settings.hold_partial_cover) as i64);
+    put(chart.settings.pe_alpha_extension as i64);
+
+    let end = chart
+        .lines
+        .iter()
+        .flat_map(|it| it.notes.iter())
+        .map(|it| match it.kind {
+            NoteKind::Hold { end_time, .. } => end_time,
+            _ => it.time,
+        })
+        .fold(0., f32::max);
+    let samples = (end * SAMPLE_RATE).ceil().max(0.) as u32 + 1;
+
+    put(chart.lines.len() as i64);
+    for line in &chart.lines {
+        put(line.parent.map_or(-1, |it| it as i64));
+        let obj = &line.object;
+        let (mut x, mut y, mut rotation, mut alpha, mut height) =
+            (obj.translation.0.clone(), obj.translation.1.clone(), obj.rotation.clone(), obj.alpha.clone(), line.height.clone());
+        for i in 0..samples {
+            let t = i as f32 / SAMPLE_RATE;
+            put(sample(&mut x, t, 0.));
+            put(sample(&mut y, t, 0.));
+            put(sample(&mut rotation, t, 0.));
+            put(sample(&mut alpha, t, 1.));
+            put(sample(&mut height, t, 0.));
+        }
+
+        // notes are sorted on their own since their order in the chart file does not matter
+        let mut notes: Vec<_> = line
+            .notes
+            .iter()
+            .map(|note| {
+                let (kind, end_time) = match note.kind {
+                    NoteKind::Click => (0, note.time),
+                    NoteKind::Hold { end_time, .. } => (1, end_time),
+                    NoteKind::Flick => (2, note.time),
+                    NoteKind::Drag => (3, note.time),
+                };
+                let mut x = note.object.translation.0.clone();
+                let mut y = note.object.translation.1.clone();
+                [
+                    time(note.time),
+                    kind,
+                    time(end_time),
+                    sample(&mut x, note.time, 0.),
+                    sample(&mut y, note.time, 0.),
+                    value(note.height),
+                    value(note.speed),
+                    note.above as i64,
+                    note.fake as i64,
+                ]
+            })
+            .collect();
+        notes.sort();
+        put(notes.len() as i64);
+        for note in notes {
+            note.into_iter().for_each(&mut put);
+        }
+    }
+    Fingerprint(sha.finalize().into())
+}
@@ -9,7 +9,59 @@ use crate::{
 };
 use anyhow::{anyhow, bail, Context, Result};
 use macroquad::prelude::warn;
-use std::cell::RefCell;
+use std::{cell::RefCell, fmt, str::SplitWhitespace};
+
+/// Judge line ids above this are rejected, since every line up to the id is allocated.
+pub const PEC_MAX_LINE: usize = 10000;
+
+/// A problem found in a line that was skipped while parsing.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// Whitespace-separated tokens of a line, remembering where the last one starts.
+struct Tokens<'a> {
+    line: &'a str,
+    iter: SplitWhitespace<'a>,
+    last: Option<usize>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(line: &'a str) -> Self {
+        Self {
+            line,
+            iter: line.split_whitespace(),
+            last: None,
+        }
+    }
+
+    /// The column of the last token taken, or the end of the line if there's none left.
+    fn column(&self) -> usize {
+        let offset = self.last.unwrap_or(self.line.len());
+        self.line[..offset].chars().count() + 1
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.iter.next();
+        self.last = token.map(|it| it.as_ptr() as usize - self.line.as_ptr() as usize);
+        token
+    }
+}
 
 trait Take {
     fn take_f32(&mut self) -> Result<f32>;
@@ -53,20 +105,23 @@ struct PECEvent {
     end_time: f32,
     end: f32,
     easing: TweenId,
+    /// 1-based line in the source, for diagnostics
+    source_line: usize,
 }
 
 impl PECEvent {
-    pub fn new(start_time: f32, end_time: f32, end: f32, tween: TweenId) -> Self {
+    pub fn new(start_time: f32, end_time: f32, end: f32, tween: TweenId, source_line: usize) -> Self {
         Self {
             start_time,
             end_time,
             end,
             easing: tween,
+            source_line,
         }
     }
 
-    pub fn single(time: f32, value: f32) -> Self {
-        Self::new(time, time, value, 0)
+    pub fn single(time: f32, value: f32, source_line: usize) -> Self {
+        Self::new(time, time, value, 0, source_line)
     }
 }
 
@@ -97,7 +152,8 @@ fn sanitize_events(events: &mut [PECEvent], id: usize, desc: &str) {
     }
 }
 
-fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str) -> Result<AnimFloat> {
+/// In tolerant mode (`diagnostics` given), interpolating events before the first concrete value are skipped instead of failing.
+fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str, diagnostics: &mut Option<&mut Vec<Diagnostic>>) -> Result<AnimFloat> {
     sanitize_events(&mut events, id, desc);
     let mut kfs = Vec::new();
     for e in events {
@@ -105,9 +161,16 @@ fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str) -> Result<Anim
             kfs.push(Keyframe::new(e.start_time, e.end, 0));
         } else {
             if kfs.is_empty() {
-                bail!("Failed to parse {desc} events: interpolating event found before a concrete value appears");
+                let Some(diagnostics) = diagnostics else {
+                    bail!("Failed to parse {desc} events: interpolating event found before a concrete value appears");
+                };
+                diagnostics.push(Diagnostic {
+                    line: e.source_line,
+                    column: 1,
+                    message: format!("In judge line #{id}: interpolating {desc} event found before a concrete value appears"),
+                });
+                continue;
             }
-            assert!(!kfs.is_empty());
             kfs.push(Keyframe::new(e.start_time, kfs.last().unwrap().value, e.easing));
             kfs.push(Keyframe::new(e.end_time, e.end, 0));
         }
@@ -116,7 +179,7 @@ fn parse_events(mut events: Vec<PECEvent>, id: usize, desc: &str) -> Result<Anim
 }
 
 fn parse_speed_events(mut pec: Vec<(f32, f32)>, max_time: f32) -> AnimFloat {
-    if pec[0].0 >= EPS {
+    if pec.first().map_or(true, |it| it.0 >= EPS) {
         pec.insert(0, (0., 0.));
     }
     let mut kfs = Vec::new();
@@ -133,7 +196,7 @@ fn parse_speed_events(mut pec: Vec<(f32, f32)>, max_time: f32) -> AnimFloat {
     AnimFloat::new(kfs)
 }
 
-fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<JudgeLine> {
+fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32, mut diagnostics: Option<&mut Vec<Diagnostic>>) -> Result<JudgeLine> {
     let mut height = parse_speed_events(pec.speed_events, max_time);
     let mut process_notes = |notes: &mut Vec<Note>| {
         for note in notes {
@@ -156,9 +219,12 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
     let cache = JudgeLineCache::new(&mut pec.notes);
     Ok(JudgeLine {
         object: Object {
-            alpha: parse_events(pec.alpha_events, id, "alpha")?,
-            translation: AnimVector(parse_events(pec.move_events.0, id, "move X")?, parse_events(pec.move_events.1, id, "move Y")?),
-            rotation: parse_events(pec.rotate_events, id, "rotate")?,
+            alpha: parse_events(pec.alpha_events, id, "alpha", &mut diagnostics)?,
+            translation: AnimVector(
+                parse_events(pec.move_events.0, id, "move X", &mut diagnostics)?,
+                parse_events(pec.move_events.1, id, "move Y", &mut diagnostics)?,
+            ),
+            rotation: parse_events(pec.rotate_events, id, "rotate", &mut diagnostics)?,
             scale: AnimVector(AnimFloat::fixed(3.91 / 6.), AnimFloat::default()),
         },
         ctrl_obj: RefCell::default(),
@@ -171,25 +237,39 @@ fn parse_judge_line(mut pec: PECJudgeLine, id: usize, max_time: f32) -> Result<J
         z_index: 0,
         show_below: false,
         attach_ui: None,
+        group: None,
+        links: Vec::new(),
 
         cache,
     })
 }
 
 pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
+    parse_pec_inner(source, extra, false).map(|it| it.0)
+}
+
+/// Parses the chart, skipping malformed lines instead of failing. Problems of skipped lines are returned along with the chart.
+pub fn parse_pec_tolerant(source: &str, extra: ChartExtra) -> Result<(Chart, Vec<Diagnostic>)> {
+    parse_pec_inner(source, extra, true)
+}
+
+fn parse_pec_inner(source: &str, extra: ChartExtra, tolerant: bool) -> Result<(Chart, Vec<Diagnostic>)> {
     let mut offset = None;
     let mut r = None;
     let mut lines = Vec::new();
     let mut bpm_list = Vec::new();
     let mut last_line = None;
-    fn get_line(lines: &mut Vec<PECJudgeLine>, id: usize) -> &mut PECJudgeLine {
+    fn get_line(lines: &mut Vec<PECJudgeLine>, id: usize) -> Result<&mut PECJudgeLine> {
+        if id > PEC_MAX_LINE {
+            bail!("Judge line id {id} is too large (at most {PEC_MAX_LINE})");
+        }
         if lines.len() <= id {
             lines.reserve(id - lines.len() + 1);
             for _ in 0..=(id - lines.len()) {
                 lines.push(PECJudgeLine::default());
             }
         }
-        &mut lines[id]
+        Ok(&mut lines[id])
     }
     fn ensure_bpm<'a>(r: &'a mut Option<BpmList>, bpm_list: &mut Vec<(f32, f32)>) -> &'a mut BpmList {
         if r.is_none() {
@@ -204,16 +284,18 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
     }
     macro_rules! last_note {
         () => {{
-            let Some(last_line) = last_line else {
+            let Some(note) = last_line.and_then(|it| lines[it].notes.last_mut()) else {
                 bail!("No note has been inserted yet");
             };
-            lines[last_line].notes.last_mut().unwrap()
+            note
         }};
     }
-    let mut inner = |line: &str| -> Result<()> {
-        let mut it = line.split_whitespace();
+    let mut inner = |it: &mut Tokens, no: usize| -> Result<()> {
         if offset.is_none() {
-            offset = Some(it.take_f32()? / 1000. - 0.15);
+            // a malformed offset is taken as zero, otherwise the next line would be mistaken for it
+            let value = it.take_f32();
+            offset = Some(value.as_ref().map_or(0., |it| *it) / 1000. - 0.15);
+            value?;
         } else {
             let Some(cmd) = it.next() else {
 				return Ok(());
@@ -231,9 +313,10 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                 }
                 'n' if cs.len() == 2 && ('1'..='4').contains(&cs[1]) => {
                     let r = bpm!();
-                    let line = it.take_usize()?;
-                    last_line = Some(line);
-                    let line = get_line(&mut lines, line);
+                    // a skipped note line must not let the following `#` or `&` change the previous note
+                    last_line = None;
+                    let id = it.take_usize()?;
+                    let line = get_line(&mut lines, id)?;
                     let time = it.take_time(r)?;
                     let kind = match cs[1] {
                         '1' => NoteKind::Click,
@@ -268,6 +351,7 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                         fake,
                         judge: JudgeStatus::NotJudged,
                     });
+                    last_line = Some(id);
                     if it.next() == Some("#") {
                         last_note!().speed = it.take_f32()?;
                     }
@@ -291,7 +375,7 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                 }
                 'c' if cs.len() == 2 => {
                     let r = bpm!();
-                    let line = get_line(&mut lines, it.take_usize()?);
+                    let line = get_line(&mut lines, it.take_usize()?)?;
                     let time = it.take_time(r)?;
                     match cs[1] {
                         'v' => {
@@ -300,29 +384,29 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
                         'p' => {
                             let x = it.take_f32()?;
                             let y = it.take_f32()?;
-                            line.move_events.0.push(PECEvent::single(time, x));
-                            line.move_events.1.push(PECEvent::single(time, y));
+                            line.move_events.0.push(PECEvent::single(time, x, no));
+                            line.move_events.1.push(PECEvent::single(time, y, no));
                         }
                         'd' => {
-                            line.rotate_events.push(PECEvent::single(time, -it.take_f32()?));
+                            line.rotate_events.push(PECEvent::single(time, -it.take_f32()?, no));
                         }
                         'a' => {
-                            line.alpha_events.push(PECEvent::single(time, it.take_f32()?));
+                            line.alpha_events.push(PECEvent::single(time, it.take_f32()?, no));
                         }
                         'm' => {
                             let end_time = it.take_time(r)?;
                             let x = it.take_f32()?;
                             let y = it.take_f32()?;
                             let t = it.take_tween()?;
-                            line.move_events.0.push(PECEvent::new(time, end_time, x, t));
-                            line.move_events.1.push(PECEvent::new(time, end_time, y, t));
+                            line.move_events.0.push(PECEvent::new(time, end_time, x, t, no));
+                            line.move_events.1.push(PECEvent::new(time, end_time, y, t, no));
                         }
                         'r' => {
                             line.rotate_events
-                                .push(PECEvent::new(time, it.take_time(r)?, -it.take_f32()?, it.take_tween()?));
+                                .push(PECEvent::new(time, it.take_time(r)?, -it.take_f32()?, it.take_tween()?, no));
                         }
                         'f' => {
-                            line.alpha_events.push(PECEvent::new(time, it.take_time(r)?, it.take_f32()?, 2));
+                            line.alpha_events.push(PECEvent::new(time, it.take_time(r)?, it.take_f32()?, 2, no));
                         }
                         _ => bail!("Unknown command {cmd}"),
                     }
@@ -335,8 +419,19 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
         }
         Ok(())
     };
+    let mut diagnostics = Vec::new();
     for (id, line) in source.lines().enumerate() {
-        inner(line).with_context(|| anyhow!("On line #{}", id + 1))?;
+        let mut it = Tokens::new(line);
+        if let Err(err) = inner(&mut it, id + 1) {
+            if !tolerant {
+                return Err(err.context(format!("On line #{}", id + 1)));
+            }
+            diagnostics.push(Diagnostic {
+                line: id + 1,
+                column: it.column(),
+                message: format!("{err:#}"),
+            });
+        }
     }
     let max_time = *lines
         .iter()
@@ -358,12 +453,16 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
     let mut lines = lines
         .into_iter()
         .enumerate()
-        .map(|(id, line)| parse_judge_line(line, id, max_time).with_context(|| format!("In judge line #{id}")))
+        .map(|(id, line)| parse_judge_line(line, id, max_time, tolerant.then_some(&mut diagnostics)).with_context(|| format!("In judge line #{id}")))
         .collect::<Result<Vec<_>>>()?;
+    diagnostics.sort_by_key(|it| it.line);
+    for diagnostic in &diagnostics {
+        warn!("Skipped {diagnostic}");
+    }
     process_lines(&mut lines);
     ensure_bpm(&mut r, &mut bpm_list);
-    Ok(Chart::new(
-        offset.unwrap(),
+    let chart = Chart::new(
+        offset.context("Empty chart")?,
         lines,
         r.unwrap(),
         ChartSettings {
@@ -371,5 +470,6 @@ pub fn parse_pec(source: &str, extra: ChartExtra) -> Result<Chart> {
             ..Default::default()
         },
         extra,
-    ))
+    );
+    Ok((chart, diagnostics))
 }
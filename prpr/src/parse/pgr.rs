@@ -19,7 +19,10 @@ struct PgrEvent {
     pub end_time: f32,
     pub start: f32,
     pub end: f32,
+    // absent in formatVersion 1, where both coordinates are packed into `start` and `end`
+    #[serde(default)]
     pub start2: f32,
+    #[serde(default)]
     pub end2: f32,
 }
 
@@ -29,7 +32,8 @@ struct PgrSpeedEvent {
     pub start_time: f32,
     pub end_time: f32,
     pub value: f32,
-    pub floor_position: f32,
+    #[serde(default)]
+    pub floor_position: Option<f32>,
 }
 
 #[derive(Deserialize)]
@@ -41,7 +45,8 @@ pub struct PgrNote {
     position_x: f32,
     hold_time: f32,
     speed: f32,
-    floor_position: f32,
+    #[serde(default)]
+    floor_position: Option<f32>,
 }
 
 #[derive(Deserialize)]
@@ -63,10 +68,16 @@ struct PgrJudgeLine {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PgrChart {
+    #[serde(default = "default_format_version")]
+    format_version: u32,
     offset: f32,
     judge_line_list: Vec<PgrJudgeLine>,
 }
 
+fn default_format_version() -> u32 {
+    3
+}
+
 macro_rules! validate_events {
     ($pgr:expr) => {
         $pgr.retain(|it| {
@@ -91,10 +102,16 @@ macro_rules! validate_events {
 fn parse_speed_events(r: f32, mut pgr: Vec<PgrSpeedEvent>, max_time: f32) -> Result<(AnimFloat, AnimFloat)> {
     validate_events!(pgr);
     assert_eq!(pgr[0].start_time, 0.0);
+    // older charts don't come with floor positions, integrate the speed instead
+    let mut floor_position = 0.;
+    for e in &mut pgr {
+        let fp = *e.floor_position.get_or_insert(floor_position);
+        floor_position = fp + (e.end_time - e.start_time) * r * e.value;
+    }
     let mut kfs = Vec::new();
-    kfs.extend(pgr.iter().map(|it| Keyframe::new(it.start_time * r, it.floor_position, 2)));
+    kfs.extend(pgr.iter().map(|it| Keyframe::new(it.start_time * r, it.floor_position.unwrap(), 2)));
     let last = pgr.last().unwrap();
-    kfs.push(Keyframe::new(max_time, last.floor_position + (max_time - last.start_time * r) * last.value, 0));
+    kfs.push(Keyframe::new(max_time, last.floor_position.unwrap() + (max_time - last.start_time * r) * last.value, 0));
     for kf in &mut kfs {
         kf.value /= HEIGHT_RATIO;
     }
@@ -114,8 +131,16 @@ fn parse_float_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimFloat> {
     Ok(AnimFloat::new(kfs))
 }
 
-fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>) -> Result<AnimVector> {
+fn parse_move_events(r: f32, mut pgr: Vec<PgrEvent>, format_version: u32) -> Result<AnimVector> {
     validate_events!(pgr);
+    if format_version == 1 {
+        // x * 1000 + y, where x ranges from 0 to 880 and y from 0 to 520
+        let unpack = |value: f32| ((value / 1000.).floor() / 880., value.rem_euclid(1000.) / 520.);
+        for e in &mut pgr {
+            (e.start, e.start2) = unpack(e.start);
+            (e.end, e.end2) = unpack(e.end);
+        }
+    }
     let mut kf1 = Vec::<Keyframe<f32>>::new();
     let mut kf2 = Vec::<Keyframe<f32>>::new();
     for e in pgr {
@@ -174,7 +199,13 @@ fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mu
                 } else {
                     pgr.speed
                 },
-                height: pgr.floor_position / HEIGHT_RATIO,
+                height: match pgr.floor_position {
+                    Some(fp) => fp / HEIGHT_RATIO,
+                    None => {
+                        height.set_time(time);
+                        height.now()
+                    }
+                },
 
                 above,
                 multiple_hint: false,
@@ -185,7 +216,7 @@ fn parse_notes(r: f32, mut pgr: Vec<PgrNote>, speed: &mut AnimFloat, height: &mu
         .collect()
 }
 
-fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
+fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32, format_version: u32) -> Result<JudgeLine> {
     let r = 60. / pgr.bpm / 32.;
     let (mut speed, mut height) = parse_speed_events(r, pgr.speed_events, max_time).context("Failed to parse speed events")?;
     let notes_above = parse_notes(r, pgr.notes_above, &mut speed, &mut height, true).context("Failed to parse notes above")?;
@@ -197,7 +228,7 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
         object: Object {
             alpha: parse_float_events(r, pgr.alpha_events).context("Failed to parse alpha events")?,
             rotation: parse_float_events(r, pgr.rotate_events).context("Failed to parse rotate events")?,
-            translation: parse_move_events(r, pgr.move_events).context("Failed to parse move events")?,
+            translation: parse_move_events(r, pgr.move_events, format_version).context("Failed to parse move events")?,
             ..Default::default()
         },
         ctrl_obj: RefCell::default(),
@@ -210,6 +241,8 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
         z_index: 0,
         show_below: true,
         attach_ui: None,
+        group: None,
+        links: Vec::new(),
 
         cache,
     })
@@ -217,6 +250,9 @@ fn parse_judge_line(pgr: PgrJudgeLine, max_time: f32) -> Result<JudgeLine> {
 
 pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
     let pgr: PgrChart = serde_json::from_str(source).context("Failed to parse JSON")?;
+    if !(1..=3).contains(&pgr.format_version) {
+        bail!("Unsupported formatVersion: {}", pgr.format_version);
+    }
     let max_time = *pgr
         .judge_line_list
         .iter()
@@ -232,12 +268,15 @@ pub fn parse_phigros(source: &str, extra: ChartExtra) -> Result<Chart> {
         .max()
         .unwrap_or_default()
         + 1.;
+    // time units are based on the BPM of each line, but beats are only meaningful for the chart as a whole
+    let bpm = pgr.judge_line_list.first().map_or(120., |it| it.bpm);
+    let format_version = pgr.format_version;
     let mut lines = pgr
         .judge_line_list
         .into_iter()
         .enumerate()
-        .map(|(id, pgr)| parse_judge_line(pgr, max_time).with_context(|| format!("In judge line #{id}")))
+        .map(|(id, pgr)| parse_judge_line(pgr, max_time, format_version).with_context(|| format!("In judge line #{id}")))
         .collect::<Result<Vec<_>>>()?;
     process_lines(&mut lines);
-    Ok(Chart::new(pgr.offset, lines, BpmList::default(), ChartSettings::default(), extra))
+    Ok(Chart::new(pgr.offset, lines, BpmList::new(vec![(0., bpm)]), ChartSettings::default(), extra))
 }
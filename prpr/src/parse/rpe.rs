@@ -1,10 +1,11 @@
 use super::{process_lines, RPE_TWEEN_MAP};
 use crate::{
     core::{
-        Anim, AnimFloat, AnimVector, BezierTween, BpmList, Chart, ChartSettings, ClampedTween, CtrlObject, JudgeLine, JudgeLineCache, JudgeLineKind,
-        Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, Tweenable, UIElement, EPS, HEIGHT_RATIO, JUDGE_LINE_PERFECT_COLOR, ChartExtra,
+        Anim, AnimFloat, AnimVector, BezierTween, BpmList, Chart, ChartSettings, ClampedTween, CtrlObject, EventLink, JudgeLine, JudgeLineCache,
+        JudgeLineKind, Keyframe, Note, NoteKind, Object, StaticTween, Triple, TweenFunction, Tweenable, UIElement, EPS, HEIGHT_RATIO,
+        JUDGE_LINE_PERFECT_COLOR, ChartExtra,
     },
-    ext::NotNanExt,
+    ext::{NotNanExt, SafeTexture},
     fs::FileSystem,
     judge::JudgeStatus,
 };
@@ -15,7 +16,7 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 pub const RPE_WIDTH: f32 = 1350.;
 pub const RPE_HEIGHT: f32 = 900.;
-const SPEED_RATIO: f32 = 10. / 45. / HEIGHT_RATIO;
+pub(crate) const SPEED_RATIO: f32 = 10. / 45. / HEIGHT_RATIO;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -36,7 +37,6 @@ fn f32_one() -> f32 {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEEvent<T = f32> {
-    // TODO linkgroup
     #[serde(default = "f32_zero")]
     easing_left: f32,
     #[serde(default = "f32_one")]
@@ -50,6 +50,8 @@ struct RPEEvent<T = f32> {
     end: T,
     start_time: Triple,
     end_time: Triple,
+    #[serde(default)]
+    linkgroup: i32,
 }
 
 #[derive(Deserialize)]
@@ -64,11 +66,12 @@ struct RPECtrlEvent {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RPESpeedEvent {
-    // TODO linkgroup
     start_time: Triple,
     end_time: Triple,
     start: f32,
     end: f32,
+    #[serde(default)]
+    linkgroup: i32,
 }
 
 #[derive(Deserialize)]
@@ -121,14 +124,16 @@ struct RPENote {
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct RPEJudgeLine {
-    // TODO group
-    // TODO alphaControl, bpmfactor
+    #[serde(rename = "Group", default)]
+    group: usize,
     #[serde(rename = "Name")]
     name: String,
     #[serde(rename = "Texture")]
     texture: String,
     #[serde(rename = "father")]
     parent: Option<isize>,
+    #[serde(default = "f32_one")]
+    bpmfactor: f32,
     event_layers: Vec<Option<RPEEventLayer>>,
     extended: Option<RPEExtendedEvents>,
     notes: Option<Vec<RPENote>>,
@@ -161,7 +166,22 @@ struct RPEChart {
     meta: RPEMetadata,
     #[serde(rename = "BPMList")]
     bpm_list: Vec<RPEBpmItem>,
+    #[serde(default)]
+    judge_line_group: Vec<String>,
     judge_line_list: Vec<RPEJudgeLine>,
+    #[serde(default)]
+    prpr_settings: ChartSettings,
+}
+
+fn add_links<T>(r: &mut BpmList, links: &mut Vec<EventLink>, kind: &'static str, layer: usize, events: &[RPEEvent<T>]) {
+    for event in events.iter().filter(|it| it.linkgroup != 0) {
+        links.push(EventLink {
+            kind,
+            layer,
+            time: r.time(&event.start_time),
+            group: event.linkgroup,
+        });
+    }
 }
 
 type BezierMap = HashMap<(u16, i16, i16), Rc<dyn TweenFunction>>;
@@ -329,20 +349,40 @@ fn parse_notes(r: &mut BpmList, rpe: Vec<RPENote>, height: &mut AnimFloat) -> Re
         .collect()
 }
 
-fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> AnimFloat {
-    let vals: Vec<_> = rpe.iter().map(|it| it.value[key]).collect();
-    if rpe.is_empty() || (rpe.len() == 2 && rpe[0].easing == 1 && (vals[0] - 1.).abs() < 1e-4) {
-        return AnimFloat::default();
+fn parse_ctrl_events(rpe: &[RPECtrlEvent], key: &str) -> Result<AnimFloat> {
+    let vals = rpe
+        .iter()
+        .map(|it| it.value.get(key).copied())
+        .collect::<Option<Vec<_>>>()
+        .with_context(|| format!("Missing value in {key} control"))?;
+    // RPE fills every line with the same constant curve, which is a no-op
+    if vals.iter().all(|it| (it - 1.).abs() < 1e-4) {
+        return Ok(AnimFloat::default());
     }
-    AnimFloat::new(
+    Ok(AnimFloat::new(
         rpe.iter()
             .zip(vals.into_iter())
             .map(|(it, val)| Keyframe::new(it.x, val, RPE_TWEEN_MAP.get(it.easing.max(1) as usize).copied().unwrap_or(RPE_TWEEN_MAP[0])))
             .collect(),
-    )
+    ))
 }
 
-async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs: &mut dyn FileSystem, bezier_map: &BezierMap) -> Result<JudgeLine> {
+/// The BPM list of a line whose BPM is divided by `bpmfactor`, or `None` if it's the same as the chart's.
+fn line_bpm_list(bpm_list: &[(f32, f32)], bpmfactor: f32) -> Option<BpmList> {
+    if (bpmfactor - 1.).abs() < EPS {
+        return None;
+    }
+    Some(BpmList::new(bpm_list.iter().map(|(beats, bpm)| (*beats, bpm / bpmfactor)).collect()))
+}
+
+async fn parse_judge_line(
+    r: &mut BpmList,
+    rpe: RPEJudgeLine,
+    max_time: f32,
+    fs: &mut dyn FileSystem,
+    bezier_map: &BezierMap,
+    groups: &[String],
+) -> Result<JudgeLine> {
     let event_layers: Vec<_> = rpe.event_layers.into_iter().flatten().collect();
     fn events_with_factor(
         r: &mut BpmList,
@@ -361,6 +401,38 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
         res.map_value(|v| v * factor);
         Ok(res)
     }
+    let mut links = Vec::new();
+    // layers are numbered per kind of event, as they are chained
+    let layer_events: [(&'static str, fn(&RPEEventLayer) -> &Option<Vec<RPEEvent>>); 4] = [
+        ("alpha", |it| &it.alpha_events),
+        ("move X", |it| &it.move_x_events),
+        ("move Y", |it| &it.move_y_events),
+        ("rotate", |it| &it.rotate_events),
+    ];
+    for (kind, get) in layer_events {
+        for (layer, events) in event_layers.iter().filter_map(|it| get(it).as_ref()).enumerate() {
+            add_links(r, &mut links, kind, layer, events);
+        }
+    }
+    // speed layers are merged into the height
+    for events in event_layers.iter().filter_map(|it| it.speed_events.as_ref()) {
+        for event in events.iter().filter(|it| it.linkgroup != 0) {
+            links.push(EventLink {
+                kind: "speed",
+                layer: 0,
+                time: r.time(&event.start_time),
+                group: event.linkgroup,
+            });
+        }
+    }
+    if let Some(e) = &rpe.extended {
+        add_links(r, &mut links, "color", 0, e.color_events.as_deref().unwrap_or_default());
+        add_links(r, &mut links, "text", 0, e.text_events.as_deref().unwrap_or_default());
+        add_links(r, &mut links, "scale X", 0, e.scale_x_events.as_deref().unwrap_or_default());
+        add_links(r, &mut links, "scale Y", 0, e.scale_y_events.as_deref().unwrap_or_default());
+        add_links(r, &mut links, "incline", 0, e.incline_events.as_deref().unwrap_or_default());
+        add_links(r, &mut links, "paint", 0, e.paint_events.as_deref().unwrap_or_default());
+    }
     let mut height = parse_speed_events(r, &event_layers, max_time)?;
     let mut notes = parse_notes(r, rpe.notes.unwrap_or_default(), &mut height)?;
     let cache = JudgeLineCache::new(&mut notes);
@@ -416,10 +488,10 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
             },
         },
         ctrl_obj: RefCell::new(CtrlObject {
-            alpha: parse_ctrl_events(&rpe.alpha_control, "alpha"),
-            size: parse_ctrl_events(&rpe.size_control, "size"),
-            pos: parse_ctrl_events(&rpe.pos_control, "pos"),
-            y: parse_ctrl_events(&rpe.y_control, "y"),
+            alpha: parse_ctrl_events(&rpe.alpha_control, "alpha")?,
+            size: parse_ctrl_events(&rpe.size_control, "size")?,
+            pos: parse_ctrl_events(&rpe.pos_control, "pos")?,
+            y: parse_ctrl_events(&rpe.y_control, "y")?,
         }),
         height,
         incline: if let Some(events) = rpe.extended.as_ref().and_then(|e| e.incline_events.as_ref()) {
@@ -438,12 +510,12 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
             }
         } else {
             JudgeLineKind::Texture(
-                image::load_from_memory(
+                SafeTexture::lazy(image::load_from_memory(
                     &fs.load_file(&rpe.texture)
                         .await
                         .with_context(|| format!("加载插图 {} 失败", rpe.texture))?,
-                )?
-                .into(),
+                )?),
+                rpe.texture.clone(),
             )
         },
         color: if let Some(events) = rpe.extended.as_ref().and_then(|e| e.color_events.as_ref()) {
@@ -462,6 +534,8 @@ async fn parse_judge_line(r: &mut BpmList, rpe: RPEJudgeLine, max_time: f32, fs:
         z_index: rpe.z_order,
         show_below: rpe.is_cover != 1,
         attach_ui: rpe.attach_ui,
+        group: groups.get(rpe.group).cloned(),
+        links,
 
         cache,
     })
@@ -498,7 +572,8 @@ fn get_bezier_map(rpe: &RPEChart) -> BezierMap {
 pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra) -> Result<Chart> {
     let rpe: RPEChart = serde_json::from_str(source).context("Failed to parse JSON")?;
     let bezier_map = get_bezier_map(&rpe);
-    let mut r = BpmList::new(rpe.bpm_list.into_iter().map(|it| (it.start_time.beats(), it.bpm)).collect());
+    let bpm_list: Vec<_> = rpe.bpm_list.into_iter().map(|it| (it.start_time.beats(), it.bpm)).collect();
+    let mut r = BpmList::new(bpm_list.clone());
     fn vec<T>(v: &Option<Vec<T>>) -> impl Iterator<Item = &T> {
         v.iter().flat_map(|it| it.iter())
     }
@@ -507,6 +582,8 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra)
         .judge_line_list
         .iter()
         .map(|line| {
+            let mut line_r = line_bpm_list(&bpm_list, line.bpmfactor);
+            let r = line_r.as_mut().unwrap_or(&mut r);
             line.notes.as_ref().map(|notes| {
                 notes
                     .iter()
@@ -534,15 +611,17 @@ pub async fn parse_rpe(source: &str, fs: &mut dyn FileSystem, extra: ChartExtra)
         })
         .max().unwrap_or_default() + 1.;
     // don't want to add a whole crate for a mere join_all...
+    let groups = rpe.judge_line_group;
     let mut lines = Vec::new();
     for (id, rpe) in rpe.judge_line_list.into_iter().enumerate() {
         let name = rpe.name.clone();
+        let mut line_r = line_bpm_list(&bpm_list, rpe.bpmfactor);
         lines.push(
-            parse_judge_line(&mut r, rpe, max_time, fs, &bezier_map)
+            parse_judge_line(line_r.as_mut().unwrap_or(&mut r), rpe, max_time, fs, &bezier_map, &groups)
                 .await
                 .with_context(move || format!("In judge line #{id} ({})", name))?,
         );
     }
     process_lines(&mut lines);
-    Ok(Chart::new(rpe.meta.offset as f32 / 1000.0, lines, r, ChartSettings::default(), extra))
+    Ok(Chart::new(rpe.meta.offset as f32 / 1000.0, lines, r, rpe.prpr_settings, extra))
 }
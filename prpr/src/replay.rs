@@ -0,0 +1,316 @@
+//! Replays of judged input.
+//!
+//! A replay stores, for every frame the judge processed, the exact input it consumed (touches already mapped to chart space, raw touch
+//! events used for flick detection and key events) together with the chart time. Feeding the frames back to [`Judge::update_input`] in
+//! order reproduces the original run regardless of the current screen size or frame rate.
+//!
+//! [`Judge::update_input`]: crate::judge::Judge::update_input
+
+use crate::{
+    config::Config,
+    judge::{KeyInput, PlayResult},
+    parse::Fingerprint,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use macroquad::prelude::{vec2, Touch, TouchPhase};
+
+const MAGIC: &[u8] = b"PRPRRPL";
+const VERSION: u8 = 1;
+const MOUSE_IDS: u64 = 4;
+/// Upper bound of the decompressed size, far above what a real play takes, so that crafted files cannot exhaust memory
+const MAX_SIZE: usize = 64 << 20;
+
+#[derive(Clone, Debug, Default)]
+pub struct ReplayFrame {
+    /// Chart time (`Resource::time`) of this frame
+    pub time: f32,
+    /// Touches in chart coordinates
+    pub touches: Vec<Touch>,
+    /// Raw touch events of this frame, in normalized screen coordinates
+    pub events: Vec<Touch>,
+    /// Change in the number of keys being held
+    pub key_delta: i32,
+    /// Number of keys pressed in this frame
+    pub keys_down: u32,
+    /// Bound keys being held in keyboard mode
+    pub keys: Vec<KeyInput>,
+}
+
+impl ReplayFrame {
+    pub fn new(time: f32) -> Self {
+        Self { time, ..Default::default() }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub fingerprint: Fingerprint,
+    pub speed: f32,
+    pub offset: f32,
+    pub note_scale: f32,
+    pub aspect_ratio: f32,
+    pub dpi: u32,
+    pub result: PlayResult,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    /// Whether the replay was recorded on the given chart
+    pub fn matches(&self, fingerprint: &Fingerprint) -> bool {
+        self.fingerprint == *fingerprint
+    }
+
+    /// Overrides the judgement-related configuration with the recorded one.
+    pub fn apply_config(&self, config: &mut Config) {
+        config.autoplay = false;
+        config.speed = self.speed;
+        config.offset = self.offset;
+        config.note_scale = self.note_scale;
+        config.aspect_ratio = Some(self.aspect_ratio);
+        config.fix_aspect_ratio = true;
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.bytes(&self.fingerprint.0);
+        w.f32(self.speed);
+        w.f32(self.offset);
+        w.f32(self.note_scale);
+        w.f32(self.aspect_ratio);
+        w.varint(self.dpi as u64);
+
+        let r = &self.result;
+        w.varint(r.score as u64);
+        w.f64(r.accuracy);
+        w.varint(r.max_combo as u64);
+        w.varint(r.num_of_notes as u64);
+        for count in r.counts {
+            w.varint(count as u64);
+        }
+        w.varint(r.early as u64);
+        w.varint(r.late as u64);
+
+        w.varint(self.frames.len() as u64);
+        for frame in &self.frames {
+            w.f32(frame.time);
+            w.touches(&frame.touches);
+            w.touches(&frame.events);
+            w.varint(((frame.key_delta << 1) ^ (frame.key_delta >> 31)) as u32 as u64);
+            w.varint(frame.keys_down as u64);
+            w.keys(&frame.keys);
+        }
+
+        let mut res = MAGIC.to_vec();
+        res.push(VERSION);
+        res.extend(miniz_oxide::deflate::compress_to_vec(&w.0, 6));
+        res
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let Some(data) = data.strip_prefix(MAGIC) else {
+            bail!("Not a replay file");
+        };
+        let Some((&version, data)) = data.split_first() else {
+            bail!("Not a replay file");
+        };
+        if version != VERSION {
+            bail!("Unsupported replay version: {version}");
+        }
+        let data =
+            miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_SIZE).map_err(|err| anyhow!("Failed to decompress replay: {err:?}"))?;
+        let mut r = Reader(&data);
+        (|| -> Result<Self> {
+            let fingerprint = Fingerprint(r.bytes(32)?.try_into().unwrap());
+            let speed = r.f32()?;
+            let offset = r.f32()?;
+            let note_scale = r.f32()?;
+            let aspect_ratio = r.f32()?;
+            let dpi = r.u32()?;
+            let result = PlayResult {
+                score: r.u32()?,
+                accuracy: r.f64()?,
+                max_combo: r.u32()?,
+                num_of_notes: r.u32()?,
+                counts: [r.u32()?, r.u32()?, r.u32()?, r.u32()?],
+                early: r.u32()?,
+                late: r.u32()?,
+                records: Vec::new(),
+            };
+            let len = r.varint()? as usize;
+            let mut frames = Vec::with_capacity(len.min(r.0.len()));
+            for _ in 0..len {
+                let time = r.f32()?;
+                let touches = r.touches()?;
+                let events = r.touches()?;
+                let key_delta = r.u32()?;
+                let keys_down = r.u32()?;
+                frames.push(ReplayFrame {
+                    time,
+                    touches,
+                    events,
+                    key_delta: ((key_delta >> 1) as i32) ^ -((key_delta & 1) as i32),
+                    keys_down,
+                    keys: r.keys()?,
+                });
+            }
+            if !r.0.is_empty() {
+                bail!("Trailing data");
+            }
+            Ok(Self {
+                fingerprint,
+                speed,
+                offset,
+                note_scale,
+                aspect_ratio,
+                dpi,
+                result,
+                frames,
+            })
+        })()
+        .context("Malformed replay")
+    }
+}
+
+fn phase_to_u8(phase: TouchPhase) -> u8 {
+    match phase {
+        TouchPhase::Started => 0,
+        TouchPhase::Stationary => 1,
+        TouchPhase::Moved => 2,
+        TouchPhase::Ended => 3,
+        TouchPhase::Cancelled => 4,
+    }
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn touches(&mut self, touches: &[Touch]) {
+        self.varint(touches.len() as u64);
+        for touch in touches {
+            // mouse buttons take ids near u64::MAX, shift them to small numbers instead
+            self.varint(touch.id.wrapping_add(MOUSE_IDS));
+            self.0.push(phase_to_u8(touch.phase));
+            self.f32(touch.position.x);
+            self.f32(touch.position.y);
+        }
+    }
+
+    fn keys(&mut self, keys: &[KeyInput]) {
+        self.varint(keys.len() as u64);
+        for key in keys {
+            self.0.push(key.id);
+            self.0.push(key.flick as u8 | (key.pressed as u8) << 1 | (key.lane.is_some() as u8) << 2);
+            if let Some((left, right)) = key.lane {
+                self.f32(left);
+                self.f32(right);
+            }
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            bail!("Unexpected end of data");
+        }
+        let (res, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut res = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                bail!("Varint too long");
+            }
+            res |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break Ok(res);
+            }
+            shift += 7;
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.varint()?.try_into()?)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn touches(&mut self) -> Result<Vec<Touch>> {
+        let len = self.varint()? as usize;
+        let mut res = Vec::with_capacity(len.min(self.0.len()));
+        for _ in 0..len {
+            let id = self.varint()?.wrapping_sub(MOUSE_IDS);
+            let phase = match self.u8()? {
+                0 => TouchPhase::Started,
+                1 => TouchPhase::Stationary,
+                2 => TouchPhase::Moved,
+                3 => TouchPhase::Ended,
+                4 => TouchPhase::Cancelled,
+                x => bail!("Invalid touch phase: {x}"),
+            };
+            let x = self.f32()?;
+            let y = self.f32()?;
+            res.push(Touch {
+                id,
+                phase,
+                position: vec2(x, y),
+            });
+        }
+        Ok(res)
+    }
+
+    fn keys(&mut self) -> Result<Vec<KeyInput>> {
+        let len = self.varint()? as usize;
+        let mut res = Vec::with_capacity(len.min(self.0.len()));
+        for _ in 0..len {
+            let id = self.u8()?;
+            let flags = self.u8()?;
+            let lane = if flags & 4 != 0 { Some((self.f32()?, self.f32()?)) } else { None };
+            res.push(KeyInput {
+                id,
+                flick: flags & 1 != 0,
+                lane,
+                pressed: flags & 2 != 0,
+            });
+        }
+        Ok(res)
+    }
+}
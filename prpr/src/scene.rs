@@ -4,7 +4,7 @@ mod ending;
 pub use ending::{EndingScene, RecordUpdateState};
 
 mod game;
-pub use game::{GameMode, GameScene, FFMPEG_PATH};
+pub use game::{GameMode, GameScene, FFMPEG_PATH, REPLAY_DIR};
 
 mod loading;
 pub use loading::LoadingScene;
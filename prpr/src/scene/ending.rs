@@ -3,11 +3,13 @@ crate::tl_file!("ending");
 use super::{draw_background, draw_illustration, NextScene, Scene};
 use crate::{
     config::Config,
+    core::{JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{
         create_audio_manger, draw_parallelogram, draw_parallelogram_ex, draw_text_aligned, screen_aspect, SafeTexture, ScaleType, PARALLELOGRAM_SLOPE,
     },
     info::ChartInfo,
-    judge::{Judge, PlayResult},
+    judge::{Judge, Judgement, PlayResult, LIMIT_BAD, LIMIT_GOOD, LIMIT_PERFECT},
+    parse::Fingerprint,
     scene::show_message,
     task::Task,
     ui::{Dialog, MessageHandle, Ui},
@@ -43,13 +45,16 @@ pub struct EndingScene {
     challenge_rank: u32,
     autoplay: bool,
     speed: f32,
+    offset: f32,
+    show_details: bool,
     next: u8, // 0 -> none, 1 -> pop, 2 -> exit
     update_state: Option<RecordUpdateState>,
     rated: bool,
 
-    upload_fn: Option<fn(String) -> Task<Result<RecordUpdateState>>>,
+    upload_fn: Option<fn(String, Fingerprint) -> Task<Result<RecordUpdateState>>>,
     upload_task: Option<(Task<Result<RecordUpdateState>>, MessageHandle)>,
     record_data: Option<String>,
+    fingerprint: Fingerprint,
 }
 
 impl EndingScene {
@@ -65,8 +70,9 @@ impl EndingScene {
         challenge_texture: SafeTexture,
         config: &Config,
         bgm: AudioClip,
-        upload_fn: Option<fn(String) -> Task<Result<RecordUpdateState>>>,
+        upload_fn: Option<fn(String, Fingerprint) -> Task<Result<RecordUpdateState>>>,
         record_data: Option<String>,
+        fingerprint: Fingerprint,
     ) -> Result<Self> {
         let mut audio = create_audio_manger(config)?;
         let bgm = audio.create_music(
@@ -77,7 +83,11 @@ impl EndingScene {
                 ..Default::default()
             },
         )?;
-        let upload_task = upload_fn.and_then(|f| record_data.clone().map(|data| (f(data), show_message(tl!("uploading")).handle())));
+        let upload_task = upload_fn.and_then(|f| {
+            record_data
+                .clone()
+                .map(|data| (f(data, fingerprint), show_message(tl!("uploading")).handle()))
+        });
         Ok(Self {
             background,
             illustration,
@@ -106,15 +116,109 @@ impl EndingScene {
             challenge_rank: config.challenge_rank,
             autoplay: config.autoplay,
             speed: config.speed,
+            offset: config.offset,
+            show_details: false,
             next: 0,
 
             upload_fn,
             upload_task,
             record_data,
+            fingerprint,
         })
     }
 }
 
+impl EndingScene {
+    fn draw_details(&self, ui: &mut Ui, r: Rect) {
+        const BIN: f32 = 0.01;
+        const MISS_COLOR: Color = Color::new(1., 0.4, 0.4, 1.);
+
+        let res = &self.result;
+        draw_parallelogram(r, None, Color::new(0., 0., 0., 0.75), false);
+        let pad = 0.03;
+        let inner = Rect::new(r.x + r.h * PARALLELOGRAM_SLOPE + pad, r.y + pad, r.w - r.h * PARALLELOGRAM_SLOPE * 2. - pad * 2., r.h - pad * 2.);
+        let Some((mean, std_dev)) = res.offset_stats() else {
+            draw_text_aligned(ui, &tl!("no-offsets"), inner.center().x, inner.center().y, (0.5, 0.5), 0.4, WHITE);
+            return;
+        };
+        let text = tl!("offset-stats", "mean" => format!("{:+.1}", mean * 1000.), "std" => format!("{:.1}", std_dev * 1000.));
+        let mut top = draw_text_aligned(ui, &text, inner.x, inner.y, (0., 0.), 0.4, WHITE).bottom();
+        let hits = res.records.iter().filter(|it| it.offset.is_some()).count();
+        if hits >= 10 && mean.abs() >= 0.01 {
+            let offset = self.offset + mean * self.speed;
+            let text = tl!("offset-suggestion", "offset" => format!("{:.0}", offset * 1000.));
+            top = draw_text_aligned(ui, &text, inner.x, top + 0.01, (0., 0.), 0.34, Color::new(1., 1., 1., 0.7)).bottom();
+        }
+        let color_of = |offset: f32| {
+            let offset = offset.abs();
+            if offset <= LIMIT_PERFECT {
+                JUDGE_LINE_PERFECT_COLOR
+            } else if offset <= LIMIT_GOOD {
+                JUDGE_LINE_GOOD_COLOR
+            } else {
+                MISS_COLOR
+            }
+        };
+        let spacing = 0.03;
+        let h = (inner.bottom() - top - spacing * 2.) / 2.;
+
+        // hit error histogram
+        let area = Rect::new(inner.x, top + spacing, inner.w, h);
+        let bins = (LIMIT_BAD * 2. / BIN).round() as usize;
+        let mut counts = vec![0u32; bins];
+        for offset in res.records.iter().filter_map(|it| it.offset) {
+            let bin = ((offset + LIMIT_BAD) / BIN).floor().clamp(0., (bins - 1) as f32) as usize;
+            counts[bin] += 1;
+        }
+        let max = counts.iter().copied().max().unwrap_or_default().max(1) as f32;
+        let w = area.w / bins as f32;
+        for (i, count) in counts.into_iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let bh = area.h * count as f32 / max;
+            let center = (i as f32 + 0.5) * BIN - LIMIT_BAD;
+            ui.fill_rect(Rect::new(area.x + w * i as f32, area.bottom() - bh, w * 0.8, bh), color_of(center));
+        }
+        ui.fill_rect(Rect::new(area.center().x - 0.001, area.y, 0.002, area.h), Color::new(1., 1., 1., 0.5));
+        draw_text_aligned(ui, "Early", area.x, area.y, (0., 0.), 0.26, WHITE);
+        draw_text_aligned(ui, "Late", area.right(), area.y, (1., 0.), 0.26, WHITE);
+
+        // timeline
+        let area = Rect::new(inner.x, area.bottom() + spacing, inner.w, h);
+        ui.fill_rect(area, Color::new(1., 1., 1., 0.05));
+        let cy = area.center().y;
+        let y_of = |offset: f32| cy + offset.clamp(-LIMIT_BAD, LIMIT_BAD) / LIMIT_BAD * area.h / 2.;
+        for limit in [LIMIT_PERFECT, LIMIT_GOOD] {
+            for y in [y_of(-limit), y_of(limit)] {
+                ui.fill_rect(Rect::new(area.x, y - 0.0005, area.w, 0.001), Color::new(1., 1., 1., 0.2));
+            }
+        }
+        ui.fill_rect(Rect::new(area.x, cy - 0.001, area.w, 0.002), Color::new(1., 1., 1., 0.5));
+        let (start, end) = res
+            .records
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), it| (lo.min(it.time), hi.max(it.time)));
+        let x_of = |time: f32| {
+            area.x
+                + if end > start {
+                    (time - start) / (end - start) * area.w
+                } else {
+                    area.w / 2.
+                }
+        };
+        let dot = 0.005;
+        for record in &res.records {
+            let x = x_of(record.time);
+            if let Some(offset) = record.offset {
+                ui.fill_rect(Rect::new(x - dot / 2., y_of(offset) - dot / 2., dot, dot), color_of(offset));
+            } else if matches!(record.judgement, Judgement::Miss) {
+                ui.fill_rect(Rect::new(x - 0.0005, area.y, 0.001, area.h), Color { a: 0.4, ..MISS_COLOR });
+            }
+        }
+    }
+}
+
 thread_local! {
     static RE_UPLOAD: RefCell<bool> = RefCell::default();
 }
@@ -148,7 +252,7 @@ impl Scene for EndingScene {
             self.upload_task = self
                 .record_data
                 .clone()
-                .map(|data| ((self.upload_fn.unwrap())(data), show_message(tl!("uploading")).handle()));
+                .map(|data| ((self.upload_fn.unwrap())(data, self.fingerprint), show_message(tl!("uploading")).handle()));
         }
         if let Some((task, handle)) = &mut self.upload_task {
             if let Some(result) = task.take() {
@@ -198,6 +302,7 @@ impl Scene for EndingScene {
 
         tran(gl, (1. - ran(now, 0.1, 1.3)).powi(3));
         let r = draw_illustration(*self.illustration, -0.38, 0., 1., 1.2, WHITE);
+        let illustration = r;
         let slope = PARALLELOGRAM_SLOPE;
         let ratio = 0.2;
         draw_parallelogram_ex(
@@ -207,6 +312,10 @@ impl Scene for EndingScene {
             Color::new(0., 0., 0., 0.7),
             false,
         );
+        if self.show_details {
+            // leave the bottom strip for the name and level
+            self.draw_details(ui, Rect::new(r.x + r.h * ratio * slope, r.y, r.w - r.h * ratio * slope, r.h * (1. - ratio)));
+        }
         let rr = draw_text_aligned(ui, &self.info.level, r.right() - r.h / 7. * 13. * 0.13 - 0.01, r.bottom() - top / 20., (1., 1.), 0.46, WHITE);
         let p = (r.x + 0.04, r.bottom() - top / 20.);
         let mw = rr.x - 0.02 - p.0;
@@ -326,6 +435,10 @@ impl Scene for EndingScene {
                 .any(|touch| touch.phase == TouchPhase::Ended && rect.contains(touch.position))
         }
 
+        if now > 1.3 && touched(illustration) {
+            self.show_details ^= true;
+        }
+
         let dy = 0.006;
         let w = 0.17;
         let p = (1. - ran(now, 2., 2.7)).powi(2);
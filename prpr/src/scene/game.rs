@@ -4,18 +4,21 @@ crate::tl_file!("game");
 
 use super::{draw_background, ending::RecordUpdateState, request_input, return_input, show_message, take_input, EndingScene, NextScene, Scene};
 use crate::{
+    calibrate::{self, Calibration},
     config::Config,
     core::{copy_fbo, BadNote, Chart, ChartExtra, Effect, Point, Resource, UIElement, Vector, JUDGE_LINE_GOOD_COLOR, JUDGE_LINE_PERFECT_COLOR},
     ext::{screen_aspect, RectExt, SafeTexture},
     fs::FileSystem,
     info::{ChartFormat, ChartInfo},
-    judge::Judge,
-    parse::{parse_extra, parse_pec, parse_phigros, parse_rpe},
+    judge::{Judge, Judgement},
+    parse::{chart_fingerprint, infer_format, parse_chart_tolerant, parse_extra, Fingerprint},
+    replay::Replay,
     task::Task,
     time::TimeManager,
     ui::{RectButton, Ui},
 };
 use anyhow::{bail, Context, Result};
+use chrono::Utc;
 use concat_string::concat_string;
 use lyon::path::Path;
 use macroquad::{prelude::*, window::InternalGlContext};
@@ -30,6 +33,8 @@ use std::{
 };
 
 const PAUSE_CLICK_INTERVAL: f32 = 0.7;
+const PRACTICE_SPEED_MIN: f32 = 0.5;
+const PRACTICE_SPEED_MAX: f32 = 2.;
 
 #[cfg(feature = "closed")]
 mod inner;
@@ -37,6 +42,8 @@ mod inner;
 use inner::*;
 
 pub static FFMPEG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// Replays of normal plays are saved into this directory if set
+pub static REPLAY_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
 
 const WAIT_TIME: f32 = 0.5;
 const AFTER_TIME: f32 = 0.7;
@@ -58,13 +65,22 @@ extern "C" {
     fn on_game_start();
 }
 
-#[derive(PartialEq, Eq)]
 pub enum GameMode {
     Normal,
     TweakOffset,
     Exercise,
+    Replay(Rc<Replay>),
+    Calibrate,
 }
 
+impl PartialEq for GameMode {
+    fn eq(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl Eq for GameMode {}
+
 #[derive(Clone)]
 enum State {
     Starting,
@@ -83,6 +99,7 @@ pub struct GameScene {
     pub judge: Judge,
     pub gl: InternalGlContext<'static>,
     player: Option<String>,
+    pub fingerprint: Fingerprint,
     chart_str: String,
     chart_format: ChartFormat,
     info_offset: f32,
@@ -93,6 +110,10 @@ pub struct GameScene {
     exercise_range: Range<f32>,
     exercise_press: Option<(i8, u64)>,
     exercise_btns: (RectButton, RectButton),
+    // speed down, speed up, then auto play toggles of click, drag, hold and flick
+    practice_btns: [RectButton; 6],
+
+    replay_cursor: usize,
 
     pub music: Music,
 
@@ -105,13 +126,14 @@ pub struct GameScene {
 
     bad_notes: Vec<BadNote>,
 
-    upload_fn: Option<fn(String) -> Task<Result<RecordUpdateState>>>,
+    upload_fn: Option<fn(String, Fingerprint) -> Task<Result<RecordUpdateState>>>,
 }
 
 macro_rules! reset {
     ($self:ident, $res:expr, $tm:ident) => {{
         $self.bad_notes.clear();
         $self.judge.reset();
+        $self.replay_cursor = 0;
         $self.chart.reset();
         $res.judge_line_color = JUDGE_LINE_PERFECT_COLOR;
         $self.music.pause()?;
@@ -138,7 +160,8 @@ impl GameScene {
         bail!("Cannot find chart file")
     }
 
-    pub async fn load_chart(fs: &mut dyn FileSystem, info: &ChartInfo) -> Result<(Chart, String, ChartFormat)> {
+    /// Loads the chart, skipping malformed lines of PEC charts. The last value tells whether any line was skipped.
+    pub async fn load_chart(fs: &mut dyn FileSystem, info: &ChartInfo) -> Result<(Chart, String, ChartFormat, bool)> {
         let extra = fs.load_file("extra.json").await.ok().map(String::from_utf8).transpose()?;
         let extra = if let Some(extra) = extra {
             let ffmpeg: PathBuf = FFMPEG_PATH.lock().unwrap().to_owned().unwrap_or_else(|| "ffmpeg".into());
@@ -156,24 +179,15 @@ impl GameScene {
             ChartExtra::default()
         };
         let text = String::from_utf8(Self::load_chart_bytes(fs, info).await.context("Failed to load chart")?)?;
-        let format = info.format.clone().unwrap_or_else(|| {
-            if text.starts_with('{') {
-                if text.contains("\"META\"") {
-                    ChartFormat::Rpe
-                } else {
-                    ChartFormat::Pgr
-                }
-            } else {
-                ChartFormat::Pec
-            }
-        });
-        let mut chart = match format {
-            ChartFormat::Rpe => parse_rpe(&text, fs, extra).await,
-            ChartFormat::Pgr => parse_phigros(&text, extra),
-            ChartFormat::Pec => parse_pec(&text, extra),
-        }?;
+        let format = info.format.clone().unwrap_or_else(|| infer_format(&text));
+        let (mut chart, diagnostics) = parse_chart_tolerant(&text, &format, fs, extra).await?;
+        if let Some(first) = diagnostics.first() {
+            show_message(tl!("chart-lines-skipped", "count" => diagnostics.len(), "first" => first.to_string()))
+                .warn()
+                .duration(5.);
+        }
         chart.settings.hold_partial_cover = info.hold_partial_cover;
-        Ok((chart, text, format))
+        Ok((chart, text, format, !diagnostics.is_empty()))
     }
 
     pub async fn new(
@@ -185,18 +199,33 @@ impl GameScene {
         background: SafeTexture,
         illustration: SafeTexture,
         get_size_fn: Rc<dyn Fn() -> (u32, u32)>,
-        upload_fn: Option<fn(String) -> Task<Result<RecordUpdateState>>>,
+        upload_fn: Option<fn(String, Fingerprint) -> Task<Result<RecordUpdateState>>>,
     ) -> Result<Self> {
-        match mode {
+        match &mode {
             GameMode::TweakOffset => {
                 config.autoplay = true;
             }
             GameMode::Exercise => {
                 config.autoplay = false;
             }
+            GameMode::Replay(replay) => {
+                replay.apply_config(&mut config);
+            }
+            GameMode::Calibrate => {
+                config.autoplay = false;
+                config.speed = 1.;
+            }
             _ => {}
         }
-        let (mut chart, chart_str, chart_format) = Self::load_chart(fs.deref_mut(), &info).await?;
+        let (mut chart, chart_str, chart_format, lines_skipped) = Self::load_chart(fs.deref_mut(), &info).await?;
+        // the fingerprint of a chart with skipped lines matches no strictly parsed chart, so nothing is recorded or uploaded for it
+        let upload_fn = upload_fn.filter(|_| !lines_skipped);
+        let fingerprint = chart_fingerprint(&chart, &info);
+        if let GameMode::Replay(replay) = &mode {
+            if !replay.matches(&fingerprint) {
+                bail!("The replay was recorded on a different chart");
+            }
+        }
         let effects = std::mem::take(&mut chart.extra.global_effects);
         if config.fxaa {
             chart
@@ -212,7 +241,23 @@ impl GameScene {
             .context("Failed to load resources")?;
         let exercise_range = (chart.offset + info_offset + res.config.offset)..res.track_length;
 
-        let judge = Judge::new(&chart);
+        let mut judge = Judge::new(&chart);
+        match &mode {
+            GameMode::Normal => {
+                if !res.config.autoplay && !lines_skipped && REPLAY_DIR.lock().unwrap().is_some() {
+                    judge.start_recording();
+                }
+            }
+            GameMode::Replay(replay) => {
+                // flick detection depends on it
+                res.dpi = replay.dpi;
+            }
+            GameMode::Calibrate => {
+                res.music = calibrate::metronome_music(&res.music);
+                res.track_length = res.music.length();
+            }
+            _ => {}
+        }
 
         let music = Self::new_music(&mut res)?;
         Ok(Self {
@@ -225,6 +270,7 @@ impl GameScene {
             judge,
             gl: unsafe { get_internal_gl() },
             player,
+            fingerprint,
             chart_str,
             chart_format,
             compatible_mode: false,
@@ -235,6 +281,9 @@ impl GameScene {
             exercise_range,
             exercise_press: None,
             exercise_btns: (RectButton::new(), RectButton::new()),
+            practice_btns: [RectButton::new(); 6],
+
+            replay_cursor: 0,
 
             music,
 
@@ -314,15 +363,69 @@ impl GameScene {
 
         let margin = 0.03;
 
+        let overlay = res.config.judge_overlay.then(|| {
+            let [perfect, good, bad, miss] = self.judge.counts();
+            let judged = perfect + good + bad + miss;
+            // accuracy over the notes judged so far, rather than over the whole chart
+            let accuracy = if judged == 0 {
+                1.
+            } else {
+                (perfect as f32 + good as f32 * 0.65) / judged as f32
+            };
+            let (early, late) = self
+                .judge
+                .records()
+                .iter()
+                .filter(|it| matches!(it.judgement, Judgement::Good | Judgement::Bad))
+                .filter_map(|it| it.offset)
+                .fold((0, 0), |(early, late), offset| if offset < 0. { (early + 1, late) } else { (early, late + 1) });
+            (accuracy, early, late)
+        });
+        // the overlay follows the score, including the line it is attached to
         self.chart.with_element(ui, res, UIElement::Score, |ui, color, scale| {
-            ui.text(format!("{:07}", self.judge.score()))
+            let color = Color { a: color.a * c.a, ..color };
+            let btm = ui
+                .text(format!("{:07}", self.judge.score()))
                 .pos(1. - margin, top + eps * 2.2 - (1. - p) * 0.4)
                 .anchor(1., 0.)
                 .size(0.8)
-                .color(Color { a: color.a * c.a, ..color })
+                .color(color)
                 .scale(scale)
-                .draw();
+                .draw()
+                .bottom();
+            if let Some((accuracy, early, late)) = overlay {
+                let btm = ui
+                    .text(format!("{:.2}%", accuracy * 100.))
+                    .pos(1. - margin, btm + 0.01)
+                    .anchor(1., 0.)
+                    .size(0.5)
+                    .color(color)
+                    .draw()
+                    .bottom();
+                ui.text(format!("EARLY {early}  LATE {late}"))
+                    .pos(1. - margin, btm + 0.01)
+                    .anchor(1., 0.)
+                    .size(0.35)
+                    .color(color)
+                    .draw();
+            }
         });
+        if res.config.show_touches {
+            let touches = match &self.mode {
+                GameMode::Replay(replay) => self
+                    .replay_cursor
+                    .checked_sub(1)
+                    .and_then(|it| replay.frames.get(it))
+                    .map(|it| it.touches.clone())
+                    .unwrap_or_default(),
+                _ => Judge::get_touches(),
+            };
+            for touch in touches {
+                if touch.phase != TouchPhase::Ended && touch.phase != TouchPhase::Cancelled {
+                    ui.fill_circle(touch.position.x, touch.position.y, 0.025, Color::new(1., 1., 1., 0.4 * c.a));
+                }
+            }
+        }
         self.chart.with_element(ui, res, UIElement::Pause, |ui, color, scale| {
             let mut r = Rect::new(pause_center.x - pause_w * 1.5, pause_center.y - pause_h / 2., pause_w, pause_h);
             let ct = pause_center.coords;
@@ -333,6 +436,7 @@ impl GameScene {
                 ui.fill_rect(r, c);
             });
         });
+        let replay = matches!(self.mode, GameMode::Replay(_));
         if self.judge.combo() >= 3 {
             let btm = self.chart.with_element(ui, res, UIElement::ComboNumber, |ui, color, scale| {
                 ui.text(self.judge.combo().to_string())
@@ -344,13 +448,21 @@ impl GameScene {
                     .bottom()
             });
             self.chart.with_element(ui, res, UIElement::Combo, |ui, color, scale| {
-                ui.text(if res.config.autoplay { "AUTOPLAY" } else { "COMBO" })
-                    .pos(0., btm + 0.01)
-                    .anchor(0.5, 0.)
-                    .size(0.4)
-                    .color(Color { a: color.a * c.a, ..color })
-                    .scale(scale)
-                    .draw();
+                ui.text(if res.config.autoplay {
+                    "AUTOPLAY"
+                } else if replay {
+                    "REPLAY"
+                } else if self.mode == GameMode::Calibrate {
+                    "CALIBRATION"
+                } else {
+                    "COMBO"
+                })
+                .pos(0., btm + 0.01)
+                .anchor(0.5, 0.)
+                .size(0.4)
+                .color(Color { a: color.a * c.a, ..color })
+                .scale(scale)
+                .draw();
             });
         }
         let lf = -1. + margin;
@@ -460,6 +572,11 @@ impl GameScene {
                         if self.mode == GameMode::Exercise && tm.now() > self.exercise_range.end as f64 {
                             tm.seek_to(self.exercise_range.start as f64);
                             self.music.seek_to(self.exercise_range.start)?;
+                            // `res` is borrowed, so `seek_chart` can't be used here
+                            let offset = self.chart.offset + res.config.offset + self.info_offset;
+                            self.chart.seek(self.exercise_range.start - offset);
+                            self.judge.seek(&self.chart);
+                            self.bad_notes.clear();
                             pos = self.exercise_range.start;
                         }
                         self.music.play()?;
@@ -523,6 +640,7 @@ impl GameScene {
                     if let Some(touch) = Judge::get_touches().iter().rfind(|it| it.id == *id) {
                         let x = touch.position.x;
                         let p = (x + hw) / (hw * 2.) * (self.res.track_length - sp) + sp;
+                        let p = if *ctrl == 0 { p } else { self.snap_to_beat(p) };
                         let p = if self.res.track_length - sp <= 3. || *ctrl == 0 {
                             p.clamp(sp, self.res.track_length)
                         } else {
@@ -538,6 +656,7 @@ impl GameScene {
                         if *ctrl == 0 {
                             tm.seek_to(p as f64);
                             self.music.seek_to(p)?;
+                            self.seek_chart(p);
                         } else {
                             *(if *ctrl == -1 {
                                 &mut self.exercise_range.start
@@ -574,6 +693,33 @@ impl GameScene {
                 tx.ui
                     .fill_rect(re.feather(0.01), Color::new(1., 1., 1., if self.exercise_btns.1.touching() { 0.5 } else { 1. }));
                 tx.draw();
+
+                fn text_button(ui: &mut Ui, button: &mut RectButton, text: &str, x: f32, anchor: f32, active: bool) -> Rect {
+                    let mut tx = ui.text(text).pos(x, 0.).anchor(anchor, 0.).size(0.6).color(BLACK);
+                    let re = tx.measure();
+                    button.set(tx.ui, re);
+                    tx.ui
+                        .fill_rect(re.feather(0.01), Color::new(1., 1., 1., if button.touching() || !active { 0.5 } else { 1. }));
+                    tx.draw();
+                    re
+                }
+                ui.dy(0.11);
+                let r = ui.text(format!("{:.2}x", self.res.config.speed)).size(0.6).anchor(0.5, 0.).draw();
+                let [speed_down, speed_up, autos @ ..] = &mut self.practice_btns;
+                text_button(ui, speed_down, "-", r.x - 0.03, 1., true);
+                text_button(ui, speed_up, "+", r.right() + 0.03, 0., true);
+                ui.dy(0.09);
+                let auto = &self.judge.auto_kinds;
+                let kinds = [
+                    (tl!("ex-auto-click"), auto.click),
+                    (tl!("ex-auto-drag"), auto.drag),
+                    (tl!("ex-auto-hold"), auto.hold),
+                    (tl!("ex-auto-flick"), auto.flick),
+                ];
+                let mut x = ui.text(tl!("ex-auto")).pos(-0.6, 0.).size(0.6).draw().right() + 0.04;
+                for ((name, on), button) in kinds.iter().zip(autos.iter_mut()) {
+                    x = text_button(ui, button, name, x, 0., *on).right() + 0.04;
+                }
             }
         }
         if let Some(time) = self.pause_rewind {
@@ -591,6 +737,31 @@ impl GameScene {
         Ok(())
     }
 
+    fn save_replay(&mut self) -> Result<()> {
+        let Some(frames) = self.judge.take_recording() else {
+            return Ok(());
+        };
+        let Some(dir) = REPLAY_DIR.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        let config = &self.res.config;
+        let replay = Replay {
+            fingerprint: self.fingerprint,
+            speed: config.speed,
+            offset: config.offset,
+            note_scale: config.note_scale,
+            aspect_ratio: self.res.aspect_ratio,
+            dpi: self.res.dpi,
+            result: self.judge.result(),
+            frames,
+        };
+        std::fs::create_dir_all(&dir).context("Failed to create replay directory")?;
+        let path = dir.join(format!("{}.prr", Utc::now().format("%Y%m%d-%H%M%S")));
+        std::fs::write(&path, replay.encode()).with_context(|| format!("Failed to write replay to {}", path.display()))?;
+        info!("Replay saved to {}", path.display());
+        Ok(())
+    }
+
     fn interactive(res: &Resource, state: &State) -> bool {
         res.config.interactive && matches!(state, State::Playing)
     }
@@ -599,6 +770,42 @@ impl GameScene {
         self.chart.offset + self.res.config.offset + self.info_offset
     }
 
+    /// Moves the chart to the given music time, skipping the notes before it.
+    fn seek_chart(&mut self, time: f32) {
+        self.chart.seek(time - self.offset());
+        self.judge.seek(&self.chart);
+        self.bad_notes.clear();
+    }
+
+    /// Snaps the given music time to the nearest beat.
+    fn snap_to_beat(&self, time: f32) -> f32 {
+        let offset = self.offset();
+        let mut bpm_list = self.chart.bpm_list.borrow_mut();
+        if bpm_list.elements().is_empty() {
+            return time;
+        }
+        let beat = bpm_list.beat(time - offset).round();
+        bpm_list.time_beats(beat) + offset
+    }
+
+    /// Changes the playback speed in place, keeping the music in sync.
+    fn set_speed(&mut self, tm: &mut TimeManager, speed: f32) -> Result<()> {
+        let speed = (speed * 20.).round() / 20.;
+        if !(PRACTICE_SPEED_MIN..=PRACTICE_SPEED_MAX).contains(&speed) {
+            return Ok(());
+        }
+        self.res.config.speed = speed;
+        tm.set_speed(speed as _);
+        let playing = !self.music.paused();
+        let pos = self.music.position();
+        self.music = Self::new_music(&mut self.res)?;
+        self.music.seek_to(pos)?;
+        if playing {
+            self.music.play()?;
+        }
+        Ok(())
+    }
+
     fn tweak_offset(&mut self, ui: &mut Ui, ita: bool) {
         ui.scope(|ui| {
             let width = 0.55;
@@ -695,6 +902,7 @@ impl Scene for GameScene {
             reset!(self, self.res, tm);
             self.state = state;
             tm.seek_to(self.exercise_range.start as f64);
+            self.seek_chart(self.exercise_range.start);
             tm.pause();
             self.music.pause()?;
         }
@@ -711,6 +919,9 @@ impl Scene for GameScene {
                     } else {
                         offset.min(0.) as f64
                     });
+                    if self.mode == GameMode::Exercise {
+                        self.seek_chart(self.exercise_range.start);
+                    }
                     self.last_update_time = tm.real_time();
                     if self.first_in && self.mode == GameMode::Exercise {
                         tm.pause();
@@ -758,8 +969,19 @@ impl Scene for GameScene {
                             }
                         }
                     }
+                    if let Err(err) = self.save_replay() {
+                        warn!("Failed to save replay: {:?}", err);
+                    }
+                    if let GameMode::Replay(replay) = &self.mode {
+                        if !self.judge.result().same_score(&replay.result) {
+                            warn!("Replay result mismatch: recorded {:?}, reproduced {:?}", replay.result, self.judge.result());
+                        }
+                    }
+                    if self.mode == GameMode::Normal && !self.res.config.autoplay {
+                        calibrate::record_play(&self.judge.result(), self.res.config.offset, self.res.config.speed);
+                    }
                     self.next_scene = match self.mode {
-                        GameMode::Normal => Some(NextScene::Overlay(Box::new(EndingScene::new(
+                        GameMode::Normal | GameMode::Replay(_) => Some(NextScene::Overlay(Box::new(EndingScene::new(
                             self.res.background.clone(),
                             self.res.illustration.clone(),
                             self.res.player.clone(),
@@ -771,11 +993,16 @@ impl Scene for GameScene {
                             self.res.challenge_icons[self.res.config.challenge_color.clone() as usize].clone(),
                             &self.res.config,
                             self.res.res_pack.ending.clone(),
-                            self.upload_fn,
+                            if self.mode == GameMode::Normal { self.upload_fn } else { None },
                             record_data,
+                            self.fingerprint,
                         )?))),
                         GameMode::TweakOffset => Some(NextScene::PopWithResult(Box::new(None::<f32>))),
                         GameMode::Exercise => None,
+                        GameMode::Calibrate => {
+                            let offsets = calibrate::implied_offsets(&self.judge.result(), self.res.config.offset, self.res.config.speed);
+                            Some(NextScene::PopWithResult(Box::new(calibrate::estimate(&offsets))))
+                        }
                     };
                 }
                 self.res.alpha = 1. - (t / AFTER_TIME).min(1.).powi(2);
@@ -786,7 +1013,16 @@ impl Scene for GameScene {
         self.res.time = time;
         if !tm.paused() && self.pause_rewind.is_none() {
             self.gl.quad_gl.viewport(self.res.camera.viewport);
-            self.judge.update(&mut self.res, &mut self.chart, &mut self.bad_notes);
+            if let GameMode::Replay(replay) = &self.mode {
+                while let Some(frame) = replay.frames.get(self.replay_cursor).filter(|it| it.time <= time) {
+                    self.judge
+                        .update_input(&mut self.res, &mut self.chart, &mut self.bad_notes, frame.clone());
+                    self.replay_cursor += 1;
+                }
+                self.res.time = time;
+            } else {
+                self.judge.update(&mut self.res, &mut self.chart, &mut self.bad_notes);
+            }
             self.gl.quad_gl.viewport(None);
         }
         let counts = self.judge.counts();
@@ -815,23 +1051,29 @@ impl Scene for GameScene {
                 tm.pause();
             }
         }
+        let mut seeked = None;
         if Self::interactive(res, &self.state) {
             if is_key_pressed(KeyCode::Left) {
                 res.time -= 1.;
                 let dst = (self.music.position() - 1.).max(0.);
                 self.music.seek_to(dst)?;
                 tm.seek_to(dst as f64);
+                seeked = Some(dst);
             }
             if is_key_pressed(KeyCode::Right) {
                 res.time += 5.;
                 let dst = (self.music.position() + 5.).min(res.track_length);
                 self.music.seek_to(dst)?;
                 tm.seek_to(dst as f64);
+                seeked = Some(dst);
             }
             if is_key_pressed(KeyCode::Q) {
                 self.should_exit = true;
             }
         }
+        if let Some(dst) = seeked.filter(|_| self.mode == GameMode::Exercise) {
+            self.seek_chart(dst);
+        }
         for e in &mut self.effects {
             e.update(&self.res);
         }
@@ -899,6 +1141,17 @@ impl Scene for GameScene {
                 request_input("exercise_end", &fmt_time(self.exercise_range.end));
                 return Ok(true);
             }
+            if let Some(id) = self.practice_btns.iter_mut().position(|it| it.touch(touch)) {
+                match id {
+                    0 => self.set_speed(tm, self.res.config.speed - 0.05)?,
+                    1 => self.set_speed(tm, self.res.config.speed + 0.05)?,
+                    2 => self.judge.auto_kinds.click ^= true,
+                    3 => self.judge.auto_kinds.drag ^= true,
+                    4 => self.judge.auto_kinds.hold ^= true,
+                    _ => self.judge.auto_kinds.flick ^= true,
+                }
+                return Ok(true);
+            }
         }
         Ok(false)
     }
@@ -1024,8 +1277,9 @@ impl Scene for GameScene {
             }
             tm.speed = 1.0;
             match self.mode {
-                GameMode::Normal | GameMode::Exercise => NextScene::Pop,
+                GameMode::Normal | GameMode::Exercise | GameMode::Replay(_) => NextScene::Pop,
                 GameMode::TweakOffset => NextScene::PopWithResult(Box::new(None::<f32>)),
+                GameMode::Calibrate => NextScene::PopWithResult(Box::new(None::<Calibration>)),
             }
         } else if let Some(next_scene) = self.next_scene.take() {
             tm.speed = 1.0;
@@ -4,6 +4,7 @@ use crate::{
     ext::{draw_parallelogram, draw_text_aligned, poll_future, screen_aspect, LocalTask, SafeTexture, BLACK_TEXTURE},
     fs::FileSystem,
     info::ChartInfo,
+    parse::Fingerprint,
     task::Task,
     time::TimeManager,
     ui::Ui,
@@ -40,7 +41,7 @@ impl LoadingScene {
         mut fs: Box<dyn FileSystem>,
         player: (Option<SafeTexture>, Option<String>),
         get_size_fn: Option<Rc<dyn Fn() -> (u32, u32)>>,
-        upload_fn: Option<fn(String) -> Task<Result<RecordUpdateState>>>,
+        upload_fn: Option<fn(String, Fingerprint) -> Task<Result<RecordUpdateState>>>,
     ) -> Result<Self> {
         async fn load(fs: &mut Box<dyn FileSystem>, path: &str) -> Result<(Texture2D, Texture2D)> {
             let image = image::load_from_memory(&fs.load_file(path).await?).context("Failed to decode image")?;
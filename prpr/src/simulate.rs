@@ -0,0 +1,257 @@
+//! Offline simulation of plays.
+//!
+//! Steps a chart with a manual clock and feeds recorded or scripted input to [`Judge`], without opening a window or touching any
+//! texture or audio device. Useful to verify replays on a server.
+
+use crate::{
+    core::{Chart, ChartExtra, NoteKind, NOTE_WIDTH_RATIO_BASE},
+    fs::{self, FileSystem},
+    info::ChartInfo,
+    judge::{Judge, JudgeContext, PlayResult, LIMIT_BAD},
+    parse::{infer_format, parse_chart},
+    replay::{Replay, ReplayFrame},
+    scene::GameScene,
+    time::TimeManager,
+};
+use anyhow::{bail, Context, Result};
+use macroquad::prelude::{vec2, Touch, TouchPhase};
+use std::{cell::Cell, collections::BTreeMap, rc::Rc};
+
+pub struct SimulationConfig {
+    pub speed: f32,
+    pub aspect_ratio: f32,
+    pub note_scale: f32,
+    pub dpi: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            speed: 1.,
+            aspect_ratio: 16. / 9.,
+            note_scale: 1.,
+            dpi: 250,
+        }
+    }
+}
+
+impl SimulationConfig {
+    pub fn from_replay(replay: &Replay) -> Self {
+        Self {
+            speed: replay.speed,
+            aspect_ratio: replay.aspect_ratio,
+            note_scale: replay.note_scale,
+            dpi: replay.dpi,
+        }
+    }
+}
+
+/// Loads the chart without its extra (effects and videos need a GPU).
+pub async fn load_chart(fs: &mut dyn FileSystem) -> Result<(ChartInfo, Chart)> {
+    let info = fs::load_info(fs).await.context("Failed to load chart info")?;
+    let chart = load_chart_of(fs, &info).await?;
+    Ok((info, chart))
+}
+
+/// Like [`load_chart`], but for the difficulty selected in the given info.
+pub async fn load_chart_of(fs: &mut dyn FileSystem, info: &ChartInfo) -> Result<Chart> {
+    let text = String::from_utf8(GameScene::load_chart_bytes(fs, info).await.context("Failed to load chart")?)?;
+    let format = info.format.clone().unwrap_or_else(|| infer_format(&text));
+    parse_chart(&text, &format, fs, ChartExtra::default()).await
+}
+
+/// The chart time after which nothing is left to judge
+pub fn end_time(chart: &Chart, speed: f32) -> f32 {
+    chart
+        .lines
+        .iter()
+        .flat_map(|it| it.notes.iter())
+        .filter(|it| !it.fake)
+        .map(|it| match it.kind {
+            NoteKind::Hold { end_time, .. } => end_time,
+            _ => it.time,
+        })
+        .fold(0., f32::max)
+        + LIMIT_BAD * speed
+        + 1.
+}
+
+pub fn simulate(chart: &mut Chart, config: &SimulationConfig, frames: impl IntoIterator<Item = ReplayFrame>) -> PlayResult {
+    chart.reset();
+    let mut judge = Judge::new(chart);
+    for frame in frames {
+        let t = frame.time;
+        let ctx = JudgeContext {
+            time: t,
+            speed: config.speed,
+            aspect_ratio: config.aspect_ratio,
+            note_width: config.note_scale * NOTE_WIDTH_RATIO_BASE,
+            dpi: config.dpi,
+        };
+        judge.judge_frame(&ctx, chart, frame);
+        // mirrors `Chart::update`, which runs after judging in `GameScene`
+        for line in &mut chart.lines {
+            line.object.set_time(t);
+        }
+    }
+    judge.result()
+}
+
+enum ScriptAction {
+    Down(u64, f32, f32),
+    Move(u64, f32, f32),
+    Up(u64),
+    KeyDown,
+    KeyUp,
+}
+
+/// Parses an input script.
+///
+/// Each non-empty line not starting with `#` is an action at the given music time (in seconds), one of
+///
+/// ```text
+/// <time> down <id> <x> <y>
+/// <time> move <id> <x> <y>
+/// <time> up <id>
+/// <time> key down
+/// <time> key up
+/// ```
+///
+/// Coordinates are in chart space: `x` ranges from -1 to 1 and `y` from `-1 / aspect_ratio` (top) to `1 / aspect_ratio` (bottom).
+fn parse_script(script: &str) -> Result<Vec<(f32, ScriptAction)>> {
+    let mut res = Vec::new();
+    for (index, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        (|| -> Result<()> {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let time: f32 = parts[0].parse()?;
+            let num = |i: usize| -> Result<f32> { Ok(parts.get(i).context("Missing argument")?.parse()?) };
+            let id = |i: usize| -> Result<u64> { Ok(parts.get(i).context("Missing touch id")?.parse()?) };
+            let action = match parts.get(1).copied() {
+                Some("down") => ScriptAction::Down(id(2)?, num(3)?, num(4)?),
+                Some("move") => ScriptAction::Move(id(2)?, num(3)?, num(4)?),
+                Some("up") => ScriptAction::Up(id(2)?),
+                Some("key") => match parts.get(2).copied() {
+                    Some("down") => ScriptAction::KeyDown,
+                    Some("up") => ScriptAction::KeyUp,
+                    _ => bail!("Expected `down` or `up` after `key`"),
+                },
+                Some(action) => bail!("Unknown action: {action}"),
+                None => bail!("Missing action"),
+            };
+            if let Some((last, _)) = res.last() {
+                if *last > time {
+                    bail!("Actions must be sorted by time (previous one is at {last})");
+                }
+            }
+            res.push((time, action));
+            Ok(())
+        })()
+        .with_context(|| format!("Invalid script at line {}", index + 1))?;
+    }
+    Ok(res)
+}
+
+/// Turns an input script into frames, stepping the clock at `fps` frames per second of real time like [`GameScene`] does.
+///
+/// `offset` is the total chart offset, and `end_time` is the chart time to stop at (see [`end_time`]).
+pub fn frames_from_script(script: &str, config: &SimulationConfig, offset: f32, fps: f32, end_time: f32) -> Result<Vec<ReplayFrame>> {
+    let actions = parse_script(script)?;
+    let clock = Rc::new(Cell::new(0.));
+    let mut tm = TimeManager::manual(Box::new({
+        let clock = Rc::clone(&clock);
+        move || clock.get()
+    }));
+    tm.speed = config.speed as _;
+
+    // id -> (touch, whether it started in this frame)
+    let mut touches: BTreeMap<u64, (Touch, bool)> = BTreeMap::new();
+    let mut pending_up = Vec::new();
+    let mut actions = actions.into_iter().peekable();
+    let mut frames = Vec::new();
+    let mut frame_id = 0u64;
+    loop {
+        let music_time = tm.now() as f32;
+        let time = (music_time - offset).max(0.);
+        if time > end_time {
+            break;
+        }
+        let mut frame = ReplayFrame::new(time);
+        for id in pending_up.drain(..) {
+            if let Some((touch, _)) = touches.get_mut(&id) {
+                touch.phase = TouchPhase::Ended;
+            }
+        }
+        let mut events = Vec::new();
+        while let Some((_, action)) = actions.next_if(|it| it.0 <= music_time) {
+            let mut event = |id: u64, phase: TouchPhase, x: f32, y: f32| {
+                events.push(Touch {
+                    id,
+                    phase,
+                    position: vec2(x, y * config.aspect_ratio),
+                });
+            };
+            match action {
+                ScriptAction::Down(id, x, y) => {
+                    event(id, TouchPhase::Started, x, y);
+                    touches.insert(
+                        id,
+                        (
+                            Touch {
+                                id,
+                                phase: TouchPhase::Started,
+                                position: vec2(x, y),
+                            },
+                            true,
+                        ),
+                    );
+                }
+                ScriptAction::Move(id, x, y) => {
+                    event(id, TouchPhase::Moved, x, y);
+                    if let Some((touch, _)) = touches.get_mut(&id) {
+                        touch.position = vec2(x, y);
+                        if touch.phase != TouchPhase::Started {
+                            touch.phase = TouchPhase::Moved;
+                        }
+                    }
+                }
+                ScriptAction::Up(id) => {
+                    if let Some((touch, fresh)) = touches.get_mut(&id) {
+                        event(id, TouchPhase::Ended, touch.position.x, touch.position.y);
+                        if *fresh {
+                            // a touch has to last for at least a frame
+                            pending_up.push(id);
+                        } else {
+                            touch.phase = TouchPhase::Ended;
+                        }
+                    }
+                }
+                ScriptAction::KeyDown => {
+                    frame.key_delta += 1;
+                    frame.keys_down += 1;
+                }
+                ScriptAction::KeyUp => {
+                    frame.key_delta -= 1;
+                }
+            }
+        }
+        frame.events = events;
+        frame.touches = touches.values().map(|it| it.0.clone()).collect();
+        touches.retain(|_, (touch, fresh)| {
+            *fresh = false;
+            if matches!(touch.phase, TouchPhase::Ended) {
+                return false;
+            }
+            touch.phase = TouchPhase::Stationary;
+            true
+        });
+        frames.push(frame);
+
+        frame_id += 1;
+        clock.set(frame_id as f64 / fps as f64);
+    }
+    Ok(frames)
+}
@@ -105,6 +105,13 @@ impl TimeManager {
         self.wait();
     }
 
+    /// Changes the speed while keeping the current time.
+    pub fn set_speed(&mut self, speed: f64) {
+        let now = self.now();
+        self.speed = speed;
+        self.start_time = self.pause_time.unwrap_or_else(&self.get_time_fn) - now / speed;
+    }
+
     pub fn seek_to(&mut self, pos: f64) {
         self.start_time = self.pause_time.unwrap_or_else(&self.get_time_fn) - pos / self.speed;
         self.wait();
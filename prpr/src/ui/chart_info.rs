@@ -29,7 +29,9 @@ impl ChartInfoEdit {
 
     pub async fn to_patches(&self) -> Result<HashMap<String, Vec<u8>>> {
         let mut res = HashMap::new();
-        res.insert("info.yml".to_owned(), serde_yaml::to_string(&self.info)?.into_bytes());
+        let mut info = self.info.clone();
+        info.sync_difficulty();
+        res.insert("info.yml".to_owned(), serde_yaml::to_string(&info)?.into_bytes());
         #[cfg(not(target_arch = "wasm32"))]
         {
             if let Some(chart) = &self.chart {